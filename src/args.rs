@@ -0,0 +1,75 @@
+use std::path::Path;
+
+// a file to open, `path` is `None` for stdin; line and column count from 1
+pub struct FileArg {
+  pub path: Option<String>,
+  pub line: Option<usize>,
+  pub column: Option<usize>,
+}
+
+#[derive(Default)]
+pub struct Args {
+  pub files: Vec<FileArg>,
+  // -R, open every file read-only
+  pub read_only: bool,
+  pub errors: Vec<String>,
+}
+
+// understands `+LINE FILE`, `FILE:LINE[:COLUMN]`, `-` for stdin, `-R` and
+// `--` to end the options
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Args {
+  let mut parsed = Args::default();
+  let mut line = None;
+  let mut options = true;
+  for arg in args {
+    if options && arg == "--" {
+      options = false;
+    } else if options && arg == "-R" {
+      parsed.read_only = true;
+    } else if arg == "-" {
+      parsed.files.push(FileArg { path: None, line: line.take(), column: None });
+    } else if let Some(number) = arg.strip_prefix('+').filter(|_| options) {
+      match number.parse() {
+        Ok(number) => line = Some(number),
+        Err(_) => parsed.errors.push(format!("Invalid line number {arg}")),
+      }
+    } else if options && arg.starts_with('-') {
+      parsed.errors.push(format!("Unknown option {arg}"));
+    } else {
+      let mut file = split_position(&arg);
+      file.line = file.line.or(line.take());
+      parsed.files.push(file);
+    }
+  }
+  if line.is_some() {
+    parsed.errors.push("Line number given without a file".to_string());
+  }
+
+  parsed
+}
+
+// splits `file:line:column`, unless a file with the full name exists
+fn split_position(arg: &str) -> FileArg {
+  let whole = FileArg { path: Some(arg.to_string()), line: None, column: None };
+  if Path::new(arg).exists() {
+    return whole;
+  }
+  let mut parts = arg.rsplitn(3, ':');
+  let (Some(last), Some(middle)) = (parts.next(), parts.next()) else {
+    return whole;
+  };
+  match (parts.next(), middle.parse(), last.parse()) {
+    (Some(path), Ok(line), Ok(column)) if !path.is_empty() =>
+      FileArg { path: Some(path.to_string()), line: Some(line), column: Some(column) },
+    (_, _, Ok(line)) => {
+      // only `file:line`, the middle part belongs to the path
+      let path = arg[..arg.len() - last.len() - 1].to_string();
+      if path.is_empty() {
+        whole
+      } else {
+        FileArg { path: Some(path), line: Some(line), column: None }
+      }
+    },
+    _ => whole,
+  }
+}
@@ -0,0 +1,26 @@
+use crate::Document;
+use crate::Position;
+use crate::UndoStack;
+
+// an open file with its own cursor and undo history
+pub struct Buffer {
+  pub document: Document,
+  pub history: UndoStack,
+  pub cursor_position: Position<usize>,
+  pub cursor_offset: Position<usize>,
+  pub selection_anchor: Option<Position<usize>>,
+  pub block_selection: bool,
+}
+
+impl Buffer {
+  pub fn new(document: Document, history: UndoStack) -> Self {
+    Self {
+      document,
+      history,
+      cursor_position: Position::default(),
+      cursor_offset: Position::default(),
+      selection_anchor: None,
+      block_selection: false,
+    }
+  }
+}
@@ -0,0 +1,47 @@
+// System clipboard access, only available with the `clipboard` feature.
+// Without it `get_text` never has anything to offer and the editor keeps
+// using its own buffer.
+
+#[cfg(feature = "clipboard")]
+use std::cell::RefCell;
+
+#[cfg(feature = "clipboard")]
+thread_local! {
+  // on X11 the copied text is only served while the clipboard handle is alive
+  static CLIPBOARD: RefCell<Option<arboard::Clipboard>> = const { RefCell::new(None) };
+}
+
+#[cfg(feature = "clipboard")]
+fn with_clipboard<T>(f: impl FnOnce(&mut arboard::Clipboard) -> Result<T, arboard::Error>) -> Result<T, String> {
+  CLIPBOARD.with(|cell| {
+    let mut slot = cell.borrow_mut();
+    let clipboard = match slot.as_mut() {
+      Some(clipboard) => clipboard,
+      None => slot.insert(arboard::Clipboard::new().map_err(|err| err.to_string())?),
+    };
+    f(clipboard).map_err(|err| err.to_string())
+  })
+}
+
+#[cfg(feature = "clipboard")]
+pub fn get_text() -> Option<String> {
+  with_clipboard(arboard::Clipboard::get_text)
+    .ok()
+    .filter(|text| !text.is_empty())
+}
+
+#[cfg(feature = "clipboard")]
+pub fn set_text(text: &str) -> Result<(), String> {
+  with_clipboard(|clipboard| clipboard.set_text(text))
+}
+
+#[cfg(not(feature = "clipboard"))]
+pub fn get_text() -> Option<String> {
+  None
+}
+
+#[cfg(not(feature = "clipboard"))]
+#[allow(clippy::unnecessary_wraps)]
+pub fn set_text(_text: &str) -> Result<(), String> {
+  Ok(())
+}
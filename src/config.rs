@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::editor::{LimitStyle, LineNumberMode};
+use crate::Error;
+
+#[allow(clippy::struct_excessive_bools)]
+pub struct Config {
+  pub tab_width: usize,
+  // insert spaces instead of a literal tab when pressing Tab
+  pub expand_tabs: bool,
+  // start new lines with the indentation of the line they were split from
+  pub auto_indent: bool,
+  // typing an opening bracket or quote also inserts its closing one
+  pub auto_pairs: bool,
+  // keys are commands until `i` is pressed, Esc goes back to them
+  pub modal: bool,
+  // searches go on from the other end of the file when they reach one
+  pub wrap_search: bool,
+  pub line_numbers: LineNumberMode,
+  // long rows continue on the next screen lines instead of scrolling
+  // sideways
+  pub wrap: bool,
+  // rows and columns kept visible around the cursor when scrolling
+  pub scroll_off: usize,
+  pub side_scroll_off: usize,
+  // clicks and the wheel move the cursor, off leaves them to the terminal
+  pub mouse: bool,
+  // spaces and tabs at the ends of rows are left out of saved files, the
+  // rows themselves keep them
+  pub strip_trailing_whitespace: bool,
+  // draws spaces and tabs at the ends of rows on a red background
+  pub show_trailing_whitespace: bool,
+  // spaces, tabs and row ends are drawn as dim markers
+  pub show_invisibles: bool,
+  // columns counted from 1 marked on every row, kept in increasing order
+  pub guides: Vec<usize>,
+  // rows wider than this many columns are marked the way `limit_style`
+  // says, 0 marks none; saving and editing don't look at it
+  pub line_limit: usize,
+  pub limit_style: LimitStyle,
+  // the first save of a file copies what it replaces to the file's name
+  // followed by `backup_suffix`, next to it or in `backup_dir` if set
+  pub backup: bool,
+  pub backup_suffix: String,
+  pub backup_dir: String,
+  // seconds without a key press after which modified files are saved,
+  // 0 turns auto-saving off
+  pub auto_save: usize,
+  // edits that can be undone in each buffer, the oldest are forgotten
+  pub undo_limit: usize,
+  // modified files are written to a swap file for recovery after a crash
+  pub swap: bool,
+  // files open where the cursor was left when they were last closed
+  pub remember_cursor: bool,
+  // right side of the status bar, `{line}`, `{column}`, `{lines}` and
+  // `{percent}` are replaced with the cursor's place in the file
+  pub position_format: String,
+  // commands files are piped through before saving, by extension
+  pub formatters: HashMap<String, String>,
+  // a failing formatter stops the save instead of saving the text as it is
+  pub format_abort: bool,
+  // strftime-style format of inserted dates when none is typed
+  pub date_format: String,
+}
+
+// an option `:set` can change, pointing at the field it controls
+enum Setting<'a> {
+  Flag(&'a mut bool),
+  // a number no smaller than the second value
+  Number(&'a mut usize, usize),
+  // turned on it shows line numbers the given way
+  LineNumbers(&'a mut LineNumberMode, LineNumberMode),
+  Text(&'a mut String),
+  // a comma separated list of columns, `DEFAULT_GUIDE` without a value
+  Columns(&'a mut Vec<usize>),
+  LimitStyle(&'a mut LimitStyle),
+}
+
+const DEFAULT_GUIDE: usize = 80;
+
+impl Default for Config {
+  fn default() -> Self {
+    Self {
+      tab_width: 4,
+      expand_tabs: false,
+      auto_indent: true,
+      auto_pairs: true,
+      modal: false,
+      wrap_search: true,
+      line_numbers: LineNumberMode::Absolute,
+      wrap: false,
+      scroll_off: 3,
+      side_scroll_off: 0,
+      mouse: true,
+      strip_trailing_whitespace: false,
+      show_trailing_whitespace: false,
+      show_invisibles: false,
+      guides: Vec::new(),
+      line_limit: 0,
+      limit_style: LimitStyle::Overflow,
+      backup: false,
+      backup_suffix: "~".to_string(),
+      backup_dir: String::new(),
+      auto_save: 0,
+      undo_limit: 1000,
+      swap: true,
+      remember_cursor: false,
+      position_format: "{line}:{column} {percent}".to_string(),
+      formatters: HashMap::new(),
+      format_abort: false,
+      date_format: "%Y-%m-%d".to_string(),
+    }
+  }
+}
+
+impl Config {
+  // applies one `:set` argument: `name=value`, `name` to turn an option on
+  // or `noname` to turn it off
+  pub fn set(&mut self, argument: &str) -> Result<(), Error> {
+    let (name, value) = match argument.split_once('=') {
+      Some((name, value)) => (name, Some(value)),
+      None => (argument, None),
+    };
+    let (setting, on) = match self.setting(name) {
+      Some(setting) => (setting, true),
+      None => match name.strip_prefix("no").and_then(|name| self.setting(name)) {
+        Some(setting) => (setting, false),
+        None => return Err(Error::Config(format!("Unknown option {name}"))),
+      },
+    };
+    match (setting, value) {
+      (Setting::Number(field, min), Some(value)) if on => {
+        *field = value
+          .parse()
+          .ok()
+          .filter(|&number| number >= min)
+          .ok_or_else(|| Error::Config(format!("Invalid value {value} for {name}")))?;
+      },
+      (Setting::Text(field), Some(value)) if on => *field = value.to_string(),
+      (Setting::Columns(field), value) if on => {
+        let mut columns = match value {
+          Some(value) => value
+            .split(',')
+            .map(|column| column.trim().parse().ok().filter(|&column| column >= 1))
+            .collect::<Option<Vec<usize>>>()
+            .ok_or_else(|| Error::Config(format!("Invalid value {value} for {name}")))?,
+          None => vec![DEFAULT_GUIDE],
+        };
+        columns.sort_unstable();
+        columns.dedup();
+        *field = columns;
+      },
+      (Setting::Columns(field), None) => field.clear(),
+      (Setting::LimitStyle(field), Some(value)) if on => {
+        *field = LimitStyle::parse(value).ok_or_else(|| Error::Config(format!("Invalid value {value} for {name}")))?;
+      },
+      (Setting::Text(field), None) if !on => field.clear(),
+      (Setting::Number(..) | Setting::Text(_) | Setting::LimitStyle(_), _) => {
+        return Err(Error::Config(format!("{name} needs a value")));
+      },
+      (_, Some(_)) => return Err(Error::Config(format!("{name} takes no value"))),
+      (Setting::Flag(field), None) => *field = on,
+      (Setting::LineNumbers(field, mode), None) => {
+        *field = if on { mode } else { LineNumberMode::Off };
+      },
+    }
+
+    Ok(())
+  }
+
+  fn setting(&mut self, name: &str) -> Option<Setting<'_>> {
+    match name {
+      "tabwidth" | "ts" => Some(Setting::Number(&mut self.tab_width, 1)),
+      "scrolloff" | "so" => Some(Setting::Number(&mut self.scroll_off, 0)),
+      "sidescrolloff" | "siso" => Some(Setting::Number(&mut self.side_scroll_off, 0)),
+      "expandtab" | "et" => Some(Setting::Flag(&mut self.expand_tabs)),
+      "autoindent" | "ai" => Some(Setting::Flag(&mut self.auto_indent)),
+      "autopairs" => Some(Setting::Flag(&mut self.auto_pairs)),
+      "modal" => Some(Setting::Flag(&mut self.modal)),
+      "wrapscan" | "ws" => Some(Setting::Flag(&mut self.wrap_search)),
+      "mouse" => Some(Setting::Flag(&mut self.mouse)),
+      "wrap" => Some(Setting::Flag(&mut self.wrap)),
+      "striptrailing" => Some(Setting::Flag(&mut self.strip_trailing_whitespace)),
+      "showtrailing" => Some(Setting::Flag(&mut self.show_trailing_whitespace)),
+      "list" | "invisibles" => Some(Setting::Flag(&mut self.show_invisibles)),
+      "colorcolumn" | "cc" => Some(Setting::Columns(&mut self.guides)),
+      "linelimit" => Some(Setting::Number(&mut self.line_limit, 0)),
+      "limitstyle" => Some(Setting::LimitStyle(&mut self.limit_style)),
+      "backup" | "bk" => Some(Setting::Flag(&mut self.backup)),
+      "backupext" | "bex" => Some(Setting::Text(&mut self.backup_suffix)),
+      "backupdir" | "bdir" => Some(Setting::Text(&mut self.backup_dir)),
+      "autosave" => Some(Setting::Number(&mut self.auto_save, 0)),
+      "undolevels" | "ul" => Some(Setting::Number(&mut self.undo_limit, 1)),
+      "swapfile" | "swf" => Some(Setting::Flag(&mut self.swap)),
+      "remembercursor" => Some(Setting::Flag(&mut self.remember_cursor)),
+      "formatabort" => Some(Setting::Flag(&mut self.format_abort)),
+      "dateformat" => Some(Setting::Text(&mut self.date_format)),
+      "number" | "nu" => Some(Setting::LineNumbers(&mut self.line_numbers, LineNumberMode::Absolute)),
+      "relativenumber" | "rnu" => Some(Setting::LineNumbers(&mut self.line_numbers, LineNumberMode::Relative)),
+      _ => None,
+    }
+  }
+}
+
+// the defaults changed by `<config dir>/config.toml`, which takes the
+// options of `:set` like `tabwidth = 2` or `number = false`; mistakes are
+// reported back and leave the defaults in place; a `[formatters]` table
+// maps extensions to commands, like `rs = "rustfmt --emit stdout"`
+pub fn load() -> (Config, Vec<Error>) {
+  let mut config = Config::default();
+  let mut errors = Vec::new();
+  let Some(path) = config_dir().map(|dir| dir.join("config.toml")) else {
+    return (config, errors);
+  };
+  let Ok(contents) = fs::read_to_string(&path) else {
+    return (config, errors);
+  };
+  let table: toml::Table = match toml::from_str(&contents) {
+    Ok(table) => table,
+    Err(err) => {
+      errors.push(Error::Config(format!("{}: {err}", path.display())));
+      return (config, errors);
+    },
+  };
+
+  for (name, value) in &table {
+    if name == "formatters" {
+      match value {
+        toml::Value::Table(formatters) => for (extension, command) in formatters {
+          match command {
+            toml::Value::String(command) => {
+              config.formatters.insert(extension.clone(), command.clone());
+            },
+            _ => errors.push(Error::Config(format!("Formatter for {extension} should be a string"))),
+          }
+        },
+        _ => errors.push(Error::Config("formatters should be a table".to_string())),
+      }
+      continue;
+    }
+    let argument = match value {
+      toml::Value::Boolean(true) => name.clone(),
+      toml::Value::Boolean(false) => format!("no{name}"),
+      toml::Value::Integer(number) => format!("{name}={number}"),
+      toml::Value::String(text) => format!("{name}={text}"),
+      // lists of numbers, like `colorcolumn = [80, 100]`
+      toml::Value::Array(items) => {
+        let items: Vec<String> = items.iter().map(ToString::to_string).collect();
+        format!("{name}={}", items.join(","))
+      },
+      _ => {
+        errors.push(Error::Config(format!("Option {name} should be a boolean, number or string")));
+        continue;
+      },
+    };
+    if let Err(err) = config.set(&argument) {
+      errors.push(err);
+    }
+  }
+
+  (config, errors)
+}
+
+// `$XDG_CONFIG_HOME/slime`, falling back to `~/.config/slime`
+pub fn config_dir() -> Option<PathBuf> {
+  let base = env::var_os("XDG_CONFIG_HOME")
+    .filter(|dir| !dir.is_empty())
+    .map(PathBuf::from)
+    .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+  Some(base.join("slime"))
+}
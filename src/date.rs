@@ -0,0 +1,23 @@
+// The current date and time, only available with the `date` feature.
+
+// formats offered in the prompt besides the configured one: ISO 8601 and
+// RFC 2822
+pub const PRESETS: &[&str] = &["%Y-%m-%dT%H:%M:%S%:z", "%a, %d %b %Y %H:%M:%S %z"];
+
+// the local time written with a strftime-style `format`
+#[cfg(feature = "date")]
+pub fn now(format: &str) -> Result<String, String> {
+  use chrono::format::{Item, StrftimeItems};
+
+  // an unknown specifier would only fail once the time is printed
+  let items: Vec<Item> = StrftimeItems::new(format).collect();
+  if items.contains(&Item::Error) {
+    return Err(format!("Invalid date format {format}"));
+  }
+  Ok(chrono::Local::now().format_with_items(items.into_iter()).to_string())
+}
+
+#[cfg(not(feature = "date"))]
+pub fn now(_format: &str) -> Result<String, String> {
+  Err("Inserting dates needs slime built with the date feature".to_string())
+}
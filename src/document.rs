@@ -1,39 +1,276 @@
+use std::borrow::Cow;
 use std::fs::File;
-use std::io::Write;
-use std::{io::Error, fs};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::{env, fs};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+
+use chardetng::{EncodingDetector, Iso2022JpDetection, Utf8Detection};
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use unicode_segmentation::UnicodeSegmentation;
 
 use crate::Row;
+use crate::Error;
+use crate::config::Config;
+use crate::swap;
+use crate::row::{Case, TRAILING_WHITESPACE};
 use crate::Position;
-use crate::editor::SearchDir;
+use crate::SyntaxDef;
+use crate::editor::{SearchDir, SearchOptions};
+
+// UTF-8 files larger than this are read into rows as they are reached
+const LAZY_LOAD_THRESHOLD: u64 = 16 << 20;
+// rows read at once from the unloaded part of a file
+const LOAD_CHUNK: usize = 4096;
+// bytes read or written between two progress reports
+const IO_CHUNK: usize = 1 << 20;
+
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+  #[default]
+  Lf,
+  CrLf,
+}
+
+impl LineEnding {
+  // the ending used by most lines of `contents`
+  fn detect(contents: &str) -> (Self, bool) {
+    let crlf = contents.matches("\r\n").count();
+    Self::from_counts(crlf, contents.matches('\n').count() - crlf)
+  }
+  // the more common of the two endings, and whether both are used
+  fn from_counts(crlf: usize, lf: usize) -> (Self, bool) {
+    let ending = if crlf > lf { LineEnding::CrLf } else { LineEnding::Lf };
+    (ending, crlf > 0 && lf > 0)
+  }
 
-#[derive(Default, Clone)]
+  pub fn as_str(self) -> &'static str {
+    match self {
+      LineEnding::Lf => "\n",
+      LineEnding::CrLf => "\r\n",
+    }
+  }
+}
+
+// counts for the whole document or a selection; line breaks count as a
+// character and as the bytes of the line ending
+#[derive(Default)]
+pub struct DocumentStats {
+  pub lines: usize,
+  pub words: usize,
+  pub characters: usize,
+  pub bytes: usize,
+}
+
+impl DocumentStats {
+  fn add_line(&mut self, line: &str, line_ending: LineEnding) {
+    if self.lines > 0 {
+      self.characters += 1;
+      self.bytes += line_ending.as_str().len();
+    }
+    self.lines += 1;
+    self.words += line.split_whitespace().count();
+    self.characters += line.graphemes(true).count();
+    self.bytes += line.len();
+  }
+}
+
+#[derive(Clone)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Document {
   pub path: Option<String>,
+  // `path` with symlinks resolved, the file saves go to, as of opening or
+  // the last save
+  canonical: Option<String>,
+  // a plain vector is kept over a rope: inserting or removing a line only
+  // moves the row handles, and edits within a row splice its string in place
   rows: Vec<Row>,
   dirty: bool,
+  syntax: Option<SyntaxDef>,
+  // rows before this index have up to date highlighting
+  highlighted_until: usize,
+  // modification time of the file when it was last read or written
+  mtime: Option<SystemTime>,
+  // last external modification time that has been reported
+  noticed_mtime: Option<SystemTime>,
+  line_ending: LineEnding,
+  // the file mixed endings, they are normalized to `line_ending`
+  mixed_line_endings: bool,
+  // whether the last row is followed by a line ending
+  final_newline: bool,
+  // encoding of the file on disk, rows are always kept as UTF-8
+  encoding: &'static Encoding,
+  // the file could not be decoded and is shown with replacement characters
+  lossy: bool,
+  // edits and saving are refused until unlocked
+  read_only: bool,
+  // where the rest of a large file is read from, and how many rows it has
+  unloaded: Option<Unloaded>,
+  unloaded_rows: usize,
+  // the file was copied to its backup by an earlier save
+  backed_up: bool,
+  // a swap file was written for the unsaved changes
+  swapped: bool,
+  // when the document was last written out in this session
+  last_saved: Option<Instant>,
+}
+
+impl Default for Document {
+  fn default() -> Self {
+    Self {
+      path: None,
+      canonical: None,
+      rows: Vec::new(),
+      dirty: false,
+      syntax: None,
+      highlighted_until: 0,
+      mtime: None,
+      noticed_mtime: None,
+      line_ending: LineEnding::default(),
+      mixed_line_endings: false,
+      final_newline: true,
+      encoding: UTF_8,
+      lossy: false,
+      read_only: false,
+      unloaded: None,
+      unloaded_rows: 0,
+      backed_up: false,
+      swapped: false,
+      last_saved: None,
+    }
+  }
 }
 
 impl Document {    
-  pub fn open(path: &str) -> Result<Self, Error> {
-    let contents = fs::read_to_string(path)?;
-    let mut rows = Vec::new();
-    for value in contents.lines() {
-      rows.push(Row::from(value));
-    }    
+  // `progress` is told the bytes read so far and the size of the file
+  pub fn open(path: &str, progress: impl FnMut(u64, u64)) -> Result<Self, Error> {
+    Self::open_as(path, None, progress)
+  }
+  // opens the file decoding it with `encoding`, or a guessed one if `None`
+  pub fn open_as(path: &str, encoding: Option<&'static Encoding>, mut progress: impl FnMut(u64, u64)) -> Result<Self, Error> {
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let write_protected = metadata.permissions().readonly();
+    let lazy = metadata.len() > LAZY_LOAD_THRESHOLD && (encoding.is_none() || encoding == Some(UTF_8));
+    let scanned = if lazy { scan(&mut file, &mut progress)? } else { None };
+    let document = if let Some(scanned) = scanned {
+      Self::lazily(file, &scanned)
+    } else {
+      file.rewind()?;
+      Self::decode_from(&read_reporting(file, progress)?, encoding)
+    };
+    let canonical = canonical(path);
     Ok(Self{
-      rows,
       path: Some(path.to_string()),
-      dirty: false,
+      mtime: modified(canonical.as_deref().unwrap_or(path)),
+      canonical,
+      read_only: document.lossy || write_protected,
+      ..document
     })
   }
+  // a document without a path holding `bytes`, e.g. read from stdin
+  pub fn from_bytes(bytes: &[u8]) -> Self {
+    Self::decode_from(bytes, None)
+  }
+  fn decode_from(bytes: &[u8], encoding: Option<&'static Encoding>) -> Self {
+    let (contents, encoding, lossy) = decode(bytes, encoding);
+    let (line_ending, mixed_line_endings) = LineEnding::detect(&contents);
+    Self{
+      rows: contents.lines().map(Row::from).collect(),
+      line_ending,
+      mixed_line_endings,
+      final_newline: contents.is_empty() || contents.ends_with('\n'),
+      encoding,
+      lossy,
+      read_only: lossy,
+      ..Self::default()
+    }
+  }
+  // a large UTF-8 file with no rows loaded yet, they are read from `file`
+  // as the view reaches them
+  fn lazily(file: File, scanned: &Scan) -> Self {
+    let (line_ending, mixed_line_endings) = LineEnding::from_counts(scanned.crlf, scanned.lf);
+    Self{
+      line_ending,
+      mixed_line_endings,
+      final_newline: scanned.final_newline,
+      unloaded: Some(Unloaded { file: Arc::new(file), offset: 0 }),
+      unloaded_rows: scanned.lines,
+      ..Self::default()
+    }
+  }
+  // reads rows of a large file until there are at least `until` of them,
+  // the rest of the file stays on disk until the view reaches it
+  pub fn load_rows(&mut self, until: usize) {
+    if self.rows.len() >= until || self.unloaded_rows == 0 {
+      return;
+    }
+    let Some(unloaded) = &mut self.unloaded else {
+      return;
+    };
+    let count = (until - self.rows.len()).max(LOAD_CHUNK).min(self.unloaded_rows);
+    let lines = unloaded.read_lines(count).unwrap_or_default();
+    let complete = lines.len() == count;
+    self.rows.extend(lines.iter().map(|line| Row::from(line.as_str())));
+    self.unloaded_rows -= count;
+    if !complete {
+      // the file shrank or can't be read anymore, saving what was read
+      // would cut it short
+      self.unloaded_rows = 0;
+      self.read_only = true;
+    }
+    if self.unloaded_rows == 0 {
+      self.unloaded = None;
+    }
+  }
+  pub fn load_all(&mut self) {
+    self.load_rows(usize::MAX);
+  }
+  // re-reads the file like `open_as`, keeping the path and syntax
+  pub fn reload(&mut self, encoding: Option<&'static Encoding>) -> Result<(), Error> {
+    let Some(path) = &self.path else {
+      return Ok(());
+    };
+    let reloaded = Self::open_as(path, encoding, |_, _| {})?;
+    let syntax = self.syntax.take();
+    *self = Self {
+      syntax,
+      backed_up: self.backed_up,
+      swapped: self.swapped,
+      last_saved: self.last_saved,
+      ..reloaded
+    };
+    Ok(())
+  }
+  // whether the file was modified by someone else since it was read or
+  // written, every modification is reported once
+  pub fn changed_on_disk(&mut self) -> bool {
+    let Some(path) = self.canonical_path() else {
+      return false;
+    };
+    let on_disk = modified(path);
+    if on_disk.is_none() || on_disk == self.mtime || on_disk == self.noticed_mtime {
+      return false;
+    }
+    self.noticed_mtime = on_disk;
+    true
+  }
   pub fn row(&self, index: usize) -> Option<&Row> {
     self.rows.get(index)
   }  
+  // number of loaded rows, the ones that can be read and edited
   pub fn rows_size(&self) -> usize {
     self.rows.len()
   }
+  // number of lines in the document, including the ones not loaded yet
+  pub fn line_count(&self) -> usize {
+    self.rows.len() + self.unloaded_rows
+  }
   pub fn is_empty(&self) -> bool {
-    self.rows.len() == 0
+    self.line_count() == 0
   }
   pub fn insert(&mut self, at: &Position<usize>, ch: char) {
     if at.y > self.rows_size() {
@@ -47,6 +284,7 @@ impl Document {
     if at.y == self.rows_size() {
       let mut row = Row::default();
       row.insert(0, ch);
+      self.unhighlight_rows(at.y);
       self.rows.push(row);
     } else if at.y < self.rows_size() {
       let row = self.row_mut(at.y).unwrap();
@@ -54,15 +292,280 @@ impl Document {
     }
   }
   pub fn insert_str(&mut self, at: &Position<usize>, s: &str) {
+    if at.y > self.rows_size() {
+      return;
+    }
+    self.dirty = true;
     if at.y == self.rows_size() {
       let mut row = Row::default();
       row.insert_str(0, s);
+      self.unhighlight_rows(at.y);
       self.rows.push(row);
     } else if at.y < self.rows_size() {
       let row = self.row_mut(at.y).unwrap();
       row.insert_str(at.x, s);      
     }
   }  
+  // replaces the grapheme at `at` with `ch`
+  pub fn overwrite(&mut self, at: &Position<usize>, ch: char) {
+    if let Some(row) = self.row_mut(at.y) {
+      row.delete(at.x);
+      row.insert(at.x, ch);
+      self.dirty = true;
+    }
+  }
+  // inserts possibly multi-line text and returns the position right after it
+  pub fn insert_text(&mut self, at: &Position<usize>, text: &str) -> Position<usize> {
+    if at.y > self.rows_size() {
+      return at.clone();
+    }
+    self.dirty = true;
+    let mut lines = text.split('\n');
+    let first = lines.next().unwrap_or_default();
+    self.insert_str(at, first);
+    let mut position = Position { x: at.x + first.graphemes(true).count(), y: at.y };
+    for line in lines {
+      self.insert_enter_key(&position);
+      position = Position { x: 0, y: position.y + 1 };
+      self.insert_str(&position, line);
+      position.x = line.graphemes(true).count();
+    }
+
+    position
+  }
+  // replaces `query_len` graphemes at `at` with `replacement`
+  pub fn replace(&mut self, at: &Position<usize>, query_len: usize, replacement: &str) {
+    if let Some(row) = self.row_mut(at.y) {
+      row.delete_slice(at.x, at.x + query_len);
+      row.insert_str(at.x, replacement);
+      self.dirty = true;
+    }
+  }
+  pub fn delete_row(&mut self, index: usize) -> Option<Row> {
+    if index < self.rows_size() {
+      self.dirty = true;
+      self.unhighlight_rows(index);
+      Some(self.rows.remove(index))
+    } else {
+      None
+    }
+  }
+  // puts `indent` in front of every row in `rows`
+  pub fn indent_rows(&mut self, rows: Range<usize>, indent: &str) {
+    for index in rows {
+      if let Some(row) = self.row_mut(index) {
+        row.insert_str(0, indent);
+        self.dirty = true;
+      }
+    }
+  }
+  // removes one leading tab or up to `tab_width` leading spaces from every
+  // row in `rows`, returning how many graphemes each row lost
+  pub fn unindent_rows(&mut self, rows: Range<usize>, tab_width: usize) -> Vec<usize> {
+    let mut removed = Vec::new();
+    for index in rows {
+      let Some(row) = self.row_mut(index) else {
+        break;
+      };
+      // in graphemes, a space or tab carrying a combining mark isn't
+      // indentation
+      let mut graphemes = row.string().graphemes(true).peekable();
+      let count = if graphemes.peek() == Some(&"\t") {
+        1
+      } else {
+        graphemes.take(tab_width).take_while(|&grapheme| grapheme == " ").count()
+      };
+      if count > 0 {
+        row.delete_slice(0, count);
+        self.dirty = true;
+      }
+      removed.push(count);
+    }
+    removed
+  }
+  // comments out the rows in `rows` by putting `marker` and a space after
+  // their indentation, or uncomments them when every row that isn't blank
+  // already is; returns the column each row changed at and by how much
+  pub fn toggle_comment(&mut self, rows: Range<usize>, marker: &str) -> Vec<(usize, isize)> {
+    let rows = rows.start..rows.end.min(self.rows_size());
+    let commented = |text: &str| text.trim_start().starts_with(marker);
+    let blank = |text: &str| text.trim().is_empty();
+    let uncomment = self.rows[rows.clone()]
+      .iter()
+      .all(|row| blank(row.string()) || commented(row.string()));
+    let marker_len = marker.graphemes(true).count();
+    let mut changes = Vec::new();
+    for index in rows.clone() {
+      let text = self.rows[index].string();
+      let indent = text.len() - text.trim_start().len();
+      let column = text[..indent].graphemes(true).count();
+      let change = if blank(text) {
+        0
+      } else if uncomment {
+        let len = marker_len + usize::from(text[indent + marker.len()..].starts_with(' '));
+        self.rows[index].delete_slice(column, column + len);
+        -isize::try_from(len).unwrap_or(0)
+      } else {
+        self.rows[index].insert_str(column, &format!("{marker} "));
+        isize::try_from(marker_len + 1).unwrap_or(0)
+      };
+      changes.push((column, change));
+    }
+    if changes.iter().any(|&(_, change)| change != 0) {
+      self.dirty = true;
+      self.unhighlight_rows(rows.start);
+    }
+    changes
+  }
+  // inserts a copy of row `index` below it
+  pub fn duplicate_row(&mut self, index: usize) -> bool {
+    let Some(row) = self.row(index).cloned() else {
+      return false;
+    };
+    self.dirty = true;
+    self.unhighlight_rows(index);
+    self.rows.insert(index + 1, row);
+    true
+  }
+  // swaps the graphemes, or the words, around `at` within its row and
+  // returns the column after them, `None` if there was nothing to swap
+  pub fn transpose(&mut self, at: &Position<usize>, words: bool) -> Option<usize> {
+    let row = self.row_mut(at.y)?;
+    let end = if words { row.transpose_words(at.x) } else { row.transpose(at.x) }?;
+    self.dirty = true;
+    Some(end)
+  }
+  // changes the case of the text between two positions, `to` is
+  // exclusive; returns where `to` ends up
+  pub fn map_case(&mut self, from: &Position<usize>, to: &Position<usize>, case: Case) -> Position<usize> {
+    let mut end = to.clone();
+    let mut changed = false;
+    for y in from.y..=to.y {
+      let Some(row) = self.rows.get_mut(y) else {
+        break;
+      };
+      let start = if y == from.y { from.x } else { 0 };
+      let stop = if y == to.y { to.x } else { row.size() };
+      let before = row.string().to_string();
+      let x = row.map_case(start..stop, case);
+      changed |= row.string() != before;
+      if y == to.y {
+        end.x = x;
+      }
+    }
+    if changed {
+      self.dirty = true;
+      self.unhighlight_rows(from.y);
+    }
+    end
+  }
+  // removes the rows in `rows` that repeat the row before them, like
+  // `uniq`, and returns how many there were
+  pub fn remove_duplicate_rows(&mut self, rows: Range<usize>) -> usize {
+    self.remove_rows_after(rows, |previous, row| previous == row)
+  }
+  // leaves one blank row of every run of them in `rows`, returns how many
+  // were removed
+  pub fn squeeze_blank_rows(&mut self, rows: Range<usize>) -> usize {
+    let blank = |text: &str| text.trim().is_empty();
+    self.remove_rows_after(rows, |previous, row| blank(previous) && blank(row))
+  }
+  // removes the rows in `rows` for which `remove` holds given the text of
+  // the row kept before them
+  fn remove_rows_after(&mut self, rows: Range<usize>, remove: impl Fn(&str, &str) -> bool) -> usize {
+    let rows = rows.start..rows.end.min(self.rows_size());
+    if rows.is_empty() {
+      return 0;
+    }
+    let mut kept: Vec<Row> = Vec::with_capacity(rows.len());
+    for row in self.rows.drain(rows.clone()) {
+      if kept.last().is_none_or(|previous| !remove(previous.string(), row.string())) {
+        kept.push(row);
+      }
+    }
+    let removed = rows.len() - kept.len();
+    self.rows.splice(rows.start..rows.start, kept);
+    if removed > 0 {
+      self.dirty = true;
+      self.unhighlight_rows(rows.start);
+    }
+    removed
+  }
+  // swaps row `index` with the one above, the first row stays put
+  pub fn move_row_up(&mut self, index: usize) -> bool {
+    index > 0 && self.move_row_down(index - 1)
+  }
+  // swaps row `index` with the one below, the last row stays put
+  pub fn move_row_down(&mut self, index: usize) -> bool {
+    if index + 1 >= self.rows_size() {
+      return false;
+    }
+    self.dirty = true;
+    self.unhighlight_rows(index);
+    self.rows.swap(index, index + 1);
+    true
+  }
+  // replaces `count` rows starting at `index` with `rows`
+  pub fn replace_rows(&mut self, index: usize, count: usize, rows: &[String]) {
+    let start = index.min(self.rows_size());
+    let end = start.saturating_add(count).min(self.rows_size());
+    self.rows.splice(start..end, rows.iter().map(|row| Row::from(row.as_str())));
+    self.dirty = true;
+    self.unhighlight_rows(start);
+  }
+  pub fn stats(&mut self) -> DocumentStats {
+    self.load_all();
+    let mut stats = DocumentStats::default();
+    for row in &self.rows {
+      stats.add_line(row.string(), self.line_ending);
+    }
+    if self.final_newline && !self.rows.is_empty() {
+      stats.characters += 1;
+      stats.bytes += self.line_ending.as_str().len();
+    }
+    stats
+  }
+  // stats of the text between two positions, `to` is exclusive
+  pub fn stats_between(&self, from: &Position<usize>, to: &Position<usize>) -> DocumentStats {
+    let mut stats = DocumentStats::default();
+    for line in self.text_range(from, to).split('\n') {
+      stats.add_line(line, self.line_ending);
+    }
+    stats
+  }
+  // text between two positions, `to` is exclusive
+  pub fn text_range(&self, from: &Position<usize>, to: &Position<usize>) -> String {
+    let mut lines = Vec::new();
+    for y in from.y..=to.y {
+      if let Some(row) = self.row(y) {
+        let start = if y == from.y { from.x } else { 0 };
+        let end = if y == to.y { to.x } else { row.size() };
+        let line: String = row.string()
+          .graphemes(true)
+          .skip(start)
+          .take(end.saturating_sub(start))
+          .collect();
+        lines.push(line);
+      }
+    }
+    lines.join("\n")
+  }
+  pub fn delete_range(&mut self, from: &Position<usize>, to: &Position<usize>) {
+    if from.y >= self.rows_size() || (from.y, from.x) >= (to.y, to.x) {
+      return;
+    }
+    self.dirty = true;
+    self.unhighlight_rows(from.y);
+    let to_y = to.y.min(self.rows_size() - 1);
+    let tail: String = self.rows[to_y].string()
+      .graphemes(true)
+      .skip(if to_y == to.y { to.x } else { usize::MAX })
+      .collect();
+    let row = &mut self.rows[from.y];
+    row.delete_slice(from.x, row.size());
+    row.insert_str(row.size(), &tail);
+    self.rows.drain(from.y + 1..=to_y);
+  }
   pub fn delete(&mut self, at: &Position<usize>) {
     if at.y < self.rows_size() {                     
       if at.y < self.rows_size() - 1 {
@@ -70,6 +573,7 @@ impl Document {
           if at.x == prev_row.size() {
             prev_row.insert_str(prev_row.size(), row.string());
             self.rows.remove(at.y + 1);
+            self.unhighlight_rows(at.y);
 
             return;
           }
@@ -80,62 +584,335 @@ impl Document {
       row.delete(at.x);                     
     }          
   } 
-  pub fn save_to_disk(&mut self) -> Result<(), Error> {
+  // writes the rows out, trimmed and backed up first as `config` says;
+  // `progress` is told the bytes written so far and how many there are,
+  // with a total of 0 while the text is still being put together
+  pub fn save_to_disk(&mut self, config: &Config, mut progress: impl FnMut(u64, u64)) -> Result<(), Error> {
+    if self.lossy {
+      return Err(Error::Encoding("file could not be decoded".to_string()));
+    }
+    if self.read_only {
+      return Err(Error::ReadOnly);
+    }
+    while self.unloaded_rows > 0 {
+      self.load_rows(self.rows.len() + LOAD_CHUNK);
+      progress(0, 0);
+    }
     if let Some(path) = &self.path {
-      let mut file = File::create(path)?;
-      for row in &self.rows {
-        file.write_all(row.as_bytes())?;
-        file.write_all(b"\n")?;
+      let mut contents = String::new();
+      for (index, row) in self.rows.iter().enumerate() {
+        if index % LOAD_CHUNK == 0 {
+          progress(0, 0);
+        }
+        let text = row.string();
+        contents.push_str(if config.strip_trailing_whitespace { text.trim_end_matches(TRAILING_WHITESPACE) } else { text });
+        if self.final_newline || index + 1 < self.rows.len() {
+          contents.push_str(self.line_ending.as_str());
+        }
       }      
+      let (bytes, unmappable) = encode(&contents, self.encoding);
+      if unmappable {
+        return Err(Error::Encoding(format!("text can't be represented in {}", self.encoding.name())));
+      }
+      if config.backup && !self.backed_up && Path::new(path).exists() {
+        fs::copy(path, backup_path(path, config)?)?;
+        self.backed_up = true;
+      }
+      write_atomically(path, &bytes, progress)?;
+      self.canonical = canonical(path);
+      self.mtime = modified(self.canonical_path().unwrap_or(path));
+      self.last_saved = Some(Instant::now());
+      if self.swapped {
+        swap::remove(path);
+        self.swapped = false;
+      }
     }
 
     self.dirty = false;
     Ok(())
   }
+  // writes the rows to the swap file so they survive a crash, the ones of
+  // a large file not loaded yet are copied from the file
+  pub fn write_swap(&mut self, cursor: &Position<usize>) -> Result<(), Error> {
+    let Some(path) = &self.path else {
+      return Ok(());
+    };
+    swap::write(path, cursor, |out| {
+      let mut separate = false;
+      let mut write_line = |line: &[u8]| {
+        if separate {
+          out.write_all(b"\n")?;
+        }
+        separate = true;
+        out.write_all(line)
+      };
+      for row in &self.rows {
+        write_line(row.string().as_bytes())?;
+      }
+      if let Some(unloaded) = &self.unloaded {
+        let (copied, _) = unloaded.each_line(self.unloaded_rows, write_line)?;
+        if copied < self.unloaded_rows {
+          return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the file was cut short"));
+        }
+      }
+      Ok(())
+    })?;
+    self.swapped = true;
+    Ok(())
+  }
+  // removes the swap file written for this document, if any
+  pub fn remove_swap(&mut self) {
+    if let Some(path) = self.path.as_deref().filter(|_| self.swapped) {
+      swap::remove(path);
+      self.swapped = false;
+    }
+  }
+  // replaces the rows with text recovered from a swap file, the file on
+  // disk stays as it was until saved
+  pub fn recover(&mut self, text: &str) {
+    self.rows = text.split('\n').map(Row::from).collect();
+    self.unloaded = None;
+    self.unloaded_rows = 0;
+    self.dirty = true;
+    // the swap is rewritten from here on and removed once saved
+    self.swapped = true;
+    self.unhighlight_rows(0);
+  }
+  // position after the next word, a row end counts as one
+  pub fn next_word(&self, at: &Position<usize>) -> Position<usize> {
+    let Some(row) = self.row(at.y) else {
+      return at.clone();
+    };
+    if at.x >= row.size() {
+      if at.y + 1 < self.rows_size() {
+        return Position { x: 0, y: at.y + 1 };
+      }
+      return Position { x: row.size(), y: at.y };
+    }
+    Position { x: row.next_word_end(at.x), y: at.y }
+  }
+  // start of the previous word, a row start counts as one
+  pub fn prev_word(&self, at: &Position<usize>) -> Position<usize> {
+    if at.x == 0 {
+      return match at.y.checked_sub(1).and_then(|y| self.row(y).map(|row| (row, y))) {
+        Some((row, y)) => Position { x: row.size(), y },
+        None => at.clone(),
+      };
+    }
+    let x = self.row(at.y).map_or(0, |row| row.prev_word_start(at.x));
+    Position { x, y: at.y }
+  }
+  // the first bracket at or after `at` on its row
+  pub fn bracket_at(&self, at: &Position<usize>) -> Option<Position<usize>> {
+    let row = self.row(at.y)?;
+    let x = (at.x..row.size()).find(|&x| row.grapheme(x).and_then(bracket_pair).is_some())?;
+    Some(Position { x, y: at.y })
+  }
+  // the bracket closing or opening the one at `at`, brackets of the same
+  // kind in between are counted so nested pairs are skipped
+  pub fn find_matching_bracket(&mut self, at: &Position<usize>) -> Option<Position<usize>> {
+    let bracket = self.row(at.y)?.grapheme(at.x)?.to_string();
+    let (own, matching, forward) = bracket_pair(&bracket)?;
+    // the bracket itself is the first one counted
+    let mut depth = 0usize;
+    let mut count = |grapheme: &str| {
+      if grapheme == own {
+        depth += 1;
+      } else if grapheme == matching {
+        depth -= 1;
+      }
+      depth == 0
+    };
+    if forward {
+      let mut y = at.y;
+      let mut skip = at.x;
+      loop {
+        self.load_rows(y + 1);
+        let row = self.row(y)?;
+        if let Some(x) = row.string().graphemes(true).skip(skip).position(&mut count) {
+          return Some(Position { x: skip + x, y });
+        }
+        y += 1;
+        skip = 0;
+      }
+    }
+    let mut y = at.y;
+    let mut end = at.x + 1;
+    loop {
+      let graphemes: Vec<&str> = self.row(y)?.string().graphemes(true).take(end).collect();
+      if let Some(x) = graphemes.into_iter().rev().position(&mut count) {
+        return Some(Position { x: end - 1 - x, y });
+      }
+      y = y.checked_sub(1)?;
+      end = self.row(y).map_or(0, Row::size);
+    }
+  }
+  pub fn encoding(&self) -> &'static Encoding {
+    self.encoding
+  }
+  pub fn is_lossy(&self) -> bool {
+    self.lossy
+  }
+  pub fn is_read_only(&self) -> bool {
+    self.read_only
+  }
+  // a lossy document stays read-only, saving it would corrupt the file
+  pub fn set_read_only(&mut self, read_only: bool) {
+    self.read_only = read_only || self.lossy;
+  }
+  pub fn line_ending(&self) -> LineEnding {
+    self.line_ending
+  }
+  pub fn has_mixed_line_endings(&self) -> bool {
+    self.mixed_line_endings
+  }
+  // the file the document is kept in with symlinks resolved, the path as
+  // given while it doesn't exist
+  pub fn canonical_path(&self) -> Option<&str> {
+    self.canonical.as_deref().or(self.path.as_deref())
+  }
+  // the full path with the home directory written as `~`, or `[No Name]`
+  pub fn display_path(&self) -> String {
+    let Some(path) = self.canonical_path() else {
+      return "[No Name]".to_string();
+    };
+    let absolute = path.to_string();
+    let home = env::var("HOME").unwrap_or_default();
+    match absolute.strip_prefix(home.trim_end_matches('/')) {
+      Some(rest) if !home.is_empty() && rest.starts_with('/') => format!("~{rest}"),
+      _ => absolute,
+    }
+  }
+  pub fn last_saved(&self) -> Option<Instant> {
+    self.last_saved
+  }
+  pub fn syntax(&self) -> Option<&SyntaxDef> {
+    self.syntax.as_ref()
+  }
+  pub fn set_syntax(&mut self, syntax: Option<SyntaxDef>) {
+    self.syntax = syntax;
+    self.highlighted_until = 0;
+  }
+  // brings highlighting up to date for every row before `until`
+  pub fn highlight(&mut self, until: usize) {
+    let until = until.min(self.rows_size());
+    let mut start_in_comment = self.highlighted_until
+      .checked_sub(1)
+      .and_then(|index| self.rows.get(index))
+      .is_some_and(Row::ends_in_comment);
+    for row in self.rows.iter_mut().take(until).skip(self.highlighted_until) {
+      start_in_comment = row.highlight(self.syntax.as_ref(), start_in_comment);
+    }
+    self.highlighted_until = self.highlighted_until.max(until);
+  }
   pub fn is_dirty(&self) -> bool {
     self.dirty
   }
-  pub fn find(&self, query: &str, at: &Position<usize>, direction: SearchDir) -> Option<Position<usize>> {    
-    if at.y > self.rows_size() {
-      return None
-    }
-
-    let start = if direction == SearchDir::Forward {
-      at.y
+  pub fn set_dirty(&mut self, dirty: bool) {
+    self.dirty = dirty;
+  }
+  // the nearest match starting at or after `at` going forward, or before
+  // it going backward; rows past `at` are searched whole
+  pub fn find(&self, query: &str, at: &Position<usize>, direction: SearchDir, options: SearchOptions) -> Option<Position<usize>> {    
+    let rows: Vec<usize> = if direction == SearchDir::Forward {
+      (at.y..self.rows_size()).collect()
     } else {
-      0
+      (0..self.rows_size().min(at.y + 1)).rev().collect()
     };
 
-    let end = if direction == SearchDir::Forward {
-      self.rows_size()      
+    for y in rows {
+      let row = &self.rows[y];
+      let from = match direction {
+        _ if y == at.y => at.x.min(row.size()),
+        SearchDir::Forward => 0,
+        SearchDir::Backward => row.size(),
+      };
+      if let Some(x) = row.find(query, from, direction, options) {
+        return Some(Position { x, y });
+      }
+    }
+
+    None
+  }
+  // number of matches of `query`, counting stops at `limit` so typing a
+  // search in a huge file stays quick
+  pub fn count_matches(&self, query: &str, options: SearchOptions, limit: usize) -> usize {
+    self.matches(query, options).take(limit).count()
+  }
+  // number of matches starting before `at`, also stopping at `limit`
+  pub fn count_matches_before(&self, query: &str, options: SearchOptions, at: &Position<usize>, limit: usize) -> usize {
+    self.matches(query, options)
+      .take_while(|found| (found.y, found.x) < (at.y, at.x))
+      .take(limit)
+      .count()
+  }
+  // every match from the start of the document, overlapping ones too like
+  // stepping through them with `find` visits them
+  fn matches<'a>(&'a self, query: &'a str, options: SearchOptions) -> impl Iterator<Item = Position<usize>> + 'a {
+    self.rows.iter().enumerate().flat_map(move |(y, row)| {
+      let mut x = 0;
+      std::iter::from_fn(move || {
+        let found = row.find(query, x, SearchDir::Forward, options)?;
+        x = found + 1;
+        Some(Position { x: found, y })
+      })
+    })
+  }
+  // returns the match position and its length in graphemes
+  #[cfg(feature = "regex")]
+  pub fn find_regex(&self, regex: &regex::Regex, at: &Position<usize>, direction: SearchDir) -> Option<(Position<usize>, usize)> {
+    let rows: Vec<usize> = if direction == SearchDir::Forward {
+      (at.y..self.rows_size()).collect()
     } else {
-      at.y.saturating_add(1)
+      (0..=at.y.min(self.rows_size().saturating_sub(1))).rev().collect()
     };
 
-    let mut position = Position { x: at.x, y: at.y };
-
-    for _ in start..end {
-      if let Some(row) = self.row(position.y) {
-        if let Some(x) = row.find(query, position.x, direction) {
-          position.x = x;
-          return Some(position);
-        }
-        if direction == SearchDir::Forward {
-          position.y = position.y.saturating_add(1);
-          position.x = 0;
-        } else {
-          position.y = position.y.saturating_sub(1);
-          position.x = self.rows[position.y].size();
-        }
-      } else {
-        return None;
+    for y in rows {
+      let string = self.rows[y].string();
+      let grapheme_index = |byte_index: usize| string[..byte_index].graphemes(true).count();
+      let mut matches = regex
+        .find_iter(string)
+        .filter(|found| !found.is_empty())
+        .map(|found| (grapheme_index(found.start()), found.as_str().graphemes(true).count()));
+      let found = match (direction, y == at.y) {
+        (SearchDir::Forward, true) => matches.find(|&(x, _)| x >= at.x),
+        (SearchDir::Forward, false) => matches.next(),
+        (SearchDir::Backward, true) => matches.filter(|&(x, _)| x < at.x).last(),
+        (SearchDir::Backward, false) => matches.last(),
+      };
+      if let Some((x, len)) = found {
+        return Some((Position { x, y }, len));
       }
     }
-    
+
     None
   }
+  // the regex counterparts of `count_matches` and `count_matches_before`,
+  // empty matches are skipped like `find_regex` skips them
+  #[cfg(feature = "regex")]
+  pub fn count_regex_matches(&self, regex: &regex::Regex, limit: usize) -> usize {
+    self.regex_matches(regex).take(limit).count()
+  }
+  #[cfg(feature = "regex")]
+  pub fn count_regex_matches_before(&self, regex: &regex::Regex, at: &Position<usize>, limit: usize) -> usize {
+    self.regex_matches(regex)
+      .take_while(|found| (found.y, found.x) < (at.y, at.x))
+      .take(limit)
+      .count()
+  }
+  #[cfg(feature = "regex")]
+  fn regex_matches<'a>(&'a self, regex: &'a regex::Regex) -> impl Iterator<Item = Position<usize>> + 'a {
+    self.rows.iter().enumerate().flat_map(move |(y, row)| {
+      let string = row.string();
+      regex
+        .find_iter(string)
+        .filter(|found| !found.is_empty())
+        .map(move |found| Position { x: string[..found.start()].graphemes(true).count(), y })
+    })
+  }
   fn row_mut(&mut self, index: usize) -> Option<&mut Row> {
     if index < self.rows.len() {
+      self.unhighlight_rows(index);
       Some(&mut self.rows[index])
     } else {
       None
@@ -152,4 +929,507 @@ impl Document {
       self.rows.insert(at.y + 1, new_row);
     }  
   }  
+  fn unhighlight_rows(&mut self, start: usize) {
+    self.highlighted_until = self.highlighted_until.min(start);
+  }
+}
+
+// the rest of a large file past the loaded rows, the handle is shared by
+// copies of the document and every read seeks to `offset` first
+#[derive(Clone)]
+struct Unloaded {
+  file: Arc<File>,
+  offset: u64,
+}
+
+impl Unloaded {
+  // up to `count` lines from `offset` on, which then moves past them
+  fn read_lines(&mut self, count: usize) -> io::Result<Vec<String>> {
+    let mut lines = Vec::with_capacity(count);
+    let (_, read) = self.each_line(count, |line| {
+      lines.push(String::from_utf8_lossy(line).into_owned());
+      Ok(())
+    })?;
+    self.offset += read;
+    Ok(lines)
+  }
+  // calls `f` with up to `count` lines from `offset` on, without their line
+  // endings; returns how many there were and the bytes they took
+  fn each_line(&self, count: usize, mut f: impl FnMut(&[u8]) -> io::Result<()>) -> io::Result<(usize, u64)> {
+    let mut file = &*self.file;
+    file.seek(SeekFrom::Start(self.offset))?;
+    let mut reader = BufReader::with_capacity(IO_CHUNK, file);
+    let (mut lines, mut bytes) = (0, 0);
+    let mut line = Vec::new();
+    while lines < count {
+      line.clear();
+      let read = reader.read_until(b'\n', &mut line)?;
+      if read == 0 {
+        break;
+      }
+      lines += 1;
+      bytes += read as u64;
+      if line.ends_with(b"\n") {
+        line.pop();
+        if line.ends_with(b"\r") {
+          line.pop();
+        }
+      }
+      f(&line)?;
+    }
+    Ok((lines, bytes))
+  }
+}
+
+// what a large file is opened with without holding its text
+struct Scan {
+  lines: usize,
+  crlf: usize,
+  lf: usize,
+  final_newline: bool,
+}
+
+// counts the lines and line endings of `file` a chunk at a time, `None` if
+// it isn't UTF-8 and has to be decoded whole
+fn scan(file: &mut File, mut progress: impl FnMut(u64, u64)) -> io::Result<Option<Scan>> {
+  let total = file.metadata()?.len();
+  let mut scanned = Scan { lines: 0, crlf: 0, lf: 0, final_newline: true };
+  let mut done = 0;
+  let mut previous = b'\n';
+  // the read bytes not checked yet, a character split by the chunk end
+  let mut bytes = Vec::with_capacity(IO_CHUNK + 4);
+  loop {
+    let kept = bytes.len();
+    let read = (&mut *file).take(IO_CHUNK as u64).read_to_end(&mut bytes)?;
+    if read == 0 {
+      break;
+    }
+    done += read as u64;
+    progress(done, total);
+    let valid = match std::str::from_utf8(&bytes) {
+      Ok(_) => bytes.len(),
+      Err(err) if err.error_len().is_none() => err.valid_up_to(),
+      Err(_) => return Ok(None),
+    };
+    for &byte in &bytes[kept..] {
+      if byte == b'\n' {
+        if previous == b'\r' {
+          scanned.crlf += 1;
+        } else {
+          scanned.lf += 1;
+        }
+      }
+      previous = byte;
+    }
+    bytes.drain(..valid);
+  }
+  if !bytes.is_empty() {
+    return Ok(None);
+  }
+  scanned.final_newline = previous == b'\n';
+  scanned.lines = scanned.crlf + scanned.lf + usize::from(!scanned.final_newline);
+  Ok(Some(scanned))
+}
+
+// the contents of `file`, read a chunk at a time so `progress` can follow
+fn read_reporting(mut file: File, mut progress: impl FnMut(u64, u64)) -> io::Result<Vec<u8>> {
+  let total = file.metadata()?.len();
+  let mut bytes = Vec::with_capacity(usize::try_from(total).unwrap_or(0));
+  loop {
+    let read = (&mut file).take(IO_CHUNK as u64).read_to_end(&mut bytes)?;
+    if read == 0 {
+      return Ok(bytes);
+    }
+    progress(bytes.len() as u64, total);
+  }
+}
+
+// a bracket as itself, the one pairing with it and whether that one comes
+// after it
+fn bracket_pair(grapheme: &str) -> Option<(&'static str, &'static str, bool)> {
+  match grapheme {
+    "(" => Some(("(", ")", true)),
+    "[" => Some(("[", "]", true)),
+    "{" => Some(("{", "}", true)),
+    ")" => Some((")", "(", false)),
+    "]" => Some(("]", "[", false)),
+    "}" => Some(("}", "{", false)),
+    _ => None,
+  }
+}
+
+// writes `bytes` to a temporary file next to `path` and renames it over
+// `path`, so a failed save leaves the old contents in place; the file keeps
+// its permissions and a symlink keeps pointing at it
+fn write_atomically(path: &str, bytes: &[u8], mut progress: impl FnMut(u64, u64)) -> io::Result<()> {
+  let target = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+  let name = target.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+  let temporary = target.with_file_name(format!(".{name}.slime-{}", process::id()));
+
+  let result = (|| {
+    let mut file = File::create(&temporary)?;
+    let mut written = 0;
+    for chunk in bytes.chunks(IO_CHUNK) {
+      file.write_all(chunk)?;
+      written += chunk.len();
+      progress(written as u64, bytes.len() as u64);
+    }
+    if let Ok(metadata) = fs::metadata(&target) {
+      file.set_permissions(metadata.permissions())?;
+    }
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&temporary, &target)
+  })();
+  if result.is_err() {
+    let _ = fs::remove_file(&temporary);
+  }
+  result
+}
+
+// `<path><suffix>`, or in the backup directory the whole path with `%` for
+// the separators so files of the same name don't collide
+fn backup_path(path: &str, config: &Config) -> io::Result<PathBuf> {
+  if config.backup_dir.is_empty() {
+    return Ok(PathBuf::from(format!("{path}{}", config.backup_suffix)));
+  }
+  let dir = PathBuf::from(&config.backup_dir);
+  fs::create_dir_all(&dir)?;
+  let full = fs::canonicalize(path)?;
+  let name = full.to_string_lossy().replace(std::path::MAIN_SEPARATOR, "%");
+  Ok(dir.join(format!("{name}{}", config.backup_suffix)))
+}
+
+fn canonical(path: &str) -> Option<String> {
+  fs::canonicalize(path).ok().map(|path| path.to_string_lossy().into_owned())
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+  fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// decodes `bytes` as `encoding` or, if `None`, as UTF-8 or the UTF-16 its
+// byte order mark says, falling back to a guess; a byte order mark is kept
+// as the first character; also returns whether decoding failed and
+// characters were replaced
+fn decode(bytes: &[u8], encoding: Option<&'static Encoding>) -> (String, &'static Encoding, bool) {
+  let encoding = encoding.unwrap_or_else(|| {
+    if std::str::from_utf8(bytes).is_ok() {
+      return UTF_8;
+    }
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+      return encoding;
+    }
+    let mut detector = EncodingDetector::new(Iso2022JpDetection::Deny);
+    detector.feed(bytes, true);
+    detector.guess(None, Utf8Detection::Allow)
+  });
+  if let Some(contents) = encoding.decode_without_bom_handling_and_without_replacement(bytes) {
+    return (contents.into_owned(), encoding, false);
+  }
+  let (contents, _) = encoding.decode_without_bom_handling(bytes);
+  (contents.into_owned(), encoding, true)
+}
+
+// `contents` in `encoding` and whether some characters can't be written in
+// it; encoding_rs only decodes UTF-16, so that is encoded here, with the
+// byte order mark the text starts with if the file had one
+fn encode<'a>(contents: &'a str, encoding: &'static Encoding) -> (Cow<'a, [u8]>, bool) {
+  if encoding == UTF_16LE {
+    return (contents.encode_utf16().flat_map(u16::to_le_bytes).collect(), false);
+  }
+  if encoding == UTF_16BE {
+    return (contents.encode_utf16().flat_map(u16::to_be_bytes).collect(), false);
+  }
+  let (bytes, _, unmappable) = encoding.encode(contents);
+  (bytes, unmappable)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn document(text: &str) -> Document {
+    Document::from_bytes(text.as_bytes())
+  }
+
+  fn at(x: usize, y: usize) -> Position<usize> {
+    Position { x, y }
+  }
+
+  #[test]
+  fn next_word_stops_at_every_empty_row() {
+    let document = document("one\n\n\ntwo\n");
+    let mut position = at(0, 0);
+    let mut stops = Vec::new();
+    for _ in 0..5 {
+      position = document.next_word(&position);
+      stops.push((position.x, position.y));
+    }
+    assert_eq!(stops, [(3, 0), (0, 1), (0, 2), (0, 3), (3, 3)]);
+  }
+
+  #[test]
+  fn prev_word_stops_at_every_empty_row() {
+    let document = document("one\n\n\ntwo\n");
+    let mut position = at(3, 3);
+    let mut stops = Vec::new();
+    for _ in 0..5 {
+      position = document.prev_word(&position);
+      stops.push((position.x, position.y));
+    }
+    assert_eq!(stops, [(0, 3), (0, 2), (0, 1), (3, 0), (0, 0)]);
+  }
+
+  #[test]
+  fn word_moves_skip_whitespace_and_stop_at_punctuation() {
+    let document = document("  foo.bar  \n");
+    assert_eq!(document.next_word(&at(0, 0)), at(5, 0));
+    assert_eq!(document.next_word(&at(5, 0)), at(6, 0));
+    assert_eq!(document.prev_word(&at(11, 0)), at(6, 0));
+    assert_eq!(document.prev_word(&at(6, 0)), at(5, 0));
+  }
+
+  #[test]
+  fn word_moves_stop_at_the_document_ends() {
+    let document = document("\nend");
+    assert_eq!(document.prev_word(&at(0, 0)), at(0, 0));
+    assert_eq!(document.next_word(&at(3, 1)), at(3, 1));
+  }
+
+  fn texts(document: &Document) -> Vec<&str> {
+    document.rows.iter().map(Row::string).collect()
+  }
+
+  #[test]
+  fn duplicate_row_inserts_a_copy_below() {
+    let mut document = document("a\nb\n");
+    assert!(document.duplicate_row(0));
+    assert_eq!(texts(&document), ["a", "a", "b"]);
+    assert!(document.is_dirty());
+    assert!(!document.duplicate_row(3));
+  }
+
+  #[test]
+  fn moving_rows_swaps_them_with_their_neighbour() {
+    let mut document = document("a\nb\nc\n");
+    assert!(document.move_row_down(0));
+    assert_eq!(texts(&document), ["b", "a", "c"]);
+    assert!(document.move_row_up(2));
+    assert_eq!(texts(&document), ["b", "c", "a"]);
+    assert!(document.is_dirty());
+  }
+
+  #[test]
+  fn moving_the_first_row_up_or_the_last_down_does_nothing() {
+    let mut document = document("a\nb\n");
+    assert!(!document.move_row_up(0));
+    assert!(!document.move_row_down(1));
+    assert_eq!(texts(&document), ["a", "b"]);
+    assert!(!document.is_dirty());
+  }
+
+  #[test]
+  fn delete_row_returns_the_removed_row() {
+    let mut document = document("a\nb\nc\n");
+    assert_eq!(document.delete_row(1).as_ref().map(Row::string), Some("b"));
+    assert_eq!(texts(&document), ["a", "c"]);
+    assert!(document.delete_row(2).is_none());
+  }
+
+  fn find(document: &Document, from: &Position<usize>, direction: SearchDir) -> Option<Position<usize>> {
+    document.find("ab", from, direction, SearchOptions::default())
+  }
+
+  #[test]
+  fn find_goes_to_the_nearest_match_on_either_side() {
+    let document = document("ab\nxx ab ab\n\nab\n");
+    assert_eq!(find(&document, &at(4, 1), SearchDir::Forward), Some(at(6, 1)));
+    assert_eq!(find(&document, &at(4, 1), SearchDir::Backward), Some(at(3, 1)));
+    assert_eq!(find(&document, &at(7, 1), SearchDir::Forward), Some(at(0, 3)));
+    assert_eq!(find(&document, &at(3, 1), SearchDir::Backward), Some(at(0, 0)));
+  }
+
+  #[test]
+  fn find_counts_a_match_under_the_cursor_forward_only() {
+    let document = document("xx ab\n");
+    assert_eq!(find(&document, &at(3, 0), SearchDir::Forward), Some(at(3, 0)));
+    assert_eq!(find(&document, &at(3, 0), SearchDir::Backward), None);
+  }
+
+  #[test]
+  fn find_stops_at_the_document_ends() {
+    let document = document("ab\nxx\nab\n");
+    assert_eq!(find(&document, &at(1, 2), SearchDir::Forward), None);
+    assert_eq!(find(&document, &at(0, 0), SearchDir::Backward), None);
+  }
+
+  // a path in a fresh directory of its own under the temp directory
+  fn temp_path(test: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("slime-{}-{test}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.join("file.txt")
+  }
+
+  // opens `bytes` from a file, edits nothing and saves them back
+  fn round_trip(test: &str, bytes: &[u8]) -> Vec<u8> {
+    let path = temp_path(test);
+    fs::write(&path, bytes).unwrap();
+    let mut document = Document::open(path.to_str().unwrap(), |_, _| {}).unwrap();
+    document.save_to_disk(&Config::default(), |_, _| {}).unwrap();
+    let saved = fs::read(&path).unwrap();
+    remove_temp_dir(&path);
+    saved
+  }
+
+  fn remove_temp_dir(path: &Path) {
+    let _ = fs::remove_dir_all(path.parent().unwrap());
+  }
+
+  #[test]
+  fn saving_keeps_a_missing_final_newline() {
+    assert_eq!(round_trip("no-newline", b"one\ntwo"), b"one\ntwo");
+  }
+
+  #[test]
+  fn saving_keeps_a_final_newline_and_a_blank_last_row() {
+    assert_eq!(round_trip("newline", b"one\ntwo\n"), b"one\ntwo\n");
+    assert_eq!(round_trip("blank-last", b"one\n\n"), b"one\n\n");
+    assert_eq!(round_trip("crlf", b"one\r\ntwo"), b"one\r\ntwo");
+  }
+
+  #[test]
+  fn saving_keeps_utf16() {
+    let utf16 = |text: &str, to_bytes: fn(u16) -> [u8; 2]| -> Vec<u8> {
+      text.encode_utf16().flat_map(to_bytes).collect()
+    };
+    let little_endian = utf16("\u{feff}héllo\r\n€\r\n", u16::to_le_bytes);
+    assert_eq!(round_trip("utf16le", &little_endian), little_endian);
+    let big_endian = utf16("\u{feff}héllo\n", u16::to_be_bytes);
+    assert_eq!(round_trip("utf16be", &big_endian), big_endian);
+
+    let path = temp_path("utf16-edit");
+    fs::write(&path, &little_endian).unwrap();
+    let mut document = Document::open(path.to_str().unwrap(), |_, _| {}).unwrap();
+    assert!(document.encoding() == UTF_16LE);
+    document.insert_str(&at(6, 0), "!");
+    document.save_to_disk(&Config::default(), |_, _| {}).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), utf16("\u{feff}héllo!\r\n€\r\n", u16::to_le_bytes));
+    remove_temp_dir(&path);
+  }
+
+  #[test]
+  fn a_failed_save_leaves_the_file_intact() {
+    let path = temp_path("failed-save");
+    fs::write(&path, "old\n").unwrap();
+    let mut document = Document::open(path.to_str().unwrap(), |_, _| {}).unwrap();
+    document.insert_str(&at(0, 0), "new ");
+    // a directory where the temporary file goes makes creating it fail
+    fs::create_dir(path.with_file_name(format!(".file.txt.slime-{}", process::id()))).unwrap();
+    assert!(document.save_to_disk(&Config::default(), |_, _| {}).is_err());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "old\n");
+    assert!(document.is_dirty());
+    remove_temp_dir(&path);
+  }
+
+  #[test]
+  fn opening_a_missing_file_keeps_the_io_error() {
+    let path = temp_path("missing");
+    let opened = Document::open(path.to_str().unwrap(), |_, _| {});
+    assert!(matches!(opened, Err(Error::NotFound(err)) if err.kind() == io::ErrorKind::NotFound));
+    remove_temp_dir(&path);
+  }
+
+  // opens `bytes` the way a file over the lazy loading threshold is
+  fn open_lazily(test: &str, bytes: &[u8]) -> Option<Document> {
+    let path = temp_path(test);
+    fs::write(&path, bytes).unwrap();
+    let mut file = File::open(&path).unwrap();
+    let scanned = scan(&mut file, |_, _| {}).unwrap();
+    remove_temp_dir(&path);
+    scanned.map(|scanned| Document::lazily(file, &scanned))
+  }
+
+  #[test]
+  fn a_large_file_is_read_as_rows_are_reached() {
+    let text = "row\n".repeat(LOAD_CHUNK + 10);
+    let mut document = open_lazily("lazy", text.as_bytes()).unwrap();
+    assert_eq!((document.rows_size(), document.line_count()), (0, LOAD_CHUNK + 10));
+    document.load_rows(1);
+    assert_eq!((document.rows_size(), document.line_count()), (LOAD_CHUNK, LOAD_CHUNK + 10));
+    document.load_all();
+    assert_eq!(document.rows_size(), LOAD_CHUNK + 10);
+    assert!(document.unloaded.is_none());
+    assert!(document.final_newline);
+  }
+
+  #[test]
+  fn scanning_a_large_file_keeps_its_endings_and_split_characters() {
+    // the é is split by the end of the first chunk read
+    let text = "a".repeat(IO_CHUNK - 1) + "é\r\nsecond\r\nthird";
+    let mut document = open_lazily("lazy-crlf", text.as_bytes()).unwrap();
+    document.load_all();
+    assert_eq!(document.row(0).unwrap().string(), "a".repeat(IO_CHUNK - 1) + "é");
+    assert_eq!(texts(&document)[1..], ["second", "third"]);
+    assert!(document.line_ending == LineEnding::CrLf && !document.mixed_line_endings);
+    assert!(!document.final_newline);
+  }
+
+  #[test]
+  fn a_large_file_that_is_not_utf8_is_decoded_whole() {
+    assert!(open_lazily("lazy-latin1", b"caf\xe9\n").is_none());
+    assert!(open_lazily("lazy-cut", "caf\u{e9}".as_bytes().split_last().unwrap().1).is_none());
+  }
+
+  #[test]
+  fn the_swap_of_a_large_file_copies_the_rows_not_loaded() {
+    let path = temp_path("lazy-swap");
+    fs::write(&path, "row\r\n".repeat(LOAD_CHUNK + 2)).unwrap();
+    let mut file = File::open(&path).unwrap();
+    let scanned = scan(&mut file, |_, _| {}).unwrap().unwrap();
+    let mut document = Document { path: Some(path.to_str().unwrap().to_string()), ..Document::lazily(file, &scanned) };
+    document.load_rows(1);
+    document.insert_str(&at(0, 0), "new ");
+    document.write_swap(&at(4, 0)).unwrap();
+    assert_eq!(document.rows_size(), LOAD_CHUNK);
+    let swap = swap::read(path.to_str().unwrap()).unwrap();
+    assert_eq!(swap.text, "new row".to_string() + &"\nrow".repeat(LOAD_CHUNK + 1));
+    remove_temp_dir(&path);
+  }
+
+  // a 50MB file with a long first row, edited near the top
+  #[test]
+  #[ignore = "benchmark, run with `cargo test --release -- --ignored --nocapture`"]
+  fn bench_editing_the_top_of_a_large_file() {
+    let path = temp_path("bench-large");
+    let line = "the quick brown fox jumps over the lazy dog 0123456789\n";
+    fs::write(&path, "x".repeat(100_000) + "\n" + &line.repeat((50 << 20) / line.len())).unwrap();
+    let started = Instant::now();
+    let mut document = Document::open(path.to_str().unwrap(), |_, _| {}).unwrap();
+    // a large file is turned into rows as the view reaches them
+    document.load_rows(1001);
+    let opened = started.elapsed();
+
+    let started = Instant::now();
+    for x in 0..1000 {
+      document.insert(&at(50_000 + x, 0), 'y');
+    }
+    let long_row = started.elapsed();
+    let started = Instant::now();
+    for y in 1..1001 {
+      document.insert(&at(10, y), 'y');
+    }
+    let short_rows = started.elapsed();
+    let started = Instant::now();
+    for y in (1..201).step_by(2) {
+      document.insert(&at(5, y), '\n');
+    }
+    let splits = started.elapsed();
+    println!(
+      "opened in {opened:.2?}; 1000 keys typed into the long row in {long_row:.2?}, \
+       into short rows in {short_rows:.2?}; 100 rows split in {splits:.2?}",
+    );
+    remove_temp_dir(&path);
+  }
 }
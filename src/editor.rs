@@ -1,31 +1,115 @@
+use std::cmp::Reverse;
+use std::collections::VecDeque;
 use std::env;
-use std::io::Error;
+use std::fs;
+use std::fmt::Write;
+use std::mem;
+use std::ops::Range;
+use std::path::Path;
 use std::time::{Instant, Duration};
 
-use crossterm::event::{Event, KeyCode, KeyModifiers, KeyEvent};
-use crossterm::style::{Color, Colors};
+use crossterm::event::{Event, KeyCode, KeyModifiers, KeyEvent, MouseButton, MouseEvent, MouseEventKind};
+use crossterm::style::{Color, Colors, SetForegroundColor};
+use encoding_rs::{Encoding, UTF_8};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-use crate::Row;
-use crate::Terminal;
+use crate::{RenderOptions, Row};
+use crate::row::Case;
+use crate::{Terminal, TerminalBackend};
+use crate::Buffer;
+use crate::Pane;
 use crate::Document;
+use crate::Error;
+use crate::LineEnding;
+use crate::Config;
+use crate::SyntaxDef;
+use crate::syntax;
+use crate::swap;
+use crate::positions;
+use crate::shell;
+use crate::date;
+use crate::progress::Progress;
+use crate::keymap::{Action, Keymap};
+use crate::startup::Startup;
+use crate::{Operation, UndoStack};
+use crate::clipboard;
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, PartialEq, Debug)]
 pub struct Position<T> {
   pub x: T,
   pub y: T,
 }
 
+// a rectangle of text, `x` counts screen columns and the right edge is
+// exclusive, the bottom row is part of it
+struct Block {
+  top_left: Position<usize>,
+  bottom_right: Position<usize>,
+}
+
+// how the editor was left, `main` turns it into the exit code
+pub enum ExitStatus {
+  Clean,
+  // quit without saving the changes to these buffers
+  Discarded(Vec<String>),
+}
+
+impl ExitStatus {
+  pub fn code(&self) -> i32 {
+    match self {
+      ExitStatus::Clean => 0,
+      ExitStatus::Discarded(_) => 2,
+    }
+  }
+
+  // a line for stderr once the terminal is back to normal
+  pub fn summary(&self) -> Option<String> {
+    match self {
+      ExitStatus::Clean => None,
+      ExitStatus::Discarded(names) => Some(format!("unsaved changes discarded in {}", names.join(", "))),
+    }
+  }
+}
+
+#[derive(PartialEq, PartialOrd, Clone, Copy)]
+pub enum Severity {
+  Info,
+  Warning,
+  Error,
+}
+
 pub struct StatusMessage {
   text: String,
   time: Instant,
+  severity: Severity,
 }
 
 impl StatusMessage {
+  // news, problems are made with `error` and `warning`
   fn from(message: String) -> Self {
-    Self {
-      text: message,
-      time: Instant::now()
-    }
+    Self::with_severity(message, Severity::Info)
+  }
+
+  fn error(message: String) -> Self {
+    Self::with_severity(message, Severity::Error)
+  }
+
+  fn warning(message: String) -> Self {
+    Self::with_severity(message, Severity::Warning)
+  }
+
+  fn with_severity(text: String, severity: Severity) -> Self {
+    Self { text, time: Instant::now(), severity }
+  }
+
+  // how long the message stays, problems longer than news
+  fn live_time(&self) -> Duration {
+    Duration::from_secs(match self.severity {
+      Severity::Info => STATUS_MESSAGE_LIVE_TIME,
+      Severity::Warning => 2 * STATUS_MESSAGE_LIVE_TIME,
+      Severity::Error => 3 * STATUS_MESSAGE_LIVE_TIME,
+    })
   }
 }
 
@@ -36,92 +120,348 @@ pub enum SearchDir {
   Backward,
 }
 
-pub struct Editor {
+#[derive(Copy, Clone)]
+pub struct SearchOptions {
+  pub case_sensitive: bool,
+  pub whole_word: bool,
+  // only honoured when built with the `regex` feature
+  pub regex: bool,
+}
+
+impl Default for SearchOptions {
+  fn default() -> Self {
+    Self {
+      case_sensitive: true,
+      whole_word: false,
+      regex: false,
+    }
+  }
+}
+
+impl SearchOptions {
+  fn label(self) -> String {
+    let state = |enabled: bool| if enabled { "on" } else { "off" };
+    let regex = if cfg!(feature = "regex") {
+      format!(", Alt-R regex: {}", state(self.regex))
+    } else {
+      String::new()
+    };
+    format!(
+      "Search (Alt-C case: {}, Alt-W word: {}{regex}): ",
+      state(self.case_sensitive),
+      state(self.whole_word),
+    )
+  }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum LineNumberMode {
+  Off,
+  Absolute,
+  Relative,
+}
+
+impl LineNumberMode {
+  fn next(self) -> Self {
+    match self {
+      LineNumberMode::Off => LineNumberMode::Absolute,
+      LineNumberMode::Absolute => LineNumberMode::Relative,
+      LineNumberMode::Relative => LineNumberMode::Off,
+    }
+  }
+}
+
+// how rows longer than the `linelimit` option are marked
+#[derive(PartialEq, Copy, Clone)]
+pub enum LimitStyle {
+  // the part past the limit gets a dark red background
+  Overflow,
+  // a `!` between the line number and the text
+  Gutter,
+}
+
+impl LimitStyle {
+  pub fn parse(name: &str) -> Option<Self> {
+    match name {
+      "overflow" => Some(LimitStyle::Overflow),
+      "gutter" => Some(LimitStyle::Gutter),
+      _ => None,
+    }
+  }
+}
+
+// with the `modal` option keys are commands in normal mode and only type
+// in insert mode, without it the editor always inserts
+#[derive(PartialEq, Copy, Clone)]
+pub enum EditorMode {
+  Normal,
+  Insert,
+}
+
+// `T` is the terminal drawn to and read from, the real one unless the
+// editor is driven by something else
+#[allow(clippy::struct_excessive_bools)]
+pub struct Editor<T = Terminal> {
   should_quit: bool,  
-  terminal: Terminal,
-  cursor_position: Position<usize>,
-  cursor_offset: Position<usize>,
-  document: Document,
+  terminal: T,
   status_message: StatusMessage,
   quit_times: u8,  
+  clipboard: String,
+  search_options: SearchOptions,
+  // matches highlighted while the search prompt is open
+  search_matches: Vec<(Position<usize>, usize)>,
+  last_search: Option<String>,
+  // cursors besides `cursor_position`, keys that type, delete or move act
+  // at all of them
+  cursors: Vec<Position<usize>>,
+  config: Config,
+  syntaxes: Vec<SyntaxDef>,
+  keymap: Keymap,
+  disk_checked_at: Instant,
+  // when a key was last pressed and whether an auto-save and writing the
+  // swap files are due since then
+  key_pressed_at: Instant,
+  auto_save_pending: bool,
+  swap_pending: bool,
+  // what each text row of the screen showed in the last frame, `None`
+  // forces a redraw
+  drawn_rows: Vec<Option<String>>,
+  // the status bar of each pane as last drawn, it is redrawn while idle
+  // when it would read differently
+  drawn_status: Vec<String>,
+  // the message shown under it, cleared once it expires without a key
+  drawn_message: String,
+  // the latest size reported while the terminal is being resized, taken on
+  // once it stops sending them so a drag doesn't redraw every step
+  pending_resize: Option<(u16, u16)>,
+  // the screen column moving up and down keeps to, with where that left
+  // the cursor; other keys drop it and the cursor ending up elsewhere
+  // makes it count from there again
+  preferred_column: Option<(Position<usize>, usize)>,
+  // closing characters inserted by auto-pairing, innermost last
+  auto_closed: Vec<Position<usize>>,
+  // every open buffer, the one the cursor is in is `buffer()`
+  buffers: Vec<Buffer>,
+  active: usize,
+  // panes side by side, the slot of the active one is stale while its
+  // cursor and offset are the active buffer's
+  panes: Vec<Pane>,
+  active_pane: usize,
+  // buffers and positions left by searches, go-to-line and other long
+  // moves, oldest first; `jump_index` is where going back and forward is
+  // at, past the end unless walking through them
+  jumps: Vec<(usize, Position<usize>)>,
+  jump_index: usize,
+  mode: EditorMode,
+  // the first key of a two key normal mode command like `dd`
+  pending_key: Option<char>,
+  // the keys typed since recording started, the last recorded macro and
+  // the keys of it still to be replayed, which are read before the
+  // terminal's
+  recording: Option<Vec<KeyEvent>>,
+  macro_keys: Vec<KeyEvent>,
+  replay: VecDeque<KeyEvent>,
+  // a count typed after the repeat key, `Some(0)` until a digit follows
+  pending_count: Option<usize>,
+  // typed characters replace the one under the cursor
+  overwrite: bool,
+  // messages waiting for an error or warning to have been shown for
+  // `STATUS_MESSAGE_HOLD`, oldest first
+  status_queue: VecDeque<StatusMessage>,
 }
 
 const STATUS_BAR_BG: Color = Color::Rgb { r: 239, g: 239, b: 239 };
 const STATUS_BAR_FG: Color = Color::Rgb { r: 63, g: 63, b: 63 };
+const GUTTER_FG: Color = Color::Rgb { r: 127, g: 127, b: 127 };
+const MATCH_BG: Color = Color::Rgb { r: 90, g: 90, b: 40 };
+const CURRENT_MATCH_BG: Color = Color::Rgb { r: 200, g: 140, b: 0 };
+const TRAILING_WHITESPACE_BG: Color = Color::Rgb { r: 160, g: 30, b: 30 };
+const EXTRA_CURSOR_BG: Color = Color::Rgb { r: 150, g: 150, b: 150 };
+const LIMIT_OVERFLOW_BG: Color = Color::Rgb { r: 90, g: 30, b: 30 };
+const PANE_DIVIDER: &str = "│";
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const STATUS_MESSAGE_LIVE_TIME: u64 = 5; // seconds
+const STATUS_MESSAGE_HOLD: u64 = 2; // seconds before a lesser message replaces it
+const ERROR_FG: Color = Color::Rgb { r: 230, g: 80, b: 80 };
+const WARNING_FG: Color = Color::Rgb { r: 220, g: 180, b: 60 };
 const QUIT_TIMES: u8 = 3;
+const DISK_CHECK_INTERVAL: u64 = 1; // seconds
+const SWAP_DELAY: u64 = 2; // seconds without a key press
+const JUMP_LIMIT: usize = 100;
+const MATCH_COUNT_LIMIT: usize = 1000;
+const REPEAT_LIMIT: usize = 10_000;
+const MOUSE_SCROLL_LINES: usize = 3;
 
 impl Editor {
-  pub fn run(&mut self) -> std::io::Result<()> { 
+  // stdin is read before the terminal is set up, taking it over for keys
+  pub fn default() -> Result<Editor, Error> {
+    let startup = Startup::load();
+    Ok(Self::new(Terminal::default()?, startup))
+  }
+}
+
+impl<T: TerminalBackend> Editor<T> {
+  pub fn run(&mut self) -> Result<ExitStatus, Error> { 
+    let result = self.event_loop();
+    if result.is_err() {
+      self.die()?;
+    }
+
+    result
+  }
+
+  fn event_loop(&mut self) -> Result<ExitStatus, Error> {
     self.refresh_screen()?;                   
 
     while !self.should_quit {                           
-      if let Some(event) = self.terminal.read_event()? {                         
-        if let Err(err) = self.process_event(event) {
-          self.die(err)?;        
-        }                                                    
-        self.refresh_screen()?;
-      }      
-    }      
+      if let Some(event) = self.read_event()? {                         
+        // a replayed macro is drawn once it is done
+        if self.process_event(&event) && self.replay.is_empty() {
+          self.refresh_screen()?;
+        }
+      } else {
+        // nothing was pressed for a poll interval, time may still have
+        // changed what the screen should show
+        let resized = self.pending_resize.is_some();
+        let changed = self.check_disk();
+        let saved = self.auto_save();
+        self.advance_status();
+        let stale = self.drawn_status != self.pane_statuses() || self.drawn_message != self.visible_message();
+        if self.write_swaps() || resized || saved || changed || stale {
+          self.refresh_screen()?;
+        }
+      }
+    }
+    let discarded = self.dirty_buffers();
+    self.close_buffers();
 
     self.refresh_screen()?;
     
-    Ok(())
+    Ok(if discarded.is_empty() { ExitStatus::Clean } else { ExitStatus::Discarded(discarded) })
   }
 
-  pub fn default() -> Result<Editor, Error> {    
-    let args: Vec<String> = env::args().collect();
-    
-    let mut initial_status = String::from("HELP: Ctrl-C = exit");    
-    let document = if args.len() > 1 {
-      let file_name = &args[1];
-      let doc = Document::open(&file_name);
-      if doc.is_ok() {
-        doc.unwrap()
-      } else {
-        initial_status = format!("ERR: Could not open file {}", file_name);
-        Document::default()
-      }
-    } else {
-      Document::default()
+  // opens the files given as arguments with the settings `startup` was
+  // loaded with, on the terminal already so opening a large one shows how
+  // far it got
+  pub fn new(mut terminal: T, startup: Startup) -> Self {
+    let Startup { args, mut stdin, config, keymap, syntaxes, errors } = startup;
+    let mut initial_status = match errors.first() {
+      Some(err) => StatusMessage::error(format!("ERR: {err}")),
+      None => StatusMessage::from(format!("HELP: {} = exit", keymap.describe(Action::Quit))),
     };
+    let mut buffers = Vec::new();
+    for file in &args.files {
+      let opened = match &file.path {
+        Some(path) => {
+          let mut progress = Progress::new(&mut terminal, format!("Opening {path}"));
+          open_path(path, &syntaxes, &keymap, |read, total| progress.advance(read, total))
+        },
+        // a second `-` finds stdin read already
+        None => match stdin.take() {
+          Some(document) => Ok((document, None)),
+          None => continue,
+        },
+      };
+      let (mut doc, warning) = match opened {
+        Ok(opened) => opened,
+        Err(err) => {
+          let file_name = file.path.as_deref().unwrap_or("stdin");
+          initial_status = StatusMessage::error(format!("ERR: Could not open file {file_name}: {err}"));
+          continue;
+        },
+      };
+      if args.read_only {
+        doc.set_read_only(true);
+      }
+      if let Some(warning) = warning.filter(|_| buffers.is_empty()) {
+        initial_status = warning;
+      }
+      let remembered = doc.canonical_path()
+        .filter(|_| config.remember_cursor && file.line.is_none())
+        .and_then(positions::find);
+      doc.load_rows(file.line.or(remembered.as_ref().map(|position| position.y + 1)).unwrap_or(0));
+      let mut buffer = Buffer::new(doc, UndoStack::new(config.undo_limit));
+      buffer.cursor_position = match remembered {
+        // the file may have shrunk since
+        Some(position) if position.y < buffer.document.rows_size() =>
+          position_in(&buffer.document, Some(position.y + 1), Some(position.x + 1)),
+        _ => position_in(&buffer.document, file.line, file.column),
+      };
+      buffers.push(buffer);
+    }
+    if buffers.is_empty() {
+      buffers.push(Buffer::new(Document::default(), UndoStack::new(config.undo_limit)));
+    }
 
-    Ok(Self{
+    let mut editor = Self{
       should_quit: false,
-      terminal: Terminal::default().expect("Failed to initialize terminal"),
-      cursor_position: Position::default(),
-      document,
-      cursor_offset: Position::default(), 
-      status_message: StatusMessage::from(initial_status),    
+      terminal,
+      status_message: initial_status,
+      status_queue: VecDeque::new(),
       quit_times: QUIT_TIMES,       
-    })
-  }
+      clipboard: String::new(),
+      search_options: SearchOptions::default(),
+      search_matches: Vec::new(),
+      last_search: None,
+      cursors: Vec::new(),
+      syntaxes,
+      keymap,
+      disk_checked_at: Instant::now(),
+      key_pressed_at: Instant::now(),
+      auto_save_pending: false,
+      swap_pending: false,
+      drawn_rows: Vec::new(),
+      drawn_status: Vec::new(),
+      drawn_message: String::new(),
+      pending_resize: None,
+      preferred_column: None,
+      auto_closed: Vec::new(),
+      buffers,
+      active: 0,
+      panes: vec![Pane::default()],
+      active_pane: 0,
+      jumps: Vec::new(),
+      jump_index: 0,
+      mode: if config.modal { EditorMode::Normal } else { EditorMode::Insert },
+      pending_key: None,
+      recording: None,
+      macro_keys: Vec::new(),
+      replay: VecDeque::new(),
+      pending_count: None,
+      overwrite: false,
+      config,
+    };
+    editor.scroll();
+    editor.recover_swaps();
 
-  fn draw_row(&mut self, row: &Row, row_index: usize) -> Result<(), Error> {
-    let start = self.cursor_offset.x;
-    let end = self.cursor_offset.x + (self.terminal.size().width as usize);    
-    let terminal_row = row.render(start, end);
-    self.terminal.move_cursor(0, (row_index - self.cursor_offset.y) as u16)?;
-    self.terminal.print_string(&terminal_row)        
+    editor
   }
 
   fn draw_rows(&mut self) -> Result<(), Error> {        
-    for terminal_row_index in 0..self.terminal.size().height.saturating_sub(1) {
-      let row_index = (terminal_row_index as usize) + self.cursor_offset.y;
+    let panes: Vec<Vec<String>> = (0..self.panes.len()).map(|index| self.render_pane(index)).collect();
+    let text_height = self.terminal.size().height.saturating_sub(2);
+    self.drawn_rows.resize(text_height as usize, None);
+    for terminal_row_index in 0..text_height {
+      // rows that look the same as in the previous frame are left alone
+      let mut frame_row = String::new();
+      for (index, lines) in panes.iter().enumerate() {
+        let _ = write!(frame_row, "{}:{}\0", self.pane_columns(index).start, lines[terminal_row_index as usize]);
+      }
+      let drawn = &mut self.drawn_rows[terminal_row_index as usize];
+      if drawn.as_ref() == Some(&frame_row) {
+        continue;
+      }
+      *drawn = Some(frame_row);
       self.terminal.move_cursor(0, terminal_row_index)?;
       self.terminal.clear_current_line()?;      
-      if row_index >= self.document.rows_size() {
-        self.terminal.print_string("~\r")?;
-      }
-      if let Some(row) = self.document.row(row_index as usize) {
-        // TODO: replace with draw_row method call (mutable and immutable borrow)
-        // self.draw_row(row)?;
-        let start = self.cursor_offset.x;
-        let end = self.cursor_offset.x + (self.terminal.size().width as usize);    
-        let terminal_row = row.render(start, end);
-        self.terminal.move_cursor(0, terminal_row_index)?;
-        self.terminal.print_string(&terminal_row)?;        
+      for (index, lines) in panes.iter().enumerate() {
+        let columns = self.pane_columns(index);
+        let x = u16::try_from(columns.start).unwrap_or(u16::MAX);
+        self.terminal.move_cursor(x, terminal_row_index)?;
+        self.terminal.print_string(&lines[terminal_row_index as usize])?;
+        if index + 1 < panes.len() {
+          self.draw_divider(columns.end, terminal_row_index)?;
+        }
       }
     }
     self.terminal.move_cursor(0, 0)?;
@@ -129,323 +469,2727 @@ impl Editor {
     Ok(())
   }
 
+  // the text rows of a pane, each one at most as wide as the pane
+  fn render_pane(&mut self, index: usize) -> Vec<String> {
+    let text_height = self.terminal.size().height.saturating_sub(2) as usize;
+    let (buffer, cursor, offset) = self.pane_view(index);
+    let (cursor, offset) = (cursor.clone(), offset.clone());
+    self.buffers[buffer].document.highlight(offset.y + text_height);
+    let document = &self.buffers[buffer].document;
+    let gutter_width = self.gutter_width_for(document, &cursor, &offset);
+    let width = self.pane_columns(index).len();
+    let focused = index == self.active_pane;
+
+    let text_width = width.saturating_sub(gutter_width);
+    let tab_width = self.config.tab_width;
+    let options = RenderOptions {
+      tab_width,
+      invisibles: self.config.show_invisibles,
+      guides: &self.config.guides,
+    };
+    let numbered = self.config.line_numbers != LineNumberMode::Off;
+    let limit = self.config.line_limit;
+    let marks_gutter = limit > 0 && self.config.limit_style == LimitStyle::Gutter;
+    // the line number on a row's first screen line, blank on the others,
+    // followed by `!` there if the row is over the limit
+    let gutter = |number: Option<usize>, over: bool| {
+      if gutter_width == 0 {
+        return String::new();
+      }
+      let number = number.filter(|_| numbered).map(|number| number.to_string()).unwrap_or_default();
+      let separator = if over && marks_gutter {
+        format!("{}!{}", SetForegroundColor(WARNING_FG), SetForegroundColor(Color::Reset))
+      } else {
+        " ".to_string()
+      };
+      format!(
+        "{}{number:>width$}{}{separator}",
+        SetForegroundColor(GUTTER_FG),
+        SetForegroundColor(Color::Reset),
+        width = gutter_width - 1,
+      )
+    };
+
+    let mut lines = Vec::with_capacity(text_height);
+    let mut row_index = offset.y;
+    while lines.len() < text_height {
+      let Some(row) = document.row(row_index) else {
+        // past the end of the file, drawn at the pane's first column
+        lines.push("~".to_string());
+        row_index += 1;
+        continue;
+      };
+      let over = limit > 0 && row.width(tab_width) > limit;
+      let selection = if focused { self.selected_columns(row_index) } else { None };
+      let matches = self.row_marks(row, row_index, cursor.y, focused);
+      let number = Some(self.line_number(row_index, cursor.y));
+      if self.config.wrap {
+        for (segment_index, segment) in wrap_segments(row, text_width, tab_width).into_iter().enumerate() {
+          if lines.len() == text_height {
+            break;
+          }
+          let first = segment_index == 0;
+          let mut line = gutter(number.filter(|_| first), over && first);
+          line.push_str(&row.render_segment(segment, 0, text_width, options, selection.as_ref(), &matches));
+          lines.push(line);
+        }
+      } else {
+        // text cut off by the pane's edges gives up the edge column to a
+        // marker, unless the cursor is on it
+        let row_width = row.width(tab_width);
+        let cursor_rx = (row_index == cursor.y).then(|| row.cx_to_rx(cursor.x, tab_width));
+        let left = offset.x > 0 && row_width > 0 && text_width > 1 && cursor_rx != Some(offset.x);
+        let right =
+          row_width > offset.x + text_width && text_width > 1 && cursor_rx != Some(offset.x + text_width - 1);
+        let mut line = gutter(number, over);
+        if left {
+          line.push_str(&scroll_marker('<'));
+        }
+        let (start, end) = (offset.x + usize::from(left), offset.x + text_width - usize::from(right));
+        line.push_str(&row.render(start, end, options, selection.as_ref(), &matches));
+        if right {
+          line.push_str(&scroll_marker('>'));
+        }
+        lines.push(line);
+      }
+      row_index += 1;
+    }
+    lines
+  }
+
+  // background colors of a row's graphemes, later ones drawn over
+  // earlier ones
+  fn row_marks(&self, row: &Row, row_index: usize, cursor_y: usize, focused: bool) -> Vec<(Range<usize>, Color)> {
+    let tab_width = self.config.tab_width;
+    let mut matches = if focused { self.match_columns(row_index) } else { Vec::new() };
+    if self.config.show_trailing_whitespace {
+      // first, so search matches are drawn over it
+      matches.insert(0, (row.trailing_whitespace(), TRAILING_WHITESPACE_BG));
+    }
+    let limit = self.config.line_limit;
+    if limit > 0 && self.config.limit_style == LimitStyle::Overflow && row.width(tab_width) > limit {
+      // under everything else
+      matches.insert(0, (row.rx_to_cx(limit, tab_width)..row.size(), LIMIT_OVERFLOW_BG));
+    }
+    if focused {
+      // the extra cursors are blocks, the terminal draws the main one
+      let cursors = self.cursors.iter().filter(|cursor| cursor.y == row_index);
+      matches.extend(cursors.map(|cursor| (cursor.x..cursor.x + 1, EXTRA_CURSOR_BG)));
+      // a block no wider than the cursor is drawn the same way on the
+      // rows that reach its column
+      let column = self.block().filter(|block| {
+        block.top_left.x == block.bottom_right.x
+          && (block.top_left.y..=block.bottom_right.y).contains(&row_index)
+          && row_index != cursor_y
+          && row.width(tab_width) >= block.top_left.x
+      });
+      if let Some(block) = column {
+        let x = row.rx_to_cx(block.top_left.x, tab_width);
+        matches.push((x..x + 1, EXTRA_CURSOR_BG));
+      }
+    }
+    matches
+  }
+
+  fn draw_divider(&mut self, x: usize, y: u16) -> Result<(), Error> {
+    self.terminal.move_cursor(u16::try_from(x).unwrap_or(u16::MAX), y)?;
+    self.terminal.set_fg_color(GUTTER_FG)?;
+    self.terminal.print_string(PANE_DIVIDER)?;
+    self.terminal.reset_fg_color()
+  }
+
+  // screen columns covered by a pane, panes share the width evenly and
+  // are separated by a divider column
+  fn pane_columns(&self, index: usize) -> Range<usize> {
+    let count = self.panes.len();
+    let width = self.terminal.size().width as usize;
+    let pane_width = width.saturating_sub(count - 1) / count;
+    let start = index * (pane_width + 1);
+    let end = if index + 1 == count { width } else { start + pane_width };
+    start..end.max(start)
+  }
+
+  // buffer, cursor and scroll offset shown in a pane
+  fn pane_view(&self, index: usize) -> (usize, &Position<usize>, &Position<usize>) {
+    if index == self.active_pane {
+      (self.active, &self.buffer().cursor_position, &self.buffer().cursor_offset)
+    } else {
+      let pane = &self.panes[index];
+      (pane.buffer, &pane.cursor_position, &pane.cursor_offset)
+    }
+  }
+
+  // columns reserved on the left for line numbers
+  fn gutter_width(&self) -> usize {
+    self.gutter_width_for(&self.buffer().document, &self.buffer().cursor_position, &self.buffer().cursor_offset)
+  }
+
+  fn gutter_width_for(&self, document: &Document, cursor: &Position<usize>, offset: &Position<usize>) -> usize {
+    let widest = match self.config.line_numbers {
+      // a gutter of just the column marking long rows
+      LineNumberMode::Off => {
+        return usize::from(self.config.line_limit > 0 && self.config.limit_style == LimitStyle::Gutter);
+      },
+      LineNumberMode::Absolute => document.line_count(),
+      LineNumberMode::Relative => {
+        let cursor_y = cursor.y;
+        let text_height = self.terminal.size().height.saturating_sub(2) as usize;
+        let last_visible = (offset.y + text_height)
+          .min(document.rows_size())
+          .saturating_sub(1);
+        let max_distance = cursor_y
+          .saturating_sub(offset.y)
+          .max(last_visible.saturating_sub(cursor_y));
+        max_distance.max(cursor_y + 1)
+      },
+    };
+    widest.max(1).to_string().len() + 1
+  }
+
+  fn line_number(&self, row_index: usize, cursor_y: usize) -> usize {
+    if self.config.line_numbers == LineNumberMode::Relative && row_index != cursor_y {
+      row_index.abs_diff(cursor_y)
+    } else {
+      row_index + 1
+    }
+  }
+
+  fn text_width(&self) -> usize {
+    self.pane_columns(self.active_pane).len().saturating_sub(self.gutter_width())
+  }
+
+  // ordered selection endpoints, the end is exclusive
+  fn selection(&self) -> Option<(Position<usize>, Position<usize>)> {
+    let anchor = self.buffer().selection_anchor.clone().filter(|_| !self.buffer().block_selection)?;
+    let cursor = self.buffer().cursor_position.clone();
+    if (anchor.y, anchor.x) <= (cursor.y, cursor.x) {
+      Some((anchor, cursor))
+    } else {
+      Some((cursor, anchor))
+    }
+  }
+
+  fn selected_columns(&self, row_index: usize) -> Option<Range<usize>> {
+    if let Some(block) = self.block() {
+      return (block.top_left.y..=block.bottom_right.y)
+        .contains(&row_index)
+        .then(|| self.block_columns(&block, row_index));
+    }
+    let (start, end) = self.selection()?;
+    if row_index < start.y || row_index > end.y {
+      return None;
+    }
+    let from = if row_index == start.y { start.x } else { 0 };
+    let to = if row_index == end.y {
+      end.x
+    } else {
+      self.buffer().document.row(row_index).map_or(0, Row::size)
+    };
+
+    Some(from..to)
+  }
+  fn block(&self) -> Option<Block> {
+    let anchor = self.buffer().selection_anchor.as_ref().filter(|_| self.buffer().block_selection)?;
+    let cursor = &self.buffer().cursor_position;
+    let anchor_rx = self.buffer().document.row(anchor.y).map_or(0, |row| row.cx_to_rx(anchor.x, self.config.tab_width));
+    let cursor_rx = self.cursor_rx();
+    Some(Block {
+      top_left: Position { x: anchor_rx.min(cursor_rx), y: anchor.y.min(cursor.y) },
+      bottom_right: Position { x: anchor_rx.max(cursor_rx), y: anchor.y.max(cursor.y) },
+    })
+  }
+
+  // graphemes of a row inside the columns of the block
+  fn block_columns(&self, block: &Block, row_index: usize) -> Range<usize> {
+    let tab_width = self.config.tab_width;
+    self.buffer().document.row(row_index).map_or(0..0, |row| {
+      row.rx_to_cx(block.top_left.x, tab_width)..row.rx_to_cx(block.bottom_right.x, tab_width)
+    })
+  }
+
+
   fn draw_message_bar(&mut self) -> Result<(), Error> {
     self.terminal.move_cursor(0, self.terminal.size().height.saturating_sub(1))?;
     self.terminal.clear_current_line()?;
-    let message = &self.status_message;
-    if Instant::now() - message.time < Duration::new(STATUS_MESSAGE_LIVE_TIME, 0) {      
-      let mut text = message.text.clone();
-      text.truncate(self.terminal.size().width as usize);      
-      self.terminal.print_string(&text)?;
-    }   
+    self.drawn_message = self.visible_message();
+    let mut text = self.drawn_message.clone();
+    truncate_columns(&mut text, self.terminal.size().width as usize);
+    match self.status_message.severity {
+      Severity::Info => self.terminal.print_string(&text)?,
+      severity => {
+        self.terminal.set_fg_color(if severity == Severity::Error { ERROR_FG } else { WARNING_FG })?;
+        self.terminal.print_string(&text)?;
+        self.terminal.reset_fg_color()?;
+      },
+    }
 
     Ok(())
   }
 
+  // the status message, or nothing once it has been shown for its live
+  // time
+  fn visible_message(&self) -> String {
+    let message = &self.status_message;
+    if message.time.elapsed() < message.live_time() {
+      message.text.clone()
+    } else {
+      String::new()
+    }
+  }
+
+  // shows a message, unless an error or warning shown only just now would
+  // be replaced by something less severe; it then waits its turn
+  fn set_status(&mut self, message: StatusMessage) {
+    self.status_queue.push_back(message);
+    self.advance_status();
+  }
+
+  // moves on to the next waiting message once the shown one may go
+  fn advance_status(&mut self) {
+    while let Some(next) = self.status_queue.front() {
+      let shown = &self.status_message;
+      let held = shown.time.elapsed() < Duration::from_secs(STATUS_MESSAGE_HOLD)
+        && shown.severity > next.severity
+        && !shown.text.is_empty();
+      if held {
+        return;
+      }
+      self.status_message = self.status_queue.pop_front().unwrap();
+      // whatever waited behind it starts its own time now
+      self.status_message.time = Instant::now();
+    }
+  }
+
   fn draw_status_bar(&mut self) -> Result<(), Error> {
-    let mut file_name = "[No Name]".to_string();    
-    if let Some(path) = &mut self.document.path {
-      file_name = path.clone();
-      file_name.truncate(20);
-    }    
-    let mut status = format!("{} -- {} lines", file_name, self.document.rows_size());
+    let y = self.terminal.size().height.saturating_sub(2);
+    self.drawn_status = self.pane_statuses();
+    for index in 0..self.panes.len() {
+      let columns = self.pane_columns(index);
+      let status = self.drawn_status[index].clone();
+      self.terminal.set_colors(Colors::new(STATUS_BAR_FG, STATUS_BAR_BG))?;
+      self.terminal.move_cursor(u16::try_from(columns.start).unwrap_or(u16::MAX), y)?;    
+      self.terminal.print_string(&status)?;
+      self.terminal.reset_colors()?;
+      if index + 1 < self.panes.len() {
+        self.draw_divider(columns.end, y)?;
+      }
+    }
+    Ok(())
+  }
+
+  fn pane_statuses(&self) -> Vec<String> {
+    (0..self.panes.len()).map(|index| self.pane_status(index, self.pane_columns(index).len())).collect()
+  }
 
-    if self.document.is_dirty() {
-      status.push_str(" (modified)");
+  fn pane_status(&self, index: usize, width: usize) -> String {
+    let (buffer, cursor, _) = self.pane_view(index);
+    let document = &self.buffers[buffer].document;
+    let mut status = format!(" -- {} lines", document.line_count());
+
+    if let Some(syntax) = document.syntax() {
+      status = format!("{status} [{}]", syntax.name);
+    }
+    if document.encoding() != UTF_8 {
+      status = format!("{status} [{}]", document.encoding().name());
+    }
+    if document.is_read_only() {
+      status.push_str(" (read-only)");
+    }
+    if document.is_dirty() {
+      let auto_save = self.config.auto_save > 0 && document.path.is_some() && !document.is_read_only();
+      status.push_str(if auto_save { " (modified, auto-save pending)" } else { " (modified)" });
+    }
+    if let Some(saved) = document.last_saved() {
+      status = format!("{status} saved {}", time_ago(saved.elapsed()));
     }
 
-    let width = self.terminal.size().width as usize;
-    
-    let line_indicator = format!(
-      "{}/{}",
-      self.cursor_position.y,
-      self.cursor_position.x,
-    );    
+    let line_indicator = self.config.position_format
+      .replace("{line}", &(cursor.y + 1).to_string())
+      .replace("{column}", &(cursor.x + 1).to_string())
+      .replace("{lines}", &document.line_count().to_string())
+      .replace("{percent}", &percent_through(cursor.y, document.line_count()));
+
+    // long paths lose their middle first, so the file name stays visible
+    let mut prefix = String::new();
+    if self.config.modal && index == self.active_pane {
+      prefix.push_str(if self.mode == EditorMode::Normal { "NORMAL " } else { "INSERT " });
+    }
+    if self.recording.is_some() && index == self.active_pane {
+      prefix.push_str("REC ");
+    }
+    if index == self.active_pane {
+      prefix.push_str(if self.overwrite { "OVR " } else { "INS " });
+    }
+    if self.buffers.len() > 1 {
+      prefix = format!("{prefix}[{}/{}] ", buffer + 1, self.buffers.len());
+    }
+    let room = width.saturating_sub(prefix.width() + status.width() + line_indicator.width() + 1);
+    status = format!("{prefix}{}{status}", shorten_path(&document.display_path(), room));
 
-    let len = status.len() + line_indicator.len();
+    // narrow panes drop the end of the file info, not the position
+    truncate_columns(&mut status, width.saturating_sub(line_indicator.width() + 1));
+    let len = status.width() + line_indicator.width();
     
     if width > len {
       status.push_str(&" ".repeat(width - len));
     }
 
-    status = format!("{}{}", status, line_indicator);
+    status = format!("{status}{line_indicator}");
 
-    status.truncate(width);
-    
-    self.terminal.set_colors(Colors::new(STATUS_BAR_FG, STATUS_BAR_BG))?;
-    
-    let x = 0;
-    let y = self.terminal.size().height.saturating_sub(2);
-
-    self.terminal.move_cursor(x, y)?;    
-    self.terminal.print_string(&status)?;
-    self.terminal.reset_colors()?;
-    Ok(())
+    truncate_columns(&mut status, width);
+    status
   }
 
   fn search(&mut self) {
-    let old_position = self.cursor_position.clone();
+    self.buffer_mut().document.load_all();
+    let old_position = self.buffer().cursor_position.clone();
     let mut search_dir = SearchDir::Forward;
     
     let query = self
-      .prompt("Search: ", |editor, key_event, query| {
+      .prompt(&self.search_options.label(), |editor, key_event, query| {
         let mut moved = false;
 
+        match key_event {
+          KeyEvent{modifiers: KeyModifiers::ALT, code: KeyCode::Char('c'), ..} =>
+            editor.search_options.case_sensitive = !editor.search_options.case_sensitive,
+          KeyEvent{modifiers: KeyModifiers::ALT, code: KeyCode::Char('w'), ..} =>
+            editor.search_options.whole_word = !editor.search_options.whole_word,
+          KeyEvent{modifiers: KeyModifiers::ALT, code: KeyCode::Char('r'), ..} if cfg!(feature = "regex") =>
+            editor.search_options.regex = !editor.search_options.regex,
+          KeyEvent{code: KeyCode::Enter | KeyCode::Esc, ..} => return Ok(()),
+          _ => {},
+        }
+        // the prompt only knows the label it was opened with
+        editor.status_message = StatusMessage::from(format!("{}{query}", editor.search_options.label()));
+
         match key_event.code {
           KeyCode::Right | KeyCode::Down => {
             search_dir = SearchDir::Forward;
-            editor.process_move(KeyCode::Right)?;
+            editor.process_move(KeyCode::Right);
             moved = true;
           },
           KeyCode::Up | KeyCode::Left => search_dir = SearchDir::Backward,
           _ => search_dir = SearchDir::Forward,
         }  
 
-        if let Some(position) = editor.document.find(&query[..], &editor.cursor_position, search_dir) {
-          editor.cursor_position = position;
+        let mut notes = Vec::new();
+        let mut found = editor.find(query, &editor.buffer().cursor_position, search_dir);
+        if matches!(found, Ok(None)) && editor.config.wrap_search {
+          // go on from the other end of the document
+          let from = match search_dir {
+            SearchDir::Forward => Position::default(),
+            SearchDir::Backward => {
+              let y = editor.buffer().document.rows_size().saturating_sub(1);
+              Position { x: editor.buffer().document.row(y).map_or(0, Row::size), y }
+            },
+          };
+          found = editor.find(query, &from, search_dir);
+          if matches!(found, Ok(Some(_))) {
+            let end = if search_dir == SearchDir::Forward { "bottom" } else { "top" };
+            notes.push(format!("passed the {end}, continuing"));
+          }
+        }
+        let found = match found {
+          Ok(found) => found,
+          Err(err) => {
+            notes.push(err);
+            None
+          },
+        };
+        if let Some((position, _)) = found {
+          editor.buffer_mut().cursor_position = position;
           editor.scroll();         
+          notes.insert(0, editor.match_count(query));
         } else if moved {
-          editor.process_move(KeyCode::Left)?;
+          editor.process_move(KeyCode::Left);
         }
+        if !notes.is_empty() {
+          editor.status_message = StatusMessage::from(format!(
+            "{}{query} ({})",
+            editor.search_options.label(),
+            notes.join(", "),
+          ));
+        }
+        editor.find_visible(query);
 
         Ok(())
       }).unwrap_or(None); 
+    self.search_matches.clear();
 
     if query.is_none() {      
-      self.status_message = StatusMessage::from("Find aborted".to_string());
-      self.cursor_position = old_position;
+      self.set_status(StatusMessage::from("Find aborted".to_string()));
+      self.buffer_mut().cursor_position = old_position;
       self.scroll();
     }
+    self.last_search = query.filter(|query| !query.is_empty()).or(self.last_search.take());
   }
 
-  fn prompt<C>(&mut self, prompt: &str, mut callback: C) -> Result<Option<String>, Error>
-  where
-    C: FnMut(&mut Self, KeyEvent, &String) -> Result<(), Error>
-  {
-    let mut result = String::new();
-    let mut run_prompt = true;
-    while run_prompt {
-      self.status_message = StatusMessage::from(format!("{}{}", prompt, result));
-      self.refresh_screen()?;
-      
-      if let Some(event) = self.terminal.read_event()? {
-        match event {
-          Event::Key(key_event) => {
-            match key_event {
-              KeyEvent{code: KeyCode::Char('j'), modifiers: KeyModifiers::CONTROL, ..}
-                | KeyEvent{code: KeyCode::Enter, ..} => {
-                self.status_message = StatusMessage::from(String::new());
-                run_prompt = false; 
-              },              
-              _ => match key_event.code {
-                KeyCode::Char(c) => {
-                  result.push(c);
-                },
-                KeyCode::Backspace => {
-                  result.pop();
-                },
-                KeyCode::Esc => {
-                  result.truncate(0);
-                  run_prompt = false;
-                },
-                _ => {}
-              }              
-            }
-            callback(self, key_event, &result)?;
-          },
-          _ => {}          
-        }        
+  // puts a cursor on every match of the last search, the first match at
+  // or after the cursor gets the main one
+  fn cursors_at_matches(&mut self) {
+    let Some(query) = self.last_search.clone() else {
+      self.set_status(StatusMessage::from(format!("Search with {} first", self.keymap.describe(Action::Search))));
+      return;
+    };
+    self.buffer_mut().document.load_all();
+    let mut matches = Vec::new();
+    let mut at = Position::default();
+    while let Ok(Some((position, _))) = self.find(&query, &at, SearchDir::Forward) {
+      at = Position { x: position.x + 1, y: position.y };
+      matches.push(position);
+      if matches.len() == MATCH_COUNT_LIMIT {
+        break;
       }
     }
+    if matches.is_empty() {
+      self.set_status(StatusMessage::from(format!("No matches for {query}")));
+      return;
+    }
 
-    if result.is_empty() {
-      Ok(None)
-    } else {
-      Ok(Some(result))
-    }    
+    self.buffer_mut().history.seal();
+    self.buffer_mut().selection_anchor = None;
+    let cursor = &self.buffer().cursor_position;
+    let main = matches
+      .iter()
+      .position(|found| (found.y, found.x) >= (cursor.y, cursor.x))
+      .unwrap_or(0);
+    self.buffer_mut().cursor_position = matches.remove(main);
+    self.cursors = matches;
+    self.set_status(StatusMessage::from(format!(
+      "{} cursors, {} goes back to one",
+      self.cursors.len() + 1,
+      self.keymap.describe(Action::ClearMark),
+    )));
   }
 
-  fn draw_welcome_message(&mut self) -> Result<(), Error> {
-    let mut message = format!("Slime editor -- version {}", VERSION);
-    let width = self.terminal.size().width;
-    let height = self.terminal.size().height;
-    let len = message.len();
-    let pos_x = width.saturating_sub(len as u16) / 2;
-    let pos_y = height / 2;
-    self.terminal.move_cursor(pos_x, pos_y)?;
-    message.truncate(width as usize);
-    self.terminal.print_string(&message)?;    
-    self.terminal.move_cursor(0, 0)
-  }
+  // position and length in graphemes of the next match from `at`
+  #[cfg_attr(not(feature = "regex"), allow(clippy::unnecessary_wraps))]
+  fn find(&self, query: &str, at: &Position<usize>, direction: SearchDir) -> Result<Option<(Position<usize>, usize)>, String> {
+    #[cfg(feature = "regex")]
+    if self.search_options.regex {
+      let regex = self.search_regex(query)?;
+      return Ok(self.buffer().document.find_regex(&regex, at, direction));
+    }
 
-  fn refresh_screen(&mut self) -> Result<(), Error> {  
-    self.terminal.hide_cursor()?;
-    self.terminal.move_cursor(0, 0)?;
+    let len = query.graphemes(true).count();
+    Ok(self.buffer().document
+      .find(query, at, direction, self.search_options)
+      .map(|position| (position, len)))
+  }
+  #[cfg(feature = "regex")]
+  fn search_regex(&self, query: &str) -> Result<regex::Regex, String> {
+    let mut pattern = query.to_string();
+    if self.search_options.whole_word {
+      pattern = format!(r"\b(?:{pattern})\b");
+    }
+    regex::RegexBuilder::new(&pattern)
+      .case_insensitive(!self.search_options.case_sensitive)
+      .build()
+      .map_err(|_| String::from("invalid regex"))
+  }
 
-    if self.should_quit {            
-      self.terminal.clear_screen()?;      
+  // `match 3 of 17` for the match under the cursor, counting stops at
+  // `MATCH_COUNT_LIMIT` matches
+  fn match_count(&self, query: &str) -> String {
+    let (total, before) = self.count_matches(query);
+    if before >= MATCH_COUNT_LIMIT {
+      format!("{MATCH_COUNT_LIMIT}+ matches")
+    } else if total >= MATCH_COUNT_LIMIT {
+      format!("match {} of {MATCH_COUNT_LIMIT}+", before + 1)
     } else {
-      self.draw_rows()?;      
-      self.draw_status_bar()?;
-      self.draw_message_bar()?;
-      self.terminal.move_cursor(
-        self.cursor_position.x.saturating_sub(self.cursor_offset.x) as u16, 
-        self.cursor_position.y.saturating_sub(self.cursor_offset.y) as u16)?;
-
-      if self.document.is_empty() {
-        self.draw_welcome_message()?;
-      } 
-    }           
+      format!("match {} of {total}", before + 1)
+    }
+  }
 
-    self.terminal.show_cursor()?;
+  // all matches of `query` and those before the cursor, both up to
+  // `MATCH_COUNT_LIMIT`
+  fn count_matches(&self, query: &str) -> (usize, usize) {
+    let at = &self.buffer().cursor_position;
+    #[cfg(feature = "regex")]
+    if self.search_options.regex {
+      let Ok(regex) = self.search_regex(query) else {
+        return (0, 0);
+      };
+      return (
+        self.buffer().document.count_regex_matches(&regex, MATCH_COUNT_LIMIT),
+        self.buffer().document.count_regex_matches_before(&regex, at, MATCH_COUNT_LIMIT),
+      );
+    }
 
-    Ok(())
+    let options = self.search_options;
+    (
+      self.buffer().document.count_matches(query, options, MATCH_COUNT_LIMIT),
+      self.buffer().document.count_matches_before(query, options, at, MATCH_COUNT_LIMIT),
+    )
   }
 
-  fn process_event(&mut self, event: Event) -> Result<(), Error> {  
-    match event {
-      Event::Key(event) => {
-        self.process_keyboard(event)?
-      },
-      Event::Resize(new_cols, new_rows) => {
-        self.terminal.resize(new_cols, new_rows);        
-
-        self.refresh_screen()?
+  // collects the matches on the visible rows so they can be highlighted
+  fn find_visible(&mut self, query: &str) {
+    self.search_matches.clear();
+    if query.is_empty() {
+      return;
+    }
+    let text_height = self.terminal.size().height.saturating_sub(2) as usize;
+    let last_row = self.buffer().cursor_offset.y + text_height;
+    let mut at = Position { x: 0, y: self.buffer().cursor_offset.y };
+    while let Ok(Some((position, len))) = self.find(query, &at, SearchDir::Forward) {
+      if position.y >= last_row {
+        break;
       }
-      _ => {}
+      at = Position { x: position.x + 1, y: position.y };
+      self.search_matches.push((position, len));
     }
+  }
 
-    Ok(())
+  // columns of the search matches on the row, the one under the cursor
+  // in its own color
+  fn match_columns(&self, row_index: usize) -> Vec<(Range<usize>, Color)> {
+    self.search_matches
+      .iter()
+      .filter(|(position, _)| position.y == row_index)
+      .map(|(position, len)| {
+        let color = if *position == self.buffer().cursor_position { CURRENT_MATCH_BG } else { MATCH_BG };
+        (position.x..position.x + len, color)
+      })
+      .collect()
   }
 
-  fn save(&mut self) {
-    if self.document.path.is_none() {
-      let file_name = self.prompt("Save as: ", |_, _, _| { Ok(()) }).unwrap_or(None);
-      if file_name.is_none() {
-        self.status_message = StatusMessage::from("Save aborted".to_string());
+  fn replace(&mut self) {
+    if !self.check_writable() {
+      return;
+    }
+    self.buffer_mut().document.load_all();
+    let Some(query) = self.prompt("Replace: ", |_, _, _| Ok(())).unwrap_or(None) else {
+      self.set_status(StatusMessage::from("Replace aborted".to_string()));
+      return;
+    };
+    // Enter on an empty prompt replaces with nothing, only Esc aborts
+    let mut entered = false;
+    let replacement = self.prompt(&format!("Replace {query} with: "), |_, key, _| {
+      entered = is_enter(&key);
+      Ok(())
+    }).unwrap_or(None);
+    let replacement = match replacement {
+      Some(replacement) => replacement,
+      None if entered => String::new(),
+      None => {
+        self.set_status(StatusMessage::from("Replace aborted".to_string()));
         return;
-      } else {
-        self.document.path = Some(file_name.unwrap());
+      },
+    };
+    let query_len = query.graphemes(true).count();
+    let replacement_len = replacement.graphemes(true).count();
+
+    let mut at = self.buffer().cursor_position.clone();
+    let mut replace_all = false;
+    let mut replaced = 0;
+    self.buffer_mut().history.begin_group();
+    while let Some(found) = self.buffer().document.find(&query, &at, SearchDir::Forward, self.search_options) {
+      self.buffer_mut().cursor_position = found.clone();
+      self.scroll();
+
+      if !replace_all {
+        self.buffer_mut().selection_anchor = Some(Position { x: found.x + query_len, y: found.y });
+        self.buffer_mut().block_selection = false;
+        match self.confirm("Replace this match? (y/n/a/q)").unwrap_or(None) {
+          Some('y') => {},
+          Some('a') => replace_all = true,
+          Some('n') => {
+            at = Position { x: found.x + 1, y: found.y };
+            continue;
+          },
+          _ => break,
+        }
       }
+
+      let removed = self.rows_text(found.y, found.y);
+      self.buffer_mut().document.replace(&found, query_len, &replacement);
+      let inserted = self.rows_text(found.y, found.y);
+      self.buffer_mut().history.record(Operation::ReplaceRows { at: found.clone(), removed, inserted });
+      replaced += 1;
+      // continue after the replacement so it never matches itself
+      at = Position { x: found.x + replacement_len, y: found.y };
     }
-    if self.document.save_to_disk().is_ok() {
-      self.status_message = StatusMessage::from("File saved".to_string());
+    self.buffer_mut().history.end_group();
+    self.buffer_mut().selection_anchor = None;
+    self.set_status(StatusMessage::from(format!("Replaced {replaced} occurrence(s)")));
+  }
+
+  // reads a command like `set tabwidth=2 nonumber` and runs it
+  fn run_command(&mut self) {
+    let Some(input) = self.prompt(":", |_, _, _| Ok(())).unwrap_or(None) else {
+      return;
+    };
+    let mut words = input.split_whitespace();
+    let result = match words.next() {
+      Some("set" | "se") => {
+        let result = words.try_for_each(|setting| self.config.set(setting)).map_err(|err| err.to_string());
+        for buffer in &mut self.buffers {
+          buffer.history.set_capacity(self.config.undo_limit);
+        }
+        result
+      },
+      Some(command @ ("uniq" | "squeeze")) => {
+        if self.check_writable() {
+          self.remove_rows(command == "squeeze");
+        }
+        Ok(())
+      },
+      Some(command) => Err(format!("Unknown command {command}")),
+      None => Ok(()),
+    };
+    if let Err(err) = result {
+      self.set_status(StatusMessage::error(format!("ERR: {err}")));
+    }
+    let _ = self.terminal.set_mouse_capture(self.config.mouse);
+    if !self.config.modal {
+      self.mode = EditorMode::Insert;
+    }
+  }
+
+  // moves to the bracket pairing with the one at or after the cursor on
+  // its row
+  fn go_to_matching_bracket(&mut self) {
+    let Some(bracket) = self.buffer().document.bracket_at(&self.buffer().cursor_position) else {
+      self.set_status(StatusMessage::from("No bracket on this line".to_string()));
+      return;
+    };
+    match self.buffer_mut().document.find_matching_bracket(&bracket) {
+      Some(position) => {
+        self.buffer_mut().history.seal();
+        self.buffer_mut().cursor_position = position;
+        self.scroll();
+      },
+      None => self.set_status(StatusMessage::from("No matching bracket".to_string())),
+    }
+  }
+
+  fn go_to_line(&mut self) {
+    let Some(input) = self.prompt("Go to line: ", |_, _, _| Ok(())).unwrap_or(None) else {
+      return;
+    };
+    // accepts `line` or `line:column`, both 1-based
+    let mut parts = input.trim().splitn(2, ':');
+    let line = parts.next().and_then(|line| line.parse::<usize>().ok());
+    let column = parts.next().map(|column| column.parse::<usize>().ok());
+    self.buffer_mut().document.load_all();
+
+    match (line, column) {
+      (Some(line), None | Some(Some(_))) if line >= 1 && line <= self.buffer().document.rows_size() => {
+        let y = line - 1;
+        let row_size = self.buffer().document.row(y).map_or(0, Row::size);
+        let x = column.flatten().map_or(0, |column| column.saturating_sub(1).min(row_size));
+        self.buffer_mut().cursor_position = Position { x, y };
+        self.scroll();
+      },
+      _ => self.set_status(StatusMessage::from("Invalid line number".to_string())),
+    }
+  }
+
+  // takes the waiting messages and an error or warning still shown out of
+  // the way of a prompt, which can't wait behind them; `restore_status`
+  // puts them back
+  fn suspend_status(&mut self, prompt: String) -> (Option<StatusMessage>, VecDeque<StatusMessage>) {
+    let shown = mem::replace(&mut self.status_message, StatusMessage::from(prompt));
+    let important = shown.severity > Severity::Info && shown.time.elapsed() < shown.live_time();
+    (important.then_some(shown), mem::take(&mut self.status_queue))
+  }
+
+  // shows the message a prompt interrupted again, messages set since then
+  // wait behind the ones that were waiting before
+  fn restore_status(&mut self, (shown, queue): (Option<StatusMessage>, VecDeque<StatusMessage>)) {
+    let set_since = mem::replace(&mut self.status_queue, queue);
+    if let Some(mut shown) = shown {
+      shown.time = Instant::now();
+      self.status_message = shown;
+    }
+    self.status_queue.extend(set_since);
+    self.advance_status();
+  }
+
+  fn confirm(&mut self, question: &str) -> Result<Option<char>, Error> {
+    let suspended = self.suspend_status(question.to_string());
+    let answer = self.read_answer();
+    self.restore_status(suspended);
+    answer
+  }
+
+  fn read_answer(&mut self) -> Result<Option<char>, Error> {
+    self.refresh_screen()?;
+    loop {
+      match self.read_event()? {
+        Some(Event::Key(key_event)) => return Ok(match key_event.code {
+          KeyCode::Char(c) => Some(c.to_ascii_lowercase()),
+          _ => None,
+        }),
+        Some(Event::Resize(..)) => self.refresh_screen()?,
+        _ => {},
+      }
+    }
+  }
+
+  fn prompt<C>(&mut self, prompt: &str, callback: C) -> Result<Option<String>, Error>
+  where
+    C: FnMut(&mut Self, KeyEvent, &String) -> Result<(), Error>
+  {
+    self.prompt_completing(prompt, |_| Vec::new(), callback)
+  }
+
+  // asks for a file name, Tab completes it
+  fn prompt_path(&mut self, prompt: &str) -> Option<String> {
+    self.prompt_completing(prompt, complete_path, |_, _, _| Ok(())).unwrap_or(None)
+  }
+
+  // a prompt where Tab goes through what `complete` offers for the input,
+  // a single offer is taken as the new input so Tab can go on from it
+  fn prompt_completing<C>(&mut self, prompt: &str, complete: fn(&str) -> Vec<String>, callback: C) -> Result<Option<String>, Error>
+  where
+    C: FnMut(&mut Self, KeyEvent, &String) -> Result<(), Error>
+  {
+    let suspended = self.suspend_status(prompt.to_string());
+    let input = self.read_input(prompt, complete, callback);
+    self.restore_status(suspended);
+    input
+  }
+
+  fn read_input<C>(&mut self, prompt: &str, complete: fn(&str) -> Vec<String>, mut callback: C) -> Result<Option<String>, Error>
+  where
+    C: FnMut(&mut Self, KeyEvent, &String) -> Result<(), Error>
+  {
+    let mut result = String::new();
+    let mut run_prompt = true;
+    let mut completions: Vec<String> = Vec::new();
+    let mut completion_index = 0;
+    while run_prompt {
+      self.refresh_screen()?;
+      
+      if let Some(Event::Key(key_event)) = self.read_event()? {
+        if key_event.code != KeyCode::Tab {
+          completions.clear();
+        }
+        match key_event {
+          KeyEvent{code: KeyCode::Char('j'), modifiers: KeyModifiers::CONTROL, ..}
+            | KeyEvent{code: KeyCode::Enter, ..} => {
+            self.status_message = StatusMessage::from(String::new());
+            run_prompt = false; 
+          },              
+          _ => match key_event.code {
+            KeyCode::Char(c) if !key_event.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+              result.push(c);
+            },
+            KeyCode::Backspace => {
+              result.pop();
+            },
+            KeyCode::Tab => {
+              if completions.is_empty() {
+                completions = complete(&result);
+                completion_index = 0;
+              } else {
+                completion_index = (completion_index + 1) % completions.len();
+              }
+              if let Some(completion) = completions.get(completion_index) {
+                result.clone_from(completion);
+              }
+              if completions.len() == 1 {
+                completions.clear();
+              }
+            },
+            KeyCode::Esc => {
+              result.truncate(0);
+              run_prompt = false;
+            },
+            _ => {}
+          }              
+        }
+        if run_prompt {
+          self.status_message = StatusMessage::from(format!("{prompt}{result}"));
+        }
+        callback(self, key_event, &result)?;
+      }
+    }
+
+    if result.is_empty() {
+      Ok(None)
+    } else {
+      Ok(Some(result))
+    }    
+  }
+
+  fn draw_welcome_message(&mut self) -> Result<(), Error> {
+    let mut message = format!("Slime editor -- version {VERSION}");
+    let width = self.terminal.size().width;
+    let height = self.terminal.size().height;
+    let len = message.len();
+    let pos_x = width.saturating_sub(u16::try_from(len).unwrap_or(u16::MAX)) / 2;
+    let pos_y = height / 2;
+    self.terminal.move_cursor(pos_x, pos_y)?;
+    message.truncate(width as usize);
+    self.terminal.print_string(&message)?;    
+    // the row under the message isn't what `draw_rows` drew there, so it is
+    // drawn again next frame, without the message once text is typed
+    if let Some(drawn) = self.drawn_rows.get_mut(pos_y as usize) {
+      *drawn = None;
+    }
+    self.terminal.move_cursor(0, 0)
+  }
+
+  fn refresh_screen(&mut self) -> Result<(), Error> {  
+    self.terminal.hide_cursor()?;
+    self.terminal.move_cursor(0, 0)?;
+
+    if self.should_quit {            
+      self.terminal.clear_screen()?;      
+    } else {
+      self.apply_resize();
+      self.advance_status();
+      self.check_disk();
+      // rows up to a page past the view, so a page down lands on loaded rows
+      let text_height = self.terminal.size().height as usize;
+      let buffer = self.buffer_mut();
+      buffer.document.load_rows(buffer.cursor_offset.y + 2 * text_height);
+      self.draw_rows()?;      
+      self.draw_status_bar()?;
+      self.draw_message_bar()?;
+      let (column, line) = if self.config.wrap {
+        let (segment, column) = self.cursor_segment();
+        let above: usize = (self.buffer().cursor_offset.y..self.buffer().cursor_position.y).map(|y| self.screen_lines(y)).sum();
+        (column, above + segment)
+      } else {
+        (
+          self.cursor_rx().saturating_sub(self.buffer().cursor_offset.x),
+          self.buffer().cursor_position.y.saturating_sub(self.buffer().cursor_offset.y),
+        )
+      };
+      let cursor_x = self.pane_columns(self.active_pane).start + column + self.gutter_width();
+      self.terminal.move_cursor(
+        u16::try_from(cursor_x).unwrap_or(u16::MAX), 
+        u16::try_from(line).unwrap_or(u16::MAX))?;
+
+      if self.buffer().document.is_empty() && self.panes.len() == 1 {
+        self.draw_welcome_message()?;
+      } 
+    }           
+
+    self.terminal.show_cursor()?;
+    self.terminal.flush()
+  }
+
+  // the next event, keys of a macro being replayed come first; keys read
+  // from the terminal are added to the macro being recorded, including
+  // those answering prompts
+  fn read_event(&mut self) -> Result<Option<Event>, Error> {
+    if let Some(key) = self.replay.pop_front() {
+      return Ok(Some(Event::Key(key)));
+    }
+    let event = self.terminal.read_event()?;
+    if let (Some(keys), Some(Event::Key(key))) = (&mut self.recording, &event) {
+      keys.push(*key);
+    }
+    // prompts see resizes too, the next frame is drawn at the new size
+    if let Some(Event::Resize(cols, rows)) = event {
+      self.pending_resize = Some((cols, rows));
+    }
+    Ok(event)
+  }
+
+  // returns whether the event may have changed the screen, so pointer
+  // movement and focus changes don't cost a frame
+  fn process_event(&mut self, event: &Event) -> bool {  
+    match event {
+      Event::Key(event) => {
+        self.apply_resize();
+        self.key_pressed_at = Instant::now();
+        self.auto_save_pending = true;
+        self.swap_pending = true;
+        self.process_keyboard(*event);
+      },
+      Event::Mouse(event) if self.config.mouse && event.kind != MouseEventKind::Moved => {
+        // clicks land where the screen shows things
+        self.apply_resize();
+        self.process_mouse(*event);
+      },
+      _ => return false,
+    }
+
+    true
+  }
+
+  // takes on the size of the last resize, done by the next frame or before
+  // the next key or click; a smaller screen scrolls to keep the cursor in
+  // view
+  fn apply_resize(&mut self) {
+    let Some((cols, rows)) = self.pending_resize.take() else {
+      return;
+    };
+    self.terminal.resize(cols, rows);
+    self.drawn_rows.clear();
+    self.clamp_cursor();
+    self.scroll();
+  }
+
+  fn process_mouse(&mut self, event: MouseEvent) {
+    let text_height = self.terminal.size().height.saturating_sub(2) as usize;
+    match event.kind {
+      MouseEventKind::Down(MouseButton::Left) if usize::from(event.row) < text_height => {
+        let column = usize::from(event.column);
+        let Some(pane) = (0..self.panes.len()).find(|&index| self.pane_columns(index).contains(&column)) else {
+          return;
+        };
+        self.focus_pane(pane);
+        self.buffer_mut().history.seal();
+        self.auto_closed.clear();
+        self.cursors.clear();
+        self.buffer_mut().cursor_position = self.position_on_screen(column, usize::from(event.row));
+        // dragging from here selects, a plain click drops the anchor again
+        self.buffer_mut().selection_anchor = Some(self.buffer().cursor_position.clone());
+        self.buffer_mut().block_selection = false;
+      },
+      MouseEventKind::Drag(MouseButton::Left) if self.buffer().selection_anchor.is_some() => {
+        // past the top or bottom of the text the view scrolls a row per move
+        let (row, column) = (usize::from(event.row), usize::from(event.column));
+        self.buffer_mut().cursor_position = if row == 0 {
+          self.position_at(column, self.buffer().cursor_offset.y.saturating_sub(1))
+        } else {
+          self.position_on_screen(column, row.min(text_height))
+        };
+        self.scroll();
+      },
+      MouseEventKind::Up(MouseButton::Left)
+        if self.buffer().selection_anchor.as_ref() == Some(&self.buffer().cursor_position) => self.buffer_mut().selection_anchor = None,
+      MouseEventKind::ScrollUp | MouseEventKind::ScrollDown => {
+        let last_offset = self.buffer().document.rows_size().saturating_sub(text_height);
+        self.buffer_mut().cursor_offset.y = if event.kind == MouseEventKind::ScrollUp {
+          self.buffer().cursor_offset.y.saturating_sub(MOUSE_SCROLL_LINES)
+        } else {
+          (self.buffer().cursor_offset.y + MOUSE_SCROLL_LINES).min(last_offset.max(self.buffer().cursor_offset.y))
+        };
+        // the cursor follows, outside the scroll margins so that the view
+        // stays where it was scrolled to
+        let margin = self.scroll_margin();
+        let first = if self.buffer().cursor_offset.y == 0 { 0 } else { self.buffer().cursor_offset.y + margin };
+        let shown = if self.config.wrap { self.visible_rows() } else { text_height };
+        let mut last = self.buffer().cursor_offset.y + shown.saturating_sub(1);
+        if self.buffer().cursor_offset.y < last_offset {
+          last -= margin;
+        }
+        self.buffer_mut().cursor_position.y = self.buffer().cursor_position.y.clamp(first, last.max(first));
+        self.clamp_cursor();
+      },
+      _ => {},
+    }
+  }
+
+  // the document position shown at a screen column and row of the active
+  // pane, rows below the view continue past it
+  fn position_on_screen(&self, column: usize, screen_row: usize) -> Position<usize> {
+    if !self.config.wrap {
+      return self.position_at(column, self.buffer().cursor_offset.y + screen_row);
+    }
+    let text_column = column
+      .saturating_sub(self.pane_columns(self.active_pane).start)
+      .saturating_sub(self.gutter_width());
+    let last = self.buffer().document.rows_size().saturating_sub(1);
+    let mut remaining = screen_row;
+    let mut y = self.buffer().cursor_offset.y.min(last);
+    let Some(mut row) = self.buffer().document.row(y) else {
+      return Position::default();
+    };
+    let mut segments = wrap_segments(row, self.text_width(), self.config.tab_width);
+    while remaining >= segments.len() && y < last {
+      remaining -= segments.len();
+      y += 1;
+      row = self.buffer().document.row(y).unwrap_or(row);
+      segments = wrap_segments(row, self.text_width(), self.config.tab_width);
+    }
+    let index = remaining.min(segments.len() - 1);
+    Position { x: segment_x(row, &segments, index, text_column, self.config.tab_width), y }
+  }
+
+  // the document position at a screen column of the active pane on row
+  // `y`, past the end of a row is its end
+  fn position_at(&self, column: usize, y: usize) -> Position<usize> {
+    let text_column = column
+      .saturating_sub(self.pane_columns(self.active_pane).start)
+      .saturating_sub(self.gutter_width());
+    let y = y.min(self.buffer().document.rows_size().saturating_sub(1));
+    let x = self.buffer().document.row(y).map_or(0, |row| {
+      row.rx_to_cx(self.buffer().cursor_offset.x + text_column, self.config.tab_width)
+    });
+    Position { x, y }
+  }
+
+  fn save(&mut self) {
+    let mut warning = None;
+    if self.buffer().document.path.is_none() {
+      let file_name = self.prompt_path("Save as: ").map(|input| expand_or_warn(&input)).filter(|(file_name, _)| {
+        // a typo shouldn't replace some other file
+        !Path::new(file_name).exists() || self.confirm(&format!("{file_name} exists, overwrite it? (y/n)")).unwrap_or(None) == Some('y')
+      });
+      if let Some((file_name, expand_warning)) = file_name {
+        warning = expand_warning;
+        let syntax = syntax::for_path(&self.syntaxes, &file_name).cloned();
+        self.buffer_mut().document.set_syntax(syntax);
+        self.buffer_mut().document.path = Some(file_name);
+      } else {
+        self.set_status(StatusMessage::from("Save aborted".to_string()));
+        return;
+      }
+    }
+    let format_error = match self.format() {
+      Err(err) if self.config.format_abort => {
+        self.set_status(StatusMessage::error(format!("ERR: {err}, file not saved")));
+        return;
+      },
+      result => result.err(),
+    };
+    let label = format!("Saving {}", self.buffer().document.path.as_deref().unwrap_or_default());
+    let mut progress = Progress::new(&mut self.terminal, label);
+    let document = &mut self.buffers[self.active].document;
+    match document.save_to_disk(&self.config, |written, total| progress.advance(written, total)) {
+      Ok(()) => {
+        self.buffer_mut().history.mark_saved();
+        self.set_status(match (format_error, warning) {
+          (Some(err), _) => StatusMessage::warning(format!("File saved unformatted, {err}")),
+          (None, Some(warning)) => warning,
+          (None, None) => StatusMessage::from("File saved".to_string()),
+        });
+      },
+      Err(err) => self.set_status(StatusMessage::error(format!("Failed to save file: {err}"))),
+    }
+  }
+
+  // pipes the whole file through the formatter configured for its
+  // extension as one undo step, the cursor stays on its line
+  fn format(&mut self) -> Result<(), String> {
+    let Some(command) = self.buffer().document.path.as_deref()
+      .and_then(|path| Path::new(path).extension())
+      .and_then(|extension| self.config.formatters.get(extension.to_str()?))
+      .cloned()
+    else {
+      return Ok(());
+    };
+    if self.buffer().document.is_read_only() {
+      return Ok(());
+    }
+    self.buffer_mut().document.load_all();
+    let removed = self.rows_text(0, self.buffer().document.rows_size().saturating_sub(1));
+    let mut input = removed.join("\n");
+    input.push('\n');
+
+    let mut progress = Progress::new(&mut self.terminal, format!("Formatting with {command}"));
+    let inserted: Vec<String> = shell::filter(&command, &input, || progress.tick())?
+      .lines()
+      .map(ToString::to_string)
+      .collect();
+    if inserted != removed {
+      self.buffer_mut().document.replace_rows(0, removed.len(), &inserted);
+      let at = Position { x: self.buffer().cursor_position.x, y: 0 };
+      self.buffer_mut().history.record(Operation::ReplaceRows { at, removed, inserted });
+      self.buffer_mut().selection_anchor = None;
+      self.buffer_mut().block_selection = false;
+      self.cursors.clear();
+      self.clamp_cursor();
+    }
+    Ok(())
+  }
+
+  // a scratch buffer without a file, saving it asks for a name; the
+  // other buffers are kept, so nothing needs saving first
+  fn new_buffer(&mut self) {
+    self.buffers.push(Buffer::new(Document::default(), UndoStack::new(self.config.undo_limit)));
+    self.switch_buffer(self.buffers.len() - 1);
+    self.set_status(StatusMessage::from(format!(
+      "New buffer, {} asks for a file name",
+      self.keymap.describe(Action::Save),
+    )));
+  }
+
+  fn open_buffer(&mut self) {
+    let Some(file_name) = self.prompt_path("Open: ") else {
+      self.set_status(StatusMessage::from("Open aborted".to_string()));
+      return;
+    };
+    // a file that isn't there yet is created by saving the buffer
+    let mut progress = Progress::new(&mut self.terminal, format!("Opening {file_name}"));
+    match open_path(&file_name, &self.syntaxes, &self.keymap, |read, total| progress.advance(read, total)) {
+      Ok((document, warning)) => {
+        self.buffers.push(Buffer::new(document, UndoStack::new(self.config.undo_limit)));
+        self.switch_buffer(self.buffers.len() - 1);
+        if let Some(warning) = warning {
+          self.set_status(warning);
+        }
+      },
+      Err(err) => self.set_status(StatusMessage::error(format!("ERR: Could not open file {file_name}: {err}"))),
+    }
+  }
+
+  fn switch_buffer(&mut self, index: usize) {
+    if index == self.active || index >= self.buffers.len() {
+      return;
+    }
+    self.activate_buffer(index);
+    let name = self.buffer().document.path.clone().unwrap_or_else(|| "[No Name]".to_string());
+    self.set_status(StatusMessage::from(format!("Buffer {}/{}: {name}", index + 1, self.buffers.len())));
+  }
+
+  fn activate_buffer(&mut self, index: usize) {
+    self.active = index;
+    self.search_matches.clear();
+  }
+
+  // splits the screen in two panes showing the current buffer, or goes
+  // back to the focused pane alone
+  fn toggle_split(&mut self) {
+    if self.panes.len() > 1 {
+      self.panes = vec![Pane::default()];
+      self.active_pane = 0;
+    } else {
+      self.panes.push(Pane {
+        buffer: self.active,
+        cursor_position: self.buffer().cursor_position.clone(),
+        cursor_offset: self.buffer().cursor_offset.clone(),
+      });
+    }
+    self.scroll();
+  }
+
+  fn focus_pane(&mut self, index: usize) {
+    if index == self.active_pane || index >= self.panes.len() {
+      return;
+    }
+    self.panes[self.active_pane] = Pane {
+      buffer: self.active,
+      cursor_position: self.buffer().cursor_position.clone(),
+      cursor_offset: self.buffer().cursor_offset.clone(),
+    };
+    self.active_pane = index;
+    let pane = self.panes[index].clone();
+    if pane.buffer != self.active {
+      self.activate_buffer(pane.buffer);
+    }
+    self.buffer_mut().cursor_position = pane.cursor_position;
+    self.buffer_mut().cursor_offset = pane.cursor_offset;
+    self.buffer_mut().selection_anchor = None;
+    self.clamp_cursor();
+    self.scroll();
+  }
+
+  // the buffer the cursor is in
+  fn buffer(&self) -> &Buffer {
+    &self.buffers[self.active]
+  }
+
+  fn buffer_mut(&mut self) -> &mut Buffer {
+    &mut self.buffers[self.active]
+  }
+
+  // names of the buffers with unsaved changes
+  fn dirty_buffers(&self) -> Vec<String> {
+    self.buffers
+      .iter()
+      .filter(|buffer| buffer.document.is_dirty())
+      .map(|buffer| buffer.document.path.clone().unwrap_or_else(|| "[No Name]".to_string()))
+      .collect()
+  }
+
+  // looks for external changes at most once per `DISK_CHECK_INTERVAL`
+  // and returns whether one was found
+  fn check_disk(&mut self) -> bool {
+    if self.disk_checked_at.elapsed() < Duration::new(DISK_CHECK_INTERVAL, 0) {
+      return false;
+    }
+    self.disk_checked_at = Instant::now();
+    if !self.buffer_mut().document.changed_on_disk() {
+      return false;
+    }
+    self.set_status(StatusMessage::warning(format!("File changed on disk ({} to reload)", self.keymap.describe(Action::Reload))));
+    true
+  }
+
+  // cleans up after the buffers on a clean quit: their swap files go and
+  // the cursor positions are remembered if asked to
+  fn close_buffers(&mut self) {
+    let mut remembered = Vec::new();
+    for buffer in &mut self.buffers {
+      buffer.document.remove_swap();
+      if let Some(path) = buffer.document.canonical_path() {
+        remembered.push((path.to_string(), buffer.cursor_position.clone()));
+      }
+    }
+    if self.config.remember_cursor {
+      let _ = positions::remember(&remembered);
+    }
+  }
+
+  // saves the modified buffers that have a file name once no key was
+  // pressed for `config.auto_save` seconds, returns whether it tried
+  fn auto_save(&mut self) -> bool {
+    let idle = Duration::from_secs(self.config.auto_save as u64);
+    if self.config.auto_save == 0 || !self.auto_save_pending || self.key_pressed_at.elapsed() < idle {
+      return false;
+    }
+    self.auto_save_pending = false;
+    let mut saved = 0;
+    let mut failed = None;
+    for Buffer { document, history, .. } in &mut self.buffers {
+      if !document.is_dirty() || document.path.is_none() || document.is_read_only() {
+        continue;
+      }
+      match document.save_to_disk(&self.config, |_, _| {}) {
+        Ok(()) => {
+          history.mark_saved();
+          saved += 1;
+        },
+        Err(err) => failed = Some(format!("Auto-save failed for {}: {err}", document.path.as_deref().unwrap_or(""))),
+      }
+    }
+    if let Some(message) = failed {
+      self.set_status(StatusMessage::error(message));
+    } else if saved > 0 {
+      let files = if saved == 1 { "file" } else { "files" };
+      self.set_status(StatusMessage::from(format!("Auto-saved {saved} {files}")));
+    } else {
+      return false;
+    }
+    true
+  }
+
+  // writes the swap files of modified buffers once no key was pressed for
+  // `SWAP_DELAY`, buffers back to their saved state lose theirs; returns
+  // whether writing one failed
+  fn write_swaps(&mut self) -> bool {
+    if !self.swap_pending || self.key_pressed_at.elapsed() < Duration::new(SWAP_DELAY, 0) {
+      return false;
+    }
+    self.swap_pending = false;
+    let mut failed = None;
+    for Buffer { document, cursor_position, .. } in &mut self.buffers {
+      if !document.is_dirty() || !self.config.swap {
+        document.remove_swap();
+      } else if let Err(err) = document.write_swap(cursor_position) {
+        failed = Some(err);
+      }
+    }
+    let Some(err) = failed else {
+      return false;
+    };
+    self.set_status(StatusMessage::error(format!("Could not write swap file: {err}")));
+    true
+  }
+
+  // offers to recover the opened files that have a swap file newer than
+  // themselves, left behind by a crash
+  fn recover_swaps(&mut self) {
+    let first = self.active;
+    for index in 0..self.buffers.len() {
+      let Some(path) = self.buffers[index].document.path.clone() else {
+        continue;
+      };
+      let Some(recovered) = swap::read(&path) else {
+        continue;
+      };
+      self.activate_buffer(index);
+      let question = format!("Found a swap file for {path}: (r)ecover, (d)elete or any other key to leave it");
+      let message = match self.confirm(&question).unwrap_or(None) {
+        Some('r') => {
+          self.buffer_mut().document.recover(&recovered.text);
+          self.buffer_mut().history = UndoStack::new(self.config.undo_limit);
+          self.buffer_mut().history.mark_unsaved();
+          let cursor = recovered.cursor;
+          self.buffer_mut().cursor_position = position_in(&self.buffer().document, Some(cursor.y + 1), Some(cursor.x + 1));
+          self.scroll();
+          format!("Recovered {path}, save to keep the changes")
+        },
+        Some('d') => {
+          swap::remove(&path);
+          format!("Deleted the swap file of {path}")
+        },
+        _ => format!("Left the swap file of {path}"),
+      };
+      self.set_status(StatusMessage::from(message));
+    }
+    self.activate_buffer(first);
+  }
+
+  // re-reads the file as `encoding`, or a guessed one if `None`
+  fn reload(&mut self, encoding: Option<&'static Encoding>) {
+    if self.buffer().document.path.is_none() {
+      self.set_status(StatusMessage::from("Nothing to reload".to_string()));
+      return;
+    }
+    if self.buffer().document.is_dirty() {
+      let answer = self.confirm("File has unsaved changes, discard them and reload? (y/n)").unwrap_or(None);
+      if answer != Some('y') {
+        self.set_status(StatusMessage::from("Reload aborted".to_string()));
+        return;
+      }
+    }
+    if self.buffer_mut().document.reload(encoding).is_err() {
+      self.set_status(StatusMessage::error("Failed to reload file!".to_string()));
+      return;
+    }
+    self.buffer_mut().history = UndoStack::new(self.config.undo_limit);
+    self.buffer_mut().selection_anchor = None;
+    let buffer = self.buffer_mut();
+    buffer.document.load_rows(buffer.cursor_position.y + 1);
+    self.clamp_cursor();
+    self.scroll();
+    self.set_status(StatusMessage::from(format!("File reloaded as {}", self.buffer().document.encoding().name())));
+  }
+
+  fn reopen_with_encoding(&mut self) {
+    let Some(label) = self.prompt("Reopen with encoding: ", |_, _, _| Ok(())).unwrap_or(None) else {
+      self.set_status(StatusMessage::from("Reopen aborted".to_string()));
+      return;
+    };
+    match Encoding::for_label(label.trim().as_bytes()) {
+      Some(encoding) => self.reload(Some(encoding)),
+      None => self.set_status(StatusMessage::from(format!("Unknown encoding {label}"))),
+    }
+  }
+
+  // tells the user why nothing happens when the document can't be edited
+  fn check_writable(&mut self) -> bool {
+    if self.buffer().document.is_lossy() {
+      self.set_status(StatusMessage::warning(format!(
+        "File could not be decoded, {} to reopen with another encoding",
+        self.keymap.describe(Action::ReopenWithEncoding),
+      )));
+      return false;
+    }
+    if self.buffer().document.is_read_only() {
+      self.set_status(StatusMessage::warning(format!("Buffer is read-only, {} to unlock", self.keymap.describe(Action::ToggleReadOnly))));
+      return false;
+    }
+    true
+  }
+
+  fn toggle_read_only(&mut self) {
+    if self.buffer().document.is_lossy() {
+      self.set_status(StatusMessage::from("File could not be decoded and stays read-only".to_string()));
+      return;
+    }
+    let document = &mut self.buffer_mut().document;
+    document.set_read_only(!document.is_read_only());
+    let state = if self.buffer().document.is_read_only() { "locked" } else { "unlocked" };
+    self.set_status(StatusMessage::from(format!("Buffer {state}")));
+  }
+
+  #[allow(clippy::too_many_lines)]
+  fn process_keyboard(&mut self, event: KeyEvent) {
+    if let Some(count) = self.pending_count {
+      if let KeyCode::Char(digit @ '0'..='9') = event.code {
+        let count = count.saturating_mul(10).saturating_add(digit as usize - '0' as usize).min(REPEAT_LIMIT);
+        self.pending_count = Some(count);
+        self.set_status(StatusMessage::from(format!("Repeat: {count}")));
+        return;
+      }
+    }
+    let count = match self.pending_count.take() {
+      Some(count) => {
+        self.set_status(StatusMessage::from(String::new()));
+        count.max(1)
+      },
+      None => 1,
+    };
+    if self.config.modal && self.mode == EditorMode::Normal && self.process_normal(event, count) {
+      self.scroll();
+      return;
+    }
+    if count > 1 && self.is_repeatable(&event) {
+      self.repeat(count, |editor| editor.process_keyboard(event));
+      self.scroll();
+      return;
+    }
+    let typing = matches!(event.code, KeyCode::Char(_) | KeyCode::Backspace)
+      && !event.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+    if !typing {
+      self.auto_closed.clear();
+    }
+    let action = self.keymap.action(&event);
+    if !matches!(
+      action,
+      Some(Action::MoveUp | Action::MoveDown | Action::PageUp | Action::PageDown | Action::HalfPageUp | Action::HalfPageDown)
+    ) {
+      self.preferred_column = None;
+    }
+    if !self.cursors.is_empty() {
+      let at_every_cursor = match action {
+        Some(
+          Action::Newline | Action::DeleteWordBackward | Action::DeleteWordForward
+            | Action::MoveLeft | Action::MoveRight | Action::MoveUp | Action::MoveDown
+            | Action::LineStart | Action::LineEnd | Action::WordLeft | Action::WordRight
+        ) => true,
+        None => typing || matches!(event.code, KeyCode::Tab | KeyCode::Delete),
+        _ => false,
+      };
+      if at_every_cursor {
+        self.for_each_cursor(|editor| editor.process_keyboard(event));
+        self.scroll();
+        return;
+      }
+      // the others only look at the main cursor or leave the text alone
+      let keeps_cursors = matches!(
+        action,
+        Some(
+          Action::ClearMark | Action::Save | Action::Command | Action::Statistics
+            | Action::CycleLineNumbers | Action::ToggleExpandTabs | Action::ToggleAutoIndent | Action::ToggleOverwrite
+            | Action::ToggleMouse | Action::ToggleWrap | Action::ToggleInvisibles
+            | Action::CenterView | Action::ViewTop | Action::ViewBottom
+            | Action::RepeatCount | Action::StartMacro | Action::StopMacro | Action::PlayMacro
+        )
+      );
+      if !keeps_cursors {
+        self.cursors.clear();
+      }
+    }
+    let jump_from = matches!(
+      action,
+      Some(
+        Action::Search | Action::GoToLine | Action::MatchingBracket | Action::DocumentStart | Action::DocumentEnd
+          | Action::PageUp | Action::PageDown | Action::HalfPageUp | Action::HalfPageDown
+      )
+    ).then(|| self.buffer().cursor_position.clone());
+    match action {
+      Some(Action::Newline) if self.check_writable() => self.insert_newline(),
+      Some(Action::Quit) => {
+        let dirty = self.dirty_buffers();
+        if self.quit_times > 0 && !dirty.is_empty() {          
+          self.set_status(StatusMessage::warning(
+            format!(
+              "WARNING! {} unsaved. Press {} {} more times, or {} to quit anyway.",
+              dirty.join(", "),
+              self.keymap.describe(Action::Quit),
+              self.quit_times,
+              self.keymap.describe(Action::ForceQuit),
+            )));          
+          self.quit_times -= 1;
+          return;
+        }
+        self.should_quit = true;                  
+      },
+      Some(Action::ForceQuit) => self.should_quit = true,
+      Some(Action::ToggleReadOnly) => self.toggle_read_only(),
+      Some(Action::NextBuffer) => self.switch_buffer((self.active + 1) % self.buffers.len()),
+      Some(Action::PreviousBuffer) =>
+        self.switch_buffer((self.active + self.buffers.len() - 1) % self.buffers.len()),
+      Some(Action::ToggleSplit) => self.toggle_split(),
+      Some(Action::NextPane) => self.focus_pane((self.active_pane + 1) % self.panes.len()),
+      Some(Action::Command) => self.run_command(),
+      Some(Action::OpenFile) => self.open_buffer(),
+      Some(Action::NewBuffer) => self.new_buffer(),
+      Some(Action::Save) => self.save(),
+      Some(Action::Search) => self.search(),
+      Some(Action::Replace) => self.replace(),
+      Some(Action::CursorsAtMatches) => self.cursors_at_matches(),
+      Some(Action::Reload) => self.reload(None),
+      Some(Action::ReopenWithEncoding) => self.reopen_with_encoding(),
+      Some(Action::GoToLine) => self.go_to_line(),
+      Some(Action::CycleLineNumbers) => self.config.line_numbers = self.config.line_numbers.next(),
+      Some(Action::ToggleExpandTabs) => {
+        self.config.expand_tabs = !self.config.expand_tabs;
+        let state = if self.config.expand_tabs { "spaces" } else { "tabs" };
+        self.set_status(StatusMessage::from(format!("Indenting with {state}")));
+      },
+      Some(Action::ToggleAutoIndent) => {
+        self.config.auto_indent = !self.config.auto_indent;
+        let state = if self.config.auto_indent { "on" } else { "off" };
+        self.set_status(StatusMessage::from(format!("Auto-indent {state}")));
+      },
+      Some(Action::ToggleInvisibles) => {
+        self.config.show_invisibles = !self.config.show_invisibles;
+        let state = if self.config.show_invisibles { "shown" } else { "hidden" };
+        self.set_status(StatusMessage::from(format!("Whitespace {state}")));
+      },
+      Some(Action::ToggleWrap) => {
+        self.config.wrap = !self.config.wrap;
+        let state = if self.config.wrap { "on" } else { "off" };
+        self.set_status(StatusMessage::from(format!("Line wrapping {state}")));
+      },
+      Some(Action::ToggleOverwrite) => {
+        self.overwrite = !self.overwrite;
+        let state = if self.overwrite { "Overwriting" } else { "Inserting" };
+        self.set_status(StatusMessage::from(format!("{state} typed text")));
+      },
+      Some(Action::ToggleMouse) => {
+        self.config.mouse = !self.config.mouse;
+        let _ = self.terminal.set_mouse_capture(self.config.mouse);
+        let state = if self.config.mouse { "on" } else { "off, the terminal selects text" };
+        self.set_status(StatusMessage::from(format!("Mouse {state}")));
+      },
+      Some(Action::Redo) => self.redo(),
+      Some(Action::Undo) => self.undo(),
+      Some(Action::SetMark) => {
+        self.buffer_mut().history.seal();
+        self.buffer_mut().selection_anchor = Some(self.buffer().cursor_position.clone());
+        self.buffer_mut().block_selection = false;
+        self.set_status(StatusMessage::from("Mark set".to_string()));
+      },
+      Some(Action::SetBlockMark) => {
+        self.buffer_mut().history.seal();
+        self.buffer_mut().selection_anchor = Some(self.buffer().cursor_position.clone());
+        self.buffer_mut().block_selection = true;
+        self.set_status(StatusMessage::from("Block mark set".to_string()));
+      },
+      Some(Action::ClearMark) if self.config.modal && self.mode == EditorMode::Insert => {
+        self.buffer_mut().history.seal();
+        self.mode = EditorMode::Normal;
+      },
+      Some(Action::ClearMark) => {
+        if !self.cursors.is_empty() {
+          self.cursors.clear();
+          self.set_status(StatusMessage::from("Back to one cursor".to_string()));
+        } else if self.buffer_mut().selection_anchor.take().is_some() {
+          self.set_status(StatusMessage::from("Mark unset".to_string()));
+        }
+      },
+      Some(Action::Cut) => self.cut(),
+      Some(Action::ToggleComment) if self.check_writable() => self.toggle_comment(),
+      Some(Action::Filter) if self.check_writable() => self.filter(),
+      Some(Action::InsertDate) if self.check_writable() => self.insert_date(),
+      Some(Action::TransposeCharacters) if self.check_writable() => self.transpose(false),
+      Some(Action::TransposeWords) if self.check_writable() => self.transpose(true),
+      Some(Action::Uppercase) if self.check_writable() => self.change_case(Case::Upper),
+      Some(Action::Lowercase) if self.check_writable() => self.change_case(Case::Lower),
+      Some(Action::ToggleCase) if self.check_writable() => self.change_case(Case::Toggle),
+      Some(Action::Titlecase) if self.check_writable() => self.change_case(Case::Title),
+      Some(Action::RemoveDuplicateLines) if self.check_writable() => self.remove_rows(false),
+      Some(Action::SqueezeBlankLines) if self.check_writable() => self.remove_rows(true),
+      Some(Action::Unindent) if self.check_writable() => self.shift_rows(true),
+      Some(Action::DeleteLine) if self.check_writable() => {
+        self.delete_line();
+      },
+      Some(Action::DuplicateLine) if self.check_writable() => self.duplicate_line(),
+      Some(Action::MoveLineUp) if self.check_writable() => self.move_line(SearchDir::Backward),
+      Some(Action::MoveLineDown) if self.check_writable() => self.move_line(SearchDir::Forward),
+      Some(Action::Copy) => self.copy(),
+      Some(Action::Paste) => self.paste(),
+      Some(Action::DeleteWordBackward) if self.check_writable() => self.delete_word(SearchDir::Backward),
+      Some(Action::DeleteWordForward) if self.check_writable() => self.delete_word(SearchDir::Forward),
+      Some(Action::WordLeft) => {
+        self.buffer_mut().history.seal();
+        self.buffer_mut().cursor_position = self.buffer().document.prev_word(&self.buffer().cursor_position);
+      },
+      Some(Action::WordRight) => {
+        self.buffer_mut().history.seal();
+        self.buffer_mut().cursor_position = self.buffer().document.next_word(&self.buffer().cursor_position);
+      },
+      Some(Action::DocumentEnd) => {
+        self.buffer_mut().history.seal();
+        self.buffer_mut().document.load_all();
+        let last_index = self.buffer().document.rows_size().saturating_sub(1);
+        if let Some(last_row) = self.buffer().document.row(last_index) {
+          self.buffer_mut().cursor_position = Position {
+            x: last_row.size(),
+            y: last_index,
+          }
+        }
+      },
+      Some(Action::MatchingBracket) => self.go_to_matching_bracket(),
+      Some(Action::DocumentStart) => {
+        self.buffer_mut().history.seal();
+        self.buffer_mut().cursor_position = Position {x: 0, y: 0};
+      },
+      Some(Action::Statistics) => self.show_statistics(),
+      Some(Action::RepeatCount) => {
+        self.pending_count = Some(0);
+        self.set_status(StatusMessage::from("Repeat: ".to_string()));
+      },
+      Some(Action::StartMacro) => self.start_macro(),
+      Some(Action::StopMacro) => self.stop_macro(),
+      Some(Action::PlayMacro) => self.play_macro(count),
+      Some(Action::JumpBack) => self.jump(true),
+      Some(Action::JumpForward) => self.jump(false),
+      Some(Action::CenterView) => {
+        let text_height = self.terminal.size().height.saturating_sub(2) as usize;
+        self.scroll_cursor_to(text_height / 2);
+      },
+      Some(Action::ViewTop) => self.scroll_cursor_to(self.scroll_margin()),
+      Some(Action::ViewBottom) => {
+        let text_height = self.terminal.size().height.saturating_sub(2) as usize;
+        self.scroll_cursor_to(text_height.saturating_sub(1 + self.scroll_margin()));
+      },
+      Some(Action::MoveLeft) => self.move_cursor(KeyCode::Left),
+      Some(Action::MoveRight) => self.move_cursor(KeyCode::Right),
+      Some(Action::MoveUp) => self.move_cursor(KeyCode::Up),
+      Some(Action::MoveDown) => self.move_cursor(KeyCode::Down),
+      Some(Action::LineStart) => self.move_cursor(KeyCode::Home),
+      Some(Action::LineEnd) => self.move_cursor(KeyCode::End),
+      Some(Action::PageUp) => self.move_cursor(KeyCode::PageUp),
+      Some(Action::PageDown) => self.move_cursor(KeyCode::PageDown),
+      Some(Action::HalfPageUp | Action::HalfPageDown) => {
+        self.buffer_mut().history.seal();
+        let text_height = self.terminal.size().height.saturating_sub(2) as usize;
+        self.move_page(action == Some(Action::HalfPageDown), (text_height / 2).max(1));
+      },
+      Some(_) => {},
+      None => match event.code {
+        KeyCode::Char(c) if typing && self.block().is_some() && self.check_writable() => {
+          if let Some(block) = self.block() {
+            self.replace_block(&block, c.encode_utf8(&mut [0; 4]));
+          }
+        },
+        KeyCode::Backspace | KeyCode::Delete if self.block().is_some() && self.check_writable() =>
+          self.delete_block(event.code == KeyCode::Backspace),
+        KeyCode::Char(c) if typing && self.overwrite && self.check_writable() => self.overwrite_char(c),
+        KeyCode::Char(c) if typing && self.check_writable() => self.type_char(c),
+        KeyCode::Tab if self.check_writable() => {
+          if self.selection().is_some_and(|(start, end)| start.y != end.y) {
+            self.shift_rows(false);
+          } else {
+            self.insert_tab();
+          }
+        },
+        KeyCode::Backspace
+          if typing && !(self.buffer().cursor_position.x == 0 && self.buffer().cursor_position.y == 0) && self.check_writable() => {                
+          if self.auto_closed.last() == Some(&self.buffer().cursor_position) {
+            self.auto_closed.pop();
+            // the outer closers all follow on the same row
+            for position in &mut self.auto_closed {
+              position.x -= 2;
+            }
+            self.buffer_mut().history.begin_group();
+            self.process_move(KeyCode::Left);
+            self.delete_char();
+            self.delete_char();
+            self.buffer_mut().history.end_group();
+            return;
+          }
+          self.auto_closed.clear();
+          self.process_move(KeyCode::Left);          
+          self.delete_char();
+        },
+        KeyCode::Delete if self.check_writable() => {
+          self.delete_char();
+        },                      
+        _ => {}
+      }
+    }
+
+    if let Some(from) = jump_from.filter(|from| *from != self.buffer().cursor_position) {
+      self.record_jump(from);
+    }
+
+    if self.quit_times < QUIT_TIMES {
+      self.quit_times = QUIT_TIMES;
+      self.set_status(StatusMessage::from(String::new()));
+    }
+
+    self.scroll();
+  }
+
+  // runs `apply` at every cursor, from the last one in the document to the
+  // first; an edit only moves the text after it, so the cursors already
+  // done are kept as distances from the end of the document and of their
+  // rows, which it leaves alone
+  fn for_each_cursor(&mut self, mut apply: impl FnMut(&mut Self)) {
+    let main = self.buffer().cursor_position.clone();
+    let offset = self.buffer().cursor_offset.clone();
+    let mut cursors = mem::take(&mut self.cursors);
+    cursors.push(main.clone());
+    cursors.sort_unstable_by_key(|cursor| Reverse((cursor.y, cursor.x)));
+
+    self.buffer_mut().history.begin_group();
+    let mut done = Vec::with_capacity(cursors.len());
+    for cursor in cursors {
+      let is_main = cursor == main;
+      self.buffer_mut().cursor_position = cursor;
+      self.auto_closed.clear();
+      apply(self);
+      let Position { x, y } = self.buffer().cursor_position;
+      let row_size = self.buffer().document.row(y).map_or(0, Row::size);
+      done.push((self.buffer().document.rows_size().saturating_sub(y), row_size.saturating_sub(x), is_main));
+    }
+    self.buffer_mut().history.end_group();
+    self.auto_closed.clear();
+
+    let rows_size = self.buffer().document.rows_size();
+    let mut cursors: Vec<(Position<usize>, bool)> = done
+      .into_iter()
+      .map(|(from_bottom, from_row_end, is_main)| {
+        let y = rows_size.saturating_sub(from_bottom);
+        let x = self.buffer().document.row(y).map_or(0, Row::size).saturating_sub(from_row_end);
+        (Position { x, y }, is_main)
+      })
+      .collect();
+    // cursors that ran into each other become one, the main one if it is
+    // among them
+    cursors.sort_unstable_by(|(a, a_main), (b, b_main)| (a.y, a.x, b_main).cmp(&(b.y, b.x, a_main)));
+    cursors.dedup_by(|(a, _), (b, _)| a == b);
+    for (cursor, is_main) in cursors {
+      if is_main {
+        self.buffer_mut().cursor_position = cursor;
+      } else {
+        self.cursors.push(cursor);
+      }
+    }
+    self.buffer_mut().cursor_offset = offset;
+  }
+
+  fn start_macro(&mut self) {
+    if let Some(keys) = &mut self.recording {
+      keys.pop();
+      self.set_status(StatusMessage::from("Already recording".to_string()));
+      return;
+    }
+    self.recording = Some(Vec::new());
+    self.set_status(StatusMessage::from(format!(
+      "Recording, {} stops",
+      self.keymap.describe(Action::StopMacro),
+    )));
+  }
+
+  // keeps the keys typed since recording started, without the one that
+  // stopped it
+  fn stop_macro(&mut self) {
+    let Some(mut keys) = self.recording.take() else {
+      self.set_status(StatusMessage::from("Not recording".to_string()));
+      return;
+    };
+    keys.pop();
+    self.set_status(StatusMessage::from(format!(
+      "Recorded {} keys, {} replays them",
+      keys.len(),
+      self.keymap.describe(Action::PlayMacro),
+    )));
+    self.macro_keys = keys;
+  }
+
+  // queues the keys of the macro `count` times; macros can't replay or
+  // record others, so replaying always comes to an end
+  fn play_macro(&mut self, count: usize) {
+    if let Some(keys) = &mut self.recording {
+      keys.pop();
+      self.set_status(StatusMessage::from("Can't replay while recording".to_string()));
+      return;
+    }
+    if self.macro_keys.is_empty() {
+      self.set_status(StatusMessage::from(format!(
+        "No macro recorded, {} starts recording",
+        self.keymap.describe(Action::StartMacro),
+      )));
+      return;
+    }
+    for _ in 0..count {
+      self.replay.extend(self.macro_keys.iter().copied());
+    }
+  }
+
+  // handles a key `count` times in normal mode, digits start a count;
+  // keys with Ctrl or Alt and those that aren't characters are left to the
+  // usual bindings, movement and deleting go through the keys they stand
+  // for, so they work at every cursor like those do
+  fn process_normal(&mut self, event: KeyEvent, count: usize) -> bool {
+    let pending = self.pending_key.take();
+    if event.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) {
+      return false;
+    }
+    // keys that would edit the text move like `j` and `h` or do nothing
+    let c = match event.code {
+      KeyCode::Char(c) => c,
+      KeyCode::Enter => 'j',
+      KeyCode::Backspace => 'h',
+      KeyCode::Delete | KeyCode::Tab | KeyCode::BackTab => return true,
+      _ => return false,
+    };
+    let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+    match c {
+      '1'..='9' => {
+        self.pending_count = Some(c as usize - '0' as usize);
+        self.set_status(StatusMessage::from(format!("Repeat: {c}")));
+      },
+      'h' => self.repeat(count, |editor| editor.process_keyboard(key(KeyCode::Left))),
+      'j' => self.repeat(count, |editor| editor.process_keyboard(key(KeyCode::Down))),
+      'k' => self.repeat(count, |editor| editor.process_keyboard(key(KeyCode::Up))),
+      'l' => self.repeat(count, |editor| editor.process_keyboard(key(KeyCode::Right))),
+      'x' => self.repeat(count, |editor| editor.process_keyboard(key(KeyCode::Delete))),
+      'i' => {
+        self.buffer_mut().history.seal();
+        self.mode = EditorMode::Insert;
+      },
+      'd' if pending == Some('d') => {
+        self.cursors.clear();
+        if self.check_writable() {
+          self.buffer_mut().history.seal();
+          self.repeat(count, |editor| {
+            editor.delete_line();
+          });
+        }
+      },
+      'd' => {
+        // `3dd` has its count before the first `d`
+        self.pending_key = Some('d');
+        self.pending_count = (count > 1).then_some(count);
+      },
+      ':' => self.run_command(),
+      '%' => {
+        let from = self.buffer().cursor_position.clone();
+        self.go_to_matching_bracket();
+        if self.buffer().cursor_position != from {
+          self.record_jump(from);
+        }
+      },
+      _ => {},
+    }
+    true
+  }
+
+  // keys a count repeats, the others drop it
+  fn is_repeatable(&self, event: &KeyEvent) -> bool {
+    match self.keymap.action(event) {
+      Some(
+        Action::Newline | Action::Undo | Action::Redo | Action::Paste | Action::Unindent
+          | Action::DeleteLine | Action::DuplicateLine | Action::MoveLineUp | Action::MoveLineDown
+          | Action::DeleteWordBackward | Action::DeleteWordForward | Action::WordLeft | Action::WordRight
+          | Action::MoveLeft | Action::MoveRight | Action::MoveUp | Action::MoveDown
+          | Action::PageUp | Action::PageDown | Action::HalfPageUp | Action::HalfPageDown
+      ) => true,
+      None => matches!(event.code, KeyCode::Char(_) | KeyCode::Tab | KeyCode::Backspace | KeyCode::Delete),
+      _ => false,
+    }
+  }
+
+  // runs `apply` `count` times as one undo step
+  fn repeat(&mut self, count: usize, mut apply: impl FnMut(&mut Self)) {
+    self.buffer_mut().history.begin_group();
+    for _ in 0..count {
+      apply(self);
+    }
+    self.buffer_mut().history.end_group();
+  }
+
+  // remembers a position left by a long move, dropping the jumps that
+  // were gone back over
+  fn record_jump(&mut self, from: Position<usize>) {
+    self.jumps.truncate(self.jump_index);
+    let jump = (self.active, from);
+    if self.jumps.last() != Some(&jump) {
+      self.jumps.push(jump);
+    }
+    if self.jumps.len() > JUMP_LIMIT {
+      self.jumps.remove(0);
+    }
+    self.jump_index = self.jumps.len();
+  }
+
+  // goes to the previous or the next position of the jump list, the
+  // first step back remembers where it started so it can be returned to
+  fn jump(&mut self, back: bool) {
+    if back && self.jump_index == self.jumps.len() {
+      let here = (self.active, self.buffer().cursor_position.clone());
+      if self.jumps.last() != Some(&here) {
+        self.jumps.push(here);
+      }
+      self.jump_index = self.jumps.len() - 1;
+    }
+    let index = if back { self.jump_index.checked_sub(1) } else { Some(self.jump_index + 1) };
+    let Some((buffer, position)) = index.and_then(|index| self.jumps.get(index)).cloned() else {
+      let end = if back { "oldest" } else { "newest" };
+      self.set_status(StatusMessage::from(format!("Already at the {end} jump")));
+      return;
+    };
+    self.jump_index = index.unwrap_or(0);
+    self.buffer_mut().history.seal();
+    self.buffer_mut().selection_anchor = None;
+    if buffer != self.active {
+      self.activate_buffer(buffer);
+    }
+    self.buffer_mut().cursor_position = position;
+    // rows may have been deleted since
+    self.clamp_cursor();
+  }  
+
+  fn insert_char(&mut self, ch: char) {
+    self.buffer_mut().selection_anchor = None;
+    let at = self.buffer().cursor_position.clone();
+    let rows_size = self.buffer().document.rows_size();
+    if ch == '\n' {
+      if at.y < rows_size {
+        self.buffer_mut().document.insert(&at, ch);
+        self.buffer_mut().history.record(Operation::InsertLine { at });
+      }
+      return;
+    }
+
+    if at.y == rows_size && at.y > 0 {
+      // typing on the line past the end of the document opens a new row,
+      // split it off the last row so that undo removes it again
+      let last_row_end = Position {
+        x: self.buffer().document.row(at.y - 1).map_or(0, Row::size),
+        y: at.y - 1,
+      };
+      self.buffer_mut().history.begin_group();
+      self.buffer_mut().document.insert(&last_row_end, '\n');
+      self.buffer_mut().history.record(Operation::InsertLine { at: last_row_end });
+      self.buffer_mut().document.insert(&at, ch);
+      self.buffer_mut().history.record(Operation::InsertChar { at, ch });
+      self.buffer_mut().history.end_group();
+    } else {
+      self.buffer_mut().document.insert(&at, ch);
+      self.buffer_mut().history.record(Operation::InsertChar { at, ch });
+    }
+  }
+
+  // inserts a typed character, pairing brackets and quotes and typing over
+  // closers that were inserted that way
+  fn type_char(&mut self, c: char) {
+    let at = self.buffer().cursor_position.clone();
+    let row = self.buffer().document.row(at.y);
+    let next = row.and_then(|row| row.grapheme(at.x));
+    if self.auto_closed.last() == Some(&at) && next == Some(c.encode_utf8(&mut [0; 4])) {
+      self.auto_closed.pop();
+      self.buffer_mut().history.seal();
+      self.process_move(KeyCode::Right);
+      return;
+    }
+
+    let prev = at.x.checked_sub(1).and_then(|x| row.and_then(|row| row.grapheme(x)));
+    let closer = closing_pair(c).filter(|_| {
+      let free_after = next.is_none_or(|next| {
+        next.chars().all(char::is_whitespace) || next.chars().any(|ch| ")]}\"'".contains(ch))
+      });
+      // an apostrophe inside a word is not a quote
+      let is_quote = c == '"' || c == '\'';
+      let free_before = !is_quote || prev.is_none_or(|prev| !prev.chars().any(char::is_alphanumeric));
+      self.config.auto_pairs && free_after && free_before
+    });
+
+    let inserted = if closer.is_some() { 2 } else { 1 };
+    for position in &mut self.auto_closed {
+      if position.y == at.y && position.x >= at.x {
+        position.x += inserted;
+      }
+    }
+    let Some(closer) = closer else {
+      self.insert_char(c);
+      self.process_move(KeyCode::Right);
+      return;
+    };
+    self.buffer_mut().history.begin_group();
+    self.insert_char(c);
+    self.process_move(KeyCode::Right);
+    self.insert_char(closer);
+    self.buffer_mut().history.end_group();
+    self.auto_closed.push(self.buffer().cursor_position.clone());
+  }
+
+  // replaces the grapheme under the cursor with a typed character, at the
+  // end of a row it is added like when inserting
+  fn overwrite_char(&mut self, c: char) {
+    let at = self.buffer().cursor_position.clone();
+    let Some(grapheme) = self.buffer().document.row(at.y).and_then(|row| row.grapheme(at.x)).map(ToString::to_string) else {
+      self.insert_char(c);
+      self.process_move(KeyCode::Right);
+      return;
+    };
+    self.buffer_mut().selection_anchor = None;
+    self.buffer_mut().document.overwrite(&at, c);
+    self.buffer_mut().history.begin_group();
+    self.buffer_mut().history.record(Operation::DeleteChar { at: at.clone(), grapheme });
+    self.buffer_mut().history.record(Operation::InsertChar { at, ch: c });
+    self.buffer_mut().history.end_group();
+    self.process_move(KeyCode::Right);
+  }
+
+  fn insert_newline(&mut self) {
+    let at = self.buffer().cursor_position.clone();
+    let Some(row) = self.buffer().document.row(at.y).filter(|_| self.config.auto_indent) else {
+      self.insert_char('\n');
+      self.process_move(KeyCode::Right);
+      return;
+    };
+    let leading = row.string().chars().take_while(|ch| *ch == ' ' || *ch == '\t').count();
+    // whitespace is one grapheme per char, so the counts match columns
+    let indent: String = row.string().chars().take(leading.min(at.x)).collect();
+
+    self.buffer_mut().selection_anchor = None;
+    let removed = self.rows_text(at.y, at.y);
+    let end = self.buffer_mut().document.insert_text(&at, &format!("\n{indent}"));
+    if at.x <= leading {
+      // don't leave a line holding nothing but the copied indentation
+      self.buffer_mut().document.delete_range(&Position { x: 0, y: at.y }, &at);
+    }
+    let inserted = self.rows_text(at.y, end.y);
+    self.buffer_mut().history.record(Operation::ReplaceRows { at, removed, inserted });
+    self.buffer_mut().cursor_position = end;
+  }
+
+  fn insert_tab(&mut self) {
+    if self.config.expand_tabs {
+      self.buffer_mut().history.begin_group();
+      for _ in 0..self.config.tab_width {
+        self.insert_char(' ');
+        self.process_move(KeyCode::Right);
+      }
+      self.buffer_mut().history.end_group();
+    } else {
+      self.insert_char('\t');
+      self.process_move(KeyCode::Right);
+    }
+  }
+
+  // rows touched by an indent: the selected ones, leaving out a last row
+  // the selection only reaches the start of, or the cursor's row
+  fn indent_range(&self) -> Range<usize> {
+    match self.selection() {
+      Some((start, end)) if end.y > start.y && end.x == 0 => start.y..end.y,
+      Some((start, end)) => start.y..end.y + 1,
+      None => self.buffer().cursor_position.y..self.buffer().cursor_position.y + 1,
+    }
+  }
+
+  // indents or unindents the rows in `indent_range` as one undo step, the
+  // cursor and the selection anchor keep to the same text
+  fn shift_rows(&mut self, unindent: bool) {
+    let rows = self.indent_range();
+    let removed = self.rows_text(rows.start, rows.end - 1);
+    let shifts: Vec<isize> = if unindent {
+      let tab_width = self.config.tab_width;
+      self.buffer_mut().document
+        .unindent_rows(rows.clone(), tab_width)
+        .into_iter()
+        .map(|count| -isize::try_from(count).unwrap_or(0))
+        .collect()
+    } else {
+      let indent = if self.config.expand_tabs { " ".repeat(self.config.tab_width) } else { "\t".to_string() };
+      self.buffer_mut().document.indent_rows(rows.clone(), &indent);
+      let width = isize::try_from(indent.len()).unwrap_or(0);
+      vec![width; rows.len()]
+    };
+    if shifts.iter().all(|&shift| shift == 0) {
+      return;
+    }
+    let inserted = self.rows_text(rows.start, rows.end - 1);
+    let at = Position { x: self.buffer().cursor_position.x, y: rows.start };
+    self.buffer_mut().history.record(Operation::ReplaceRows { at, removed, inserted });
+
+    let shift = |position: &mut Position<usize>| {
+      if let Some(&shift) = position.y.checked_sub(rows.start).and_then(|index| shifts.get(index)) {
+        position.x = position.x.saturating_add_signed(shift);
+      }
+    };
+    shift(&mut self.buffer_mut().cursor_position);
+    if let Some(anchor) = &mut self.buffer_mut().selection_anchor {
+      shift(anchor);
+    }
+  }
+
+  // replaces the selected rows, or the whole document, with what a shell
+  // command prints when given them; if it fails the text stays as it was
+  fn filter(&mut self) {
+    let Some(command) = self.prompt("Filter through: ", |_, _, _| Ok(())).unwrap_or(None) else {
+      self.set_status(StatusMessage::from("Filter aborted".to_string()));
+      return;
+    };
+    self.buffer_mut().document.load_all();
+    let rows = if self.selection().is_some() { self.indent_range() } else { 0..self.buffer().document.rows_size() };
+    let removed = self.rows_text(rows.start, rows.end.saturating_sub(1));
+    let mut input = removed.join("\n");
+    input.push('\n');
+
+    let mut progress = Progress::new(&mut self.terminal, format!("Running {command}"));
+    let inserted: Vec<String> = match shell::filter(&command, &input, || progress.tick()) {
+      Ok(output) => output.lines().map(ToString::to_string).collect(),
+      Err(err) => {
+        self.set_status(StatusMessage::error(format!("ERR: {err}")));
+        return;
+      },
+    };
+    self.buffer_mut().selection_anchor = None;
+    if inserted != removed {
+      self.buffer_mut().document.replace_rows(rows.start, removed.len(), &inserted);
+      let at = Position { x: self.buffer().cursor_position.x, y: rows.start };
+      self.buffer_mut().history.record(Operation::ReplaceRows { at, removed, inserted });
+      self.clamp_cursor();
+    }
+    self.set_status(StatusMessage::from(format!("Filtered {} lines through {command}", rows.len())));
+  }
+
+  // removes repeated rows, or all but one blank row of every run of them,
+  // from the selected rows or the whole file as one undo step
+  fn remove_rows(&mut self, blank: bool) {
+    self.buffer_mut().document.load_all();
+    let rows = if self.selection().is_some() { self.indent_range() } else { 0..self.buffer().document.rows_size() };
+    let removed = self.rows_text(rows.start, rows.end.saturating_sub(1));
+    let count = if blank {
+      self.buffer_mut().document.squeeze_blank_rows(rows.clone())
+    } else {
+      self.buffer_mut().document.remove_duplicate_rows(rows.clone())
+    };
+    self.buffer_mut().selection_anchor = None;
+    if count > 0 {
+      let inserted = self.rows_text(rows.start, rows.end - count - 1);
+      let at = Position { x: self.buffer().cursor_position.x, y: rows.start };
+      self.buffer_mut().history.record(Operation::ReplaceRows { at, removed, inserted });
+      self.clamp_cursor();
+    }
+    let kind = if blank { "blank" } else { "duplicate" };
+    let lines = if count == 1 { "line" } else { "lines" };
+    self.set_status(StatusMessage::from(format!("Removed {count} {kind} {lines}")));
+  }
+
+  // inserts the current date and time at the cursor in a format that is
+  // typed, picked from the presets with Tab or the configured one
+  fn insert_date(&mut self) {
+    let mut entered = false;
+    let prompt = format!("Date format [{}], Tab for presets: ", self.config.date_format);
+    let input = self.prompt_completing(&prompt, complete_date_format, |_, key, _| {
+      entered = is_enter(&key);
+      Ok(())
+    }).unwrap_or(None);
+    let format = match input {
+      Some(format) => format,
+      None if entered => self.config.date_format.clone(),
+      None => {
+        self.set_status(StatusMessage::from("Insert date aborted".to_string()));
+        return;
+      },
+    };
+    match date::now(&format) {
+      Ok(text) => {
+        self.buffer_mut().selection_anchor = None;
+        let at = self.buffer().cursor_position.clone();
+        let removed = self.rows_text(at.y, at.y);
+        let end = self.buffer_mut().document.insert_text(&at, &text);
+        let inserted = self.rows_text(at.y, end.y);
+        self.buffer_mut().history.record(Operation::ReplaceRows { at, removed, inserted });
+        self.buffer_mut().cursor_position = end;
+      },
+      Err(err) => self.set_status(StatusMessage::error(format!("ERR: {err}"))),
+    }
+  }
+
+  // comments or uncomments the rows in `indent_range` with the syntax's
+  // line comment marker
+  fn toggle_comment(&mut self) {
+    let Some(marker) = self.buffer().document.syntax().and_then(|syntax| syntax.line_comment.clone()) else {
+      self.set_status(StatusMessage::from("No line comments for this file type".to_string()));
+      return;
+    };
+    let rows = self.indent_range();
+    let removed = self.rows_text(rows.start, rows.end - 1);
+    let changes = self.buffer_mut().document.toggle_comment(rows.clone(), &marker);
+    if changes.iter().all(|&(_, change)| change == 0) {
+      return;
+    }
+    let inserted = self.rows_text(rows.start, rows.end - 1);
+    let at = Position { x: self.buffer().cursor_position.x, y: rows.start };
+    self.buffer_mut().history.record(Operation::ReplaceRows { at, removed, inserted });
+
+    // text after the marker moves, positions inside a removed one go to
+    // where it started
+    let shift = |position: &mut Position<usize>| {
+      if let Some(&(column, change)) = position.y.checked_sub(rows.start).and_then(|index| changes.get(index)) {
+        if position.x >= column {
+          position.x = position.x.saturating_add_signed(change).max(column);
+        }
+      }
+    };
+    shift(&mut self.buffer_mut().cursor_position);
+    if let Some(anchor) = &mut self.buffer_mut().selection_anchor {
+      shift(anchor);
+    }
+  }
+
+  // replaces the block with `text` on each of its rows as one undo step,
+  // rows ending left of the block are padded with spaces unless nothing
+  // is inserted; the block is left as a column right after the text
+  fn replace_block(&mut self, block: &Block, text: &str) {
+    let tab_width = self.config.tab_width;
+    let rows = block.top_left.y..(block.bottom_right.y + 1).min(self.buffer().document.rows_size());
+    if rows.is_empty() {
+      return;
+    }
+    let removed = self.rows_text(rows.start, rows.end - 1);
+    for y in rows.clone() {
+      let columns = self.block_columns(block, y);
+      let padding = self.buffer().document.row(y).map_or(0, |row| block.top_left.x.saturating_sub(row.width(tab_width)));
+      let replacement = if text.is_empty() { String::new() } else { format!("{}{text}", " ".repeat(padding)) };
+      self.buffer_mut().document.replace(&Position { x: columns.start, y }, columns.len(), &replacement);
+    }
+    let inserted = self.rows_text(rows.start, rows.end - 1);
+    if inserted != removed {
+      let at = Position { x: self.buffer().cursor_position.x, y: rows.start };
+      self.buffer_mut().history.record(Operation::ReplaceRows { at, removed, inserted });
+    }
+
+    let column = block.top_left.x + text.width();
+    let Buffer { document, cursor_position, selection_anchor, .. } = self.buffer_mut();
+    let place = |position: &mut Position<usize>| {
+      position.x = document.row(position.y).map_or(0, |row| row.rx_to_cx(column, tab_width));
+    };
+    place(cursor_position);
+    if let Some(anchor) = selection_anchor {
+      place(anchor);
+    }
+  }
+
+  // removes the block, or the column before or after a block as narrow
+  // as the cursor
+  fn delete_block(&mut self, backward: bool) {
+    let Some(mut block) = self.block() else {
+      return;
+    };
+    if block.top_left.x == block.bottom_right.x {
+      if backward {
+        let Some(left) = block.top_left.x.checked_sub(1) else {
+          return;
+        };
+        block.top_left.x = left;
+      } else {
+        block.bottom_right.x += 1;
+      }
+    }
+    self.replace_block(&block, "");
+  }
+
+  // the rows of the block one per line
+  fn block_text(&self, block: &Block) -> String {
+    (block.top_left.y..=block.bottom_right.y)
+      .filter(|&y| y < self.buffer().document.rows_size())
+      .map(|y| {
+        let columns = self.block_columns(block, y);
+        self.buffer().document.text_range(&Position { x: columns.start, y }, &Position { x: columns.end, y })
+      })
+      .collect::<Vec<String>>()
+      .join("\n")
+  }
+
+  fn delete_char(&mut self) {
+    self.buffer_mut().selection_anchor = None;
+    let at = self.buffer().cursor_position.clone();
+    let Some(row) = self.buffer().document.row(at.y) else {
+      return;
+    };
+    let op = if let Some(grapheme) = row.grapheme(at.x) {
+      Operation::DeleteChar { at: at.clone(), grapheme: grapheme.to_string() }
+    } else if at.y + 1 < self.buffer().document.rows_size() {
+      Operation::JoinLines { at: at.clone() }
     } else {
-      self.status_message = StatusMessage::from("Failed to save file!".to_string());
+      return;
+    };
+    self.buffer_mut().document.delete(&at);
+    self.buffer_mut().history.record(op);
+  }
+
+  fn cut(&mut self) {
+    if !self.check_writable() {
+      return;
+    }
+    if let Some(block) = self.block() {
+      let text = self.block_text(&block);
+      self.replace_block(&block, "");
+      self.buffer_mut().selection_anchor = None;
+      self.set_clipboard(text);
+      return;
+    }
+    if let Some((start, end)) = self.selection() {
+      let text = self.buffer().document.text_range(&start, &end);
+      self.delete_range(start, &end);
+      self.buffer_mut().selection_anchor = None;
+      self.set_clipboard(text);
+      return;
+    }
+    if let Some(row) = self.delete_line() {
+      self.set_clipboard(format!("{}\n", row.string()));
     }
   }
 
-  fn process_keyboard(&mut self, event: KeyEvent) -> Result<(), Error> {
-    match event {
-      // KP_ENTER
-      KeyEvent{modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('j'), ..}
-        | KeyEvent{code: KeyCode::Enter, ..} => {
-          self.document.insert(&self.cursor_position, '\n');
-          self.process_move(KeyCode::Right)?;
-      },
-      // Ctrl-C
-      KeyEvent{modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('c'), ..} => {
-        if self.quit_times > 0 && self.document.is_dirty() {          
-          self.status_message = StatusMessage::from(
-            format!(
-              "WARNING! File has unsaved changes. Press Ctrl-C {} more times to quit.",
-              self.quit_times
-            ));          
-          self.quit_times -= 1;
-          return Ok(());
-        }
-        self.should_quit = true;                  
-      },
-      // Ctrl-S
-      KeyEvent{modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('s'), ..} => self.save(),
-      // Ctrl-F
-      KeyEvent{modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('f'), ..} => self.search(),
-      // Ctrl-END
-      KeyEvent{modifiers: KeyModifiers::CONTROL, code: KeyCode::End, ..} => {
-        let last_index = self.document.rows_size().saturating_sub(1);
-        if let Some(last_row) = self.document.row(last_index) {
-          self.cursor_position = Position {
-            x: last_row.size(),
-            y: last_index,
-          }
-        }
-      },
-      // Ctrl-HOME
-      KeyEvent{modifiers: KeyModifiers::CONTROL, code: KeyCode::Home, ..} => {
-        self.cursor_position = Position {x: 0, y: 0};
-      },
-      _ => match event.code {
-        KeyCode::Char(c) => {          
-          self.document.insert(&self.cursor_position, c);
-          self.process_move(KeyCode::Right)?;                  
-        },               
-        KeyCode::Backspace => {                
-          if !(self.cursor_position.x == 0 && self.cursor_position.y == 0) {
-            self.process_move(KeyCode::Left)?;          
-            self.document.delete(&self.cursor_position);
-          }
-        },
-        KeyCode::Delete => {
-          self.document.delete(&self.cursor_position);        
-        },                      
-        KeyCode::Up
-          | KeyCode::Down
-          | KeyCode::Left 
-          | KeyCode::Right
-          | KeyCode::Home
-          | KeyCode::End
-          | KeyCode::PageDown
-          | KeyCode::PageUp => 
-          self.process_move(event.code)?,      
-        _ => {}
+  fn delete_line(&mut self) -> Option<Row> {
+    let at = self.buffer().cursor_position.clone();
+    let row = self.buffer_mut().document.delete_row(at.y)?;
+    self.buffer_mut().history.record(Operation::ReplaceRows {
+      at,
+      removed: vec![row.string().to_string()],
+      inserted: Vec::new(),
+    });
+    self.buffer_mut().cursor_position.x = 0;
+    self.clamp_cursor();
+    Some(row)
+  }
+
+  fn duplicate_line(&mut self) {
+    let at = self.buffer().cursor_position.clone();
+    let removed = self.rows_text(at.y, at.y);
+    if self.buffer_mut().document.duplicate_row(at.y) {
+      let inserted = self.rows_text(at.y, at.y + 1);
+      self.buffer_mut().history.record(Operation::ReplaceRows { at, removed, inserted });
+      self.buffer_mut().cursor_position.y += 1;
+    }
+  }
+
+  // swaps the cursor's row with the one above or below, the cursor moves
+  // along with it
+  fn move_line(&mut self, direction: SearchDir) {
+    let Position { x, y } = self.buffer().cursor_position;
+    let (first, moved) = match direction {
+      SearchDir::Backward if y > 0 => (y - 1, self.buffer_mut().document.move_row_up(y)),
+      SearchDir::Forward => (y, self.buffer_mut().document.move_row_down(y)),
+      SearchDir::Backward => return,
+    };
+    if !moved {
+      return;
+    }
+    let mut removed = self.rows_text(first, first + 1);
+    removed.reverse();
+    let inserted = self.rows_text(first, first + 1);
+    self.buffer_mut().history.record(Operation::ReplaceRows { at: Position { x, y: first }, removed, inserted });
+    self.buffer_mut().cursor_position.y = if first == y { y + 1 } else { first };
+  }
+
+  // swaps the graphemes or words around the cursor as one undo step, the
+  // cursor goes after them
+  fn transpose(&mut self, words: bool) {
+    self.buffer_mut().selection_anchor = None;
+    let at = self.buffer().cursor_position.clone();
+    let removed = self.rows_text(at.y, at.y);
+    if let Some(x) = self.buffer_mut().document.transpose(&at, words) {
+      let inserted = self.rows_text(at.y, at.y);
+      self.buffer_mut().history.record(Operation::ReplaceRows { at, removed, inserted });
+      self.buffer_mut().cursor_position.x = x;
+    }
+  }
+
+  // changes the case of the selection, or of the word at the cursor
+  // without one, as one undo step; the selection stays on the same text
+  fn change_case(&mut self, case: Case) {
+    if let Some(block) = self.block() {
+      let bottom = block.bottom_right.y.min(self.buffer().document.rows_size().saturating_sub(1));
+      let removed = self.rows_text(block.top_left.y, bottom);
+      for y in block.top_left.y..=bottom {
+        let columns = self.block_columns(&block, y);
+        self.buffer_mut().document.map_case(&Position { x: columns.start, y }, &Position { x: columns.end, y }, case);
       }
+      let inserted = self.rows_text(block.top_left.y, bottom);
+      if inserted != removed {
+        let at = Position { x: self.buffer().cursor_position.x, y: block.top_left.y };
+        self.buffer_mut().history.record(Operation::ReplaceRows { at, removed, inserted });
+      }
+      self.clamp_cursor();
+      return;
+    }
+    let (start, end) = if let Some(selection) = self.selection() {
+      selection
+    } else {
+      let Position { x, y } = self.buffer().cursor_position;
+      let Some(word) = self.buffer().document.row(y).and_then(|row| row.word_at(x)) else {
+        self.set_status(StatusMessage::from("No word at the cursor".to_string()));
+        return;
+      };
+      (Position { x: word.start, y }, Position { x: word.end, y })
+    };
+    let removed = self.rows_text(start.y, end.y);
+    let moved_end = self.buffer_mut().document.map_case(&start, &end, case);
+    let inserted = self.rows_text(start.y, end.y);
+    if inserted == removed {
+      return;
+    }
+    let at = Position { x: self.buffer().cursor_position.x, y: start.y };
+    self.buffer_mut().history.record(Operation::ReplaceRows { at, removed, inserted });
+    // a letter can become more than one, the end of the selection follows
+    if self.buffer().selection_anchor.as_ref() == Some(&end) {
+      self.buffer_mut().selection_anchor = Some(moved_end);
+    } else if self.buffer().selection_anchor.is_some() {
+      self.buffer_mut().cursor_position = moved_end;
     }
+    self.clamp_cursor();
+  }
 
-    if self.quit_times < QUIT_TIMES {
-      self.quit_times = QUIT_TIMES;
-      self.status_message = StatusMessage::from(String::new());
+  // deletes from the cursor to the word boundary in `direction`, joining
+  // rows when the cursor is at their edge
+  fn delete_word(&mut self, direction: SearchDir) {
+    self.buffer_mut().selection_anchor = None;
+    let at = self.buffer().cursor_position.clone();
+    if direction == SearchDir::Forward {
+      let end = self.buffer().document.next_word(&at);
+      self.delete_range(at, &end);
+    } else {
+      let start = self.buffer().document.prev_word(&at);
+      self.delete_range(start, &at);
     }
+  }
 
-    self.scroll();
+  // deletes text between two positions as a single undo step and leaves
+  // the cursor at `start`
+  fn delete_range(&mut self, start: Position<usize>, end: &Position<usize>) {
+    if start == *end {
+      return;
+    }
+    let removed = self.rows_text(start.y, end.y);
+    self.buffer_mut().document.delete_range(&start, end);
+    let inserted = self.rows_text(start.y, start.y);
+    self.buffer_mut().history.record(Operation::ReplaceRows { at: start.clone(), removed, inserted });
+    self.buffer_mut().cursor_position = start;
+  }
 
-    Ok(())      
-  }  
+  // counts lines, words, characters and bytes of the selection, or of the
+  // whole document without one
+  fn show_statistics(&mut self) {
+    let (scope, stats) = match self.selection() {
+      Some((start, end)) => ("Selection", self.buffer().document.stats_between(&start, &end)),
+      None => ("Document", self.buffer_mut().document.stats()),
+    };
+    self.set_status(StatusMessage::from(format!(
+      "{scope}: {} lines, {} words, {} characters, {} bytes",
+      stats.lines, stats.words, stats.characters, stats.bytes,
+    )));
+  }
+
+  fn copy(&mut self) {
+    if let Some(block) = self.block() {
+      let text = self.block_text(&block);
+      self.buffer_mut().selection_anchor = None;
+      self.set_status(StatusMessage::from("Copied block".to_string()));
+      self.set_clipboard(text);
+      return;
+    }
+    if let Some((start, end)) = self.selection() {
+      let text = self.buffer().document.text_range(&start, &end);
+      self.buffer_mut().selection_anchor = None;
+      self.set_status(StatusMessage::from("Copied selection".to_string()));
+      self.set_clipboard(text);
+      return;
+    }
+    if let Some(row) = self.buffer().document.row(self.buffer().cursor_position.y) {
+      let text = format!("{}\n", row.string());
+      self.set_status(StatusMessage::from("Copied line".to_string()));
+      self.set_clipboard(text);
+    }
+  }
+
+  fn set_clipboard(&mut self, text: String) {
+    if let Err(err) = clipboard::set_text(&text) {
+      self.set_status(StatusMessage::error(format!("Clipboard error: {err}")));
+    }
+    self.clipboard = text;
+  }
+
+  fn paste(&mut self) {
+    let text = clipboard::get_text()
+      .unwrap_or_else(|| self.clipboard.clone())
+      .replace("\r\n", "\n");
+    if text.is_empty() {
+      self.set_status(StatusMessage::from("Clipboard is empty".to_string()));
+      return;
+    }
+    if !self.check_writable() {
+      return;
+    }
+    self.buffer_mut().selection_anchor = None;
+    let at = self.buffer().cursor_position.clone();
+    let removed = self.rows_text(at.y, at.y);
+    let end = self.buffer_mut().document.insert_text(&at, &text);
+    let inserted = self.rows_text(at.y, end.y);
+    self.buffer_mut().history.record(Operation::ReplaceRows { at, removed, inserted });
+    self.buffer_mut().cursor_position = end;
+  }
+
+  fn rows_text(&self, from: usize, to: usize) -> Vec<String> {
+    (from..=to)
+      .filter_map(|y| self.buffer().document.row(y))
+      .map(|row| row.string().to_string())
+      .collect()
+  }
+
+  fn clamp_cursor(&mut self) {
+    let Position { mut x, mut y } = self.buffer().cursor_position;
+    y = y.min(self.buffer().document.rows_size().saturating_sub(1));
+    x = x.min(self.buffer().document.row(y).map_or(0, Row::size));
+    self.buffer_mut().cursor_position = Position { x, y };
+  }
+
+  fn undo(&mut self) {
+    if !self.check_writable() {
+      return;
+    }
+    self.buffer_mut().selection_anchor = None;
+    let Buffer { document, history, .. } = self.buffer_mut();
+    if let Some(position) = history.undo(document) {
+      self.buffer_mut().cursor_position = position;
+      self.clamp_cursor();
+    } else {
+      self.set_status(StatusMessage::from("Nothing to undo".to_string()));
+    }
+  }
+
+  fn redo(&mut self) {
+    if !self.check_writable() {
+      return;
+    }
+    self.buffer_mut().selection_anchor = None;
+    let Buffer { document, history, .. } = self.buffer_mut();
+    if let Some(position) = history.redo(document) {
+      self.buffer_mut().cursor_position = position;
+      self.clamp_cursor();
+    } else {
+      self.set_status(StatusMessage::from("Nothing to redo".to_string()));
+    }
+  }
+
+  // screen column of the cursor within its row, tabs widen it
+  fn cursor_rx(&self) -> usize {
+    let Position { x, y } = self.buffer().cursor_position;
+    self.buffer().document.row(y).map_or(0, |row| row.cx_to_rx(x, self.config.tab_width))
+  }
 
   fn scroll(&mut self) {
-    let Position { x, y } = self.cursor_position;
-    let mut offset_x = self.cursor_offset.x;
-    let mut offset_y = self.cursor_offset.y;
-    let terminal_width = self.terminal.size().width as usize;
+    if self.config.wrap {
+      self.scroll_wrapped();
+      return;
+    }
+    let x = self.cursor_rx();
+    let y = self.buffer().cursor_position.y;
+    let mut offset_x = self.buffer().cursor_offset.x;
+    let mut offset_y = self.buffer().cursor_offset.y;
+    let terminal_width = self.text_width();
     let terminal_height = self.terminal.size().height.saturating_sub(2) as usize;      
+    // context kept around the cursor, at most half the view
+    let margin_x = self.config.side_scroll_off.min(terminal_width.saturating_sub(1) / 2);
+    let margin_y = self.scroll_margin();
     let max_x = offset_x.saturating_add(terminal_width);
     let max_y = offset_y.saturating_add(terminal_height);
         
-    if x >= max_x {
-      offset_x = x.saturating_sub(terminal_width).saturating_add(1);
-    } else if x < offset_x {
-      offset_x = x;
+    if x + margin_x >= max_x {
+      offset_x = (x + margin_x).saturating_sub(terminal_width).saturating_add(1);
+    } else if x < offset_x + margin_x {
+      offset_x = x.saturating_sub(margin_x);
     }    
     
-    if y >= max_y {            
-      offset_y = y.saturating_sub(terminal_height).saturating_add(1);
-    } else if y < offset_y {
-      offset_y = y
+    if y + margin_y >= max_y {            
+      // the margin never scrolls the last line up from the bottom
+      let last_offset = self.buffer().document.line_count().saturating_sub(terminal_height);
+      offset_y = (y + margin_y)
+        .saturating_sub(terminal_height)
+        .saturating_add(1)
+        .min(last_offset)
+        .max(offset_y);
+    } else if y < offset_y + margin_y {
+      offset_y = y.saturating_sub(margin_y);
+    }
+
+    self.buffer_mut().cursor_offset = Position{x: offset_x, y: offset_y};    
+  }
+
+  // `scroll` for wrapped rows, the view starts at a row and keeps the
+  // margin in screen lines
+  fn scroll_wrapped(&mut self) {
+    let text_height = self.terminal.size().height.saturating_sub(2) as usize;
+    let margin = self.scroll_margin();
+    let y = self.buffer().cursor_position.y;
+    let (segment, _) = self.cursor_segment();
+    let mut offset_y = self.buffer().cursor_offset.y.min(y);
+    // every row takes a line at least, farther ones are surely out of view
+    offset_y = offset_y.max(y.saturating_sub(text_height));
+
+    let mut above = (offset_y..y).map(|y| self.screen_lines(y)).sum::<usize>() + segment;
+    while above < margin && offset_y > 0 {
+      offset_y -= 1;
+      above += self.screen_lines(offset_y);
+    }
+    // fewer lines are kept below near the end of the document
+    let mut below = self.screen_lines(y) - segment - 1;
+    let mut next = y + 1;
+    while below < margin && next < self.buffer().document.line_count() {
+      below += self.screen_lines(next);
+      next += 1;
+    }
+    let below = below.min(margin);
+    while above + below >= text_height && offset_y < y {
+      above -= self.screen_lines(offset_y);
+      offset_y += 1;
+    }
+
+    self.buffer_mut().cursor_offset = Position { x: 0, y: offset_y };
+  }
+
+  // number of screen lines row `y` takes when wrapped
+  fn screen_lines(&self, y: usize) -> usize {
+    self.buffer().document.row(y).map_or(1, |row| row.wrap_starts(self.text_width(), self.config.tab_width).len())
+  }
+
+  // the wrapped line of its row the cursor is on and its column there
+  fn cursor_segment(&self) -> (usize, usize) {
+    let Position { x, y } = self.buffer().cursor_position;
+    let Some(row) = self.buffer().document.row(y) else {
+      return (0, 0);
+    };
+    let starts = row.wrap_starts(self.text_width(), self.config.tab_width);
+    let segment = starts.iter().rposition(|&start| start <= x).unwrap_or(0);
+    (segment, row.segment_width(starts[segment], x, self.config.tab_width))
+  }
+
+  // rows from the top of the view that fit on screen whole when wrapped
+  fn visible_rows(&self) -> usize {
+    let text_height = self.terminal.size().height.saturating_sub(2) as usize;
+    let mut lines = 0;
+    let mut rows = 0;
+    while rows < text_height {
+      lines += self.screen_lines(self.buffer().cursor_offset.y + rows);
+      if lines > text_height {
+        break;
+      }
+      rows += 1;
     }
+    rows.max(1)
+  }
+
+  // the cursor moved a screen line up or down within wrapped rows, at the
+  // same column of the line
+  fn wrapped_step(&self, down: bool, column: usize) -> Position<usize> {
+    let at = self.buffer().cursor_position.clone();
+    let (segment, _) = self.cursor_segment();
+    let lines = self.screen_lines(at.y);
+    let (y, target) = if down && segment + 1 < lines {
+      (at.y, segment + 1)
+    } else if down && at.y + 1 < self.buffer().document.rows_size() {
+      (at.y + 1, 0)
+    } else if !down && segment > 0 {
+      (at.y, segment - 1)
+    } else if !down && at.y > 0 {
+      (at.y - 1, usize::MAX)
+    } else {
+      return at;
+    };
+    let Some(row) = self.buffer().document.row(y) else {
+      return at;
+    };
+    let segments = wrap_segments(row, self.text_width(), self.config.tab_width);
+    let index = target.min(segments.len() - 1);
+    Position { x: segment_x(row, &segments, index, column, self.config.tab_width), y }
+  }
+
+  // rows of context `scroll` keeps above and below the cursor
+  fn scroll_margin(&self) -> usize {
+    let text_height = self.terminal.size().height.saturating_sub(2) as usize;
+    self.config.scroll_off.min(text_height.saturating_sub(1) / 2)
+  }
+
+  // scrolls so that the cursor's row is `rows` below the top of the view,
+  // without scrolling past the end of the document
+  fn scroll_cursor_to(&mut self, rows: usize) {
+    let text_height = self.terminal.size().height.saturating_sub(2) as usize;
+    let last_offset = self.buffer().document.line_count().saturating_sub(text_height);
+    self.buffer_mut().cursor_offset.y = self.buffer().cursor_position.y.saturating_sub(rows).min(last_offset);
+  }
 
-    self.cursor_offset = Position{x: offset_x, y: offset_y};    
+  fn move_cursor(&mut self, key: KeyCode) {
+    self.buffer_mut().history.seal();
+    self.process_move(key);
   }
 
-  fn process_move(&mut self, key: KeyCode) -> Result<(), Error> {    
-    let Position { mut x, mut y } = self.cursor_position;
+  fn process_move(&mut self, key: KeyCode) {    
+    let Position { mut x, mut y } = self.buffer().cursor_position;
     
-    let terminal_height = self.terminal.size().height as usize;
+    let text_height = self.terminal.size().height.saturating_sub(2) as usize;
+    let vertical = matches!(key, KeyCode::Up | KeyCode::Down);
+    let column = self.target_column();
     match key {
       KeyCode::Left => {
         if x > 0 {
           x -= 1;            
         } else if y > 0 {
           y -= 1;
-          if let Some(row) = self.document.row(y) {
+          if let Some(row) = self.buffer().document.row(y) {
             x = row.size();
           } else {
             x = 0;
@@ -453,10 +3197,10 @@ impl Editor {
         }        
       },
       KeyCode::Right => {
-        if let Some(row) = self.document.row(y) {
+        if let Some(row) = self.buffer().document.row(y) {
           if x < row.size() {            
             x = x.saturating_add(1);
-          } else if y < self.document.rows_size().saturating_sub(1) {
+          } else if y < self.buffer().document.rows_size().saturating_sub(1) {
             y += 1;
             x = 0;
           }                      
@@ -464,34 +3208,673 @@ impl Editor {
           x = 0;
         }
       }
+      KeyCode::Up | KeyCode::Down if self.config.wrap => {
+        Position { x, y } = self.wrapped_step(key == KeyCode::Down, column);
+      },
       KeyCode::Up => y = y.saturating_sub(1),
       KeyCode::Down => y = y.saturating_add(1),
-      KeyCode::Home => x = 0,
+      // the first press goes to the indentation's end, the next to the row
+      // start and back; rows of only whitespace have just the start
+      KeyCode::Home => {
+        let indent = self.buffer().document.row(y).map_or(0, |row| {
+          let indent = row.leading_whitespace();
+          if indent == row.size() { 0 } else { indent }
+        });
+        x = if x == indent { 0 } else { indent };
+      },
       KeyCode::End => {
-        if let Some(row) = self.document.row(y) {
-          x = row.size()
+        if let Some(row) = self.buffer().document.row(y) {
+          x = row.size();
         } else {
           x = 0;
         }
       }
-      KeyCode::PageDown => y = y.saturating_add(terminal_height.saturating_sub(1)),
-      KeyCode::PageUp => y = y.saturating_sub(terminal_height.saturating_sub(1)),
+      KeyCode::PageDown | KeyCode::PageUp => {
+        self.move_page(key == KeyCode::PageDown, text_height);
+        return;
+      },
       _ => {},
     }
-    if let Some(row) = self.document.row(y) {
+    y = y.clamp(0, self.buffer().document.rows_size().saturating_sub(1));
+    if let Some(row) = self.buffer().document.row(y) {
+      if vertical && !self.config.wrap {
+        x = row.rx_to_cx(column, self.config.tab_width);
+      }
       x = x.clamp(0, row.size());
     } else {
       x = 0;
     }
-    y = y.clamp(0, self.document.rows_size().saturating_sub(1));
-    self.cursor_position = Position{ x, y };
-      
-    Ok(())
+    self.buffer_mut().cursor_position = Position{ x, y };
+    self.preferred_column = vertical.then(|| (self.buffer().cursor_position.clone(), column));
+  }
+
+  // the screen column moving up or down aims for, wrapped rows count it
+  // from the start of the screen line
+  fn target_column(&self) -> usize {
+    match &self.preferred_column {
+      Some((at, column)) if *at == self.buffer().cursor_position => *column,
+      _ if self.config.wrap => self.cursor_segment().1,
+      _ => self.cursor_rx(),
+    }
+  }
+
+  // scrolls the view and the cursor by `rows`, so the cursor stays where it
+  // was on screen; near an end of the document the view stops and the
+  // cursor goes on to the first or last row
+  fn move_page(&mut self, down: bool, rows: usize) {
+    let column = self.target_column();
+    let text_height = self.terminal.size().height.saturating_sub(2) as usize;
+    let last_offset = self.buffer().document.line_count().saturating_sub(text_height);
+    let Position { y, .. } = self.buffer().cursor_position;
+    let (offset, y) = if down {
+      let offset = self.buffer().cursor_offset.y;
+      ((offset + rows).min(last_offset).max(offset), (y + rows).min(self.buffer().document.rows_size().saturating_sub(1)))
+    } else {
+      (self.buffer().cursor_offset.y.saturating_sub(rows), y.saturating_sub(rows))
+    };
+    self.buffer_mut().cursor_offset.y = offset;
+    let x = self.buffer().document.row(y).map_or(0, |row| row.rx_to_cx(column, self.config.tab_width));
+    self.buffer_mut().cursor_position = Position { x, y };
+    self.preferred_column = Some((self.buffer().cursor_position.clone(), column));
+  }
+
+  // leaves the screen clean so the error can be reported once the
+  // terminal is dropped and restored
+  fn die(&mut self) -> Result<(), Error>{
+    self.terminal.clear_screen()
+  }
+}
+
+// opens a file with the matching syntax, along with a warning to show
+// about how it was read, naming the keys from `keymap` that deal with it
+fn open_document(
+  file_name: &str,
+  syntaxes: &[SyntaxDef],
+  keymap: &Keymap,
+  progress: impl FnMut(u64, u64),
+) -> Result<(Document, Option<StatusMessage>), Error> {
+  let mut doc = Document::open(file_name, progress)?;
+  doc.set_syntax(syntax::for_path(syntaxes, file_name).cloned());
+  let warning = if doc.is_lossy() {
+    Some(StatusMessage::error(format!("ERR: Could not decode file as {}, opened read-only", doc.encoding().name())))
+  } else if doc.is_read_only() {
+    Some(StatusMessage::from(format!(
+      "File is write-protected, opened read-only ({} to unlock)",
+      keymap.describe(Action::ToggleReadOnly),
+    )))
+  } else if doc.encoding() != UTF_8 {
+    Some(StatusMessage::from(format!(
+      "Guessed encoding {}, {} to reopen with another",
+      doc.encoding().name(),
+      keymap.describe(Action::ReopenWithEncoding),
+    )))
+  } else if doc.has_mixed_line_endings() {
+    let ending = if doc.line_ending() == LineEnding::CrLf { "CRLF" } else { "LF" };
+    Some(StatusMessage::warning(format!("WARNING: Mixed line endings, saving will convert them to {ending}")))
+  } else {
+    None
+  };
+
+  Ok((doc, warning))
+}
+
+// opens a path after expanding it, a file that doesn't exist yet gives an
+// empty document with the path set
+fn open_path(
+  path: &str,
+  syntaxes: &[SyntaxDef],
+  keymap: &Keymap,
+  progress: impl FnMut(u64, u64),
+) -> Result<(Document, Option<StatusMessage>), Error> {
+  let (file_name, expand_warning) = expand_or_warn(path);
+  let (document, warning) = match open_document(&file_name, syntaxes, keymap, progress) {
+    Err(Error::NotFound(_)) => (new_document(&file_name, syntaxes), Some(StatusMessage::from(format!("New file {file_name}")))),
+    opened => opened?,
+  };
+  Ok((document, expand_warning.or(warning)))
+}
+
+// an empty document for a file that doesn't exist yet
+fn new_document(file_name: &str, syntaxes: &[SyntaxDef]) -> Document {
+  let mut document = Document::default();
+  document.set_syntax(syntax::for_path(syntaxes, file_name).cloned());
+  document.path = Some(file_name.to_string());
+  document
+}
+
+// the preset date formats starting with the input
+fn complete_date_format(input: &str) -> Vec<String> {
+  date::PRESETS.iter().filter(|format| format.starts_with(input)).map(ToString::to_string).collect()
+}
+
+// paths starting with the input, directories ending in a slash; the
+// input is expanded first and files starting with a dot are only offered
+// once the input has one
+fn complete_path(input: &str) -> Vec<String> {
+  let input = expand_path(input).unwrap_or_else(|_| input.to_string());
+  let (dir, prefix) = match input.rfind('/') {
+    Some(slash) => input.split_at(slash + 1),
+    None => ("", input.as_str()),
+  };
+  let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+    return Vec::new();
+  };
+  let mut completions: Vec<String> = entries
+    .filter_map(Result::ok)
+    .filter_map(|entry| {
+      let name = entry.file_name().into_string().ok()?;
+      if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+        return None;
+      }
+      let slash = if entry.path().is_dir() { "/" } else { "" };
+      Some(format!("{dir}{name}{slash}"))
+    })
+    .collect();
+  completions.sort();
+  completions
+}
+
+// `path` with a leading `~` or `~user` replaced by the home directory and
+// `$VAR` or `${VAR}` by the variable's value; a file with the literal name
+// is left alone
+fn expand_path(path: &str) -> Result<String, String> {
+  if Path::new(path).exists() {
+    return Ok(path.to_string());
+  }
+  let mut expanded = String::new();
+  let mut rest = path;
+  if let Some(after) = path.strip_prefix('~') {
+    let end = after.find('/').unwrap_or(after.len());
+    let user = &after[..end];
+    let home = if user.is_empty() {
+      env::var("HOME").map_err(|_| "HOME is not set".to_string())?
+    } else {
+      home_of(user).ok_or_else(|| format!("Unknown user {user}"))?
+    };
+    rest = &after[end..];
+    expanded.push_str(if rest.is_empty() { &home } else { home.trim_end_matches('/') });
+  }
+  while let Some(dollar) = rest.find('$') {
+    expanded.push_str(&rest[..dollar]);
+    rest = &rest[dollar + 1..];
+    let (name, after) = if let Some(braced) = rest.strip_prefix('{') {
+      let close = braced.find('}').ok_or_else(|| format!("Missing }} in {path}"))?;
+      (&braced[..close], &braced[close + 1..])
+    } else {
+      let end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+      (&rest[..end], &rest[end..])
+    };
+    if name.is_empty() {
+      // a `$` without a name after it is kept as typed
+      expanded.push('$');
+      continue;
+    }
+    expanded.push_str(&env::var(name).map_err(|_| format!("{name} is not set"))?);
+    rest = after;
+  }
+  expanded.push_str(rest);
+  Ok(expanded)
+}
+
+// `expand_path`, or the path as typed and a warning saying why not
+fn expand_or_warn(path: &str) -> (String, Option<StatusMessage>) {
+  match expand_path(path) {
+    Ok(expanded) => (expanded, None),
+    Err(err) => (path.to_string(), Some(StatusMessage::warning(format!("WARNING: {err}, using {path} as typed")))),
+  }
+}
+
+// whether a key ends a prompt taking its input, an empty input can't tell
+// it from Esc
+fn is_enter(key: &KeyEvent) -> bool {
+  matches!(
+    key,
+    KeyEvent{code: KeyCode::Enter, ..} | KeyEvent{code: KeyCode::Char('j'), modifiers: KeyModifiers::CONTROL, ..}
+  )
+}
+
+// the home directory of `user` from the password file
+fn home_of(user: &str) -> Option<String> {
+  let passwd = fs::read_to_string("/etc/passwd").ok()?;
+  passwd.lines().find_map(|line| {
+    let mut fields = line.split(':');
+    (fields.next() == Some(user)).then(|| fields.nth(4)).flatten().map(ToString::to_string)
+  })
+}
+
+// the position at a line and column counted from 1, kept inside the document
+fn position_in(document: &Document, line: Option<usize>, column: Option<usize>) -> Position<usize> {
+  let y = line.unwrap_or(1).saturating_sub(1).min(document.rows_size().saturating_sub(1));
+  let x = column.unwrap_or(1).saturating_sub(1).min(document.row(y).map_or(0, Row::size));
+  Position { x, y }
+}
+
+// the grapheme ranges of a row's screen lines when wrapped at `width`
+fn wrap_segments(row: &Row, width: usize, tab_width: usize) -> Vec<Range<usize>> {
+  let starts = row.wrap_starts(width, tab_width);
+  let ends = starts.iter().skip(1).copied().chain([row.size()]);
+  starts.iter().zip(ends).map(|(&start, end)| start..end).collect()
+}
+
+// the `<` or `>` drawn where a row goes on past the pane's edge
+fn scroll_marker(marker: char) -> String {
+  format!("{}{marker}{}", SetForegroundColor(GUTTER_FG), SetForegroundColor(Color::Reset))
+}
+
+// the grapheme at a column of one of a row's wrapped lines; only the last
+// line can put it past its end, on the others that is the next line
+fn segment_x(row: &Row, segments: &[Range<usize>], index: usize, column: usize, tab_width: usize) -> usize {
+  let segment = segments[index].clone();
+  let x = row.segment_cx(segment.clone(), column, tab_width);
+  if index + 1 < segments.len() {
+    x.min(segment.end.saturating_sub(1)).max(segment.start)
+  } else {
+    x
+  }
+}
+
+// a coarse age like `just now` or `5m ago`, it changes at most once a minute
+fn time_ago(elapsed: Duration) -> String {
+  match elapsed.as_secs() {
+    0..=59 => "just now".to_string(),
+    seconds @ 60..=3599 => format!("{}m ago", seconds / 60),
+    seconds => format!("{}h ago", seconds / 3600),
+  }
+}
+
+// `path` in at most `width` columns by replacing leading directories
+// with `…`, like `…/dir/file.rs`; the file name itself is always kept
+fn shorten_path(path: &str, width: usize) -> String {
+  if path.width() <= width {
+    return path.to_string();
+  }
+  let mut parts = path.rsplit('/');
+  let mut shortened = parts.next().unwrap_or_default().to_string();
+  for part in parts {
+    if part.width() + shortened.width() + "…//".width() > width {
+      break;
+    }
+    shortened = format!("{part}/{shortened}");
+  }
+  format!("…/{shortened}")
+}
+
+// cuts `text` down to at most `width` terminal columns
+fn truncate_columns(text: &mut String, width: usize) {
+  let mut columns = 0;
+  let end = text
+    .char_indices()
+    .find(|(_, ch)| {
+      columns += ch.width().unwrap_or(0);
+      columns > width
+    })
+    .map_or(text.len(), |(index, _)| index);
+  text.truncate(end);
+}
+
+// how far into the file a line is, like `Top`, `42%` or `Bot`
+fn percent_through(y: usize, line_count: usize) -> String {
+  if y == 0 {
+    "Top".to_string()
+  } else if y + 1 >= line_count {
+    "Bot".to_string()
+  } else {
+    format!("{}%", (y + 1) * 100 / line_count)
+  }
+}
+
+fn closing_pair(c: char) -> Option<char> {
+  match c {
+    '(' => Some(')'),
+    '[' => Some(']'),
+    '{' => Some('}'),
+    '"' => Some('"'),
+    '\'' => Some('\''),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::args::{Args, FileArg};
+  use crate::terminal::MockTerminal;
+
+  // an editor on a screen of `width` by `height` showing `text` like it
+  // was piped in, without line numbers
+  fn editor(text: &str, width: u16, height: u16) -> Editor<MockTerminal> {
+    let startup = Startup {
+      args: Args { files: vec![FileArg { path: None, line: None, column: None }], ..Args::default() },
+      stdin: Some(Document::from_bytes(text.as_bytes())),
+      config: Config { line_numbers: LineNumberMode::Off, ..Config::default() },
+      ..Startup::default()
+    };
+    let mut editor = Editor::new(MockTerminal::new(width, height), startup);
+    editor.refresh_screen().unwrap();
+    editor
+  }
+
+  // handles the events like the event loop, drawing a frame after each;
+  // prompts read theirs from the same queue, so a prompt's keys go in one
+  // call with the key opening it
+  fn send(editor: &mut Editor<MockTerminal>, events: impl IntoIterator<Item = Event>) {
+    editor.terminal.events.extend(events);
+    while !editor.terminal.events.is_empty() {
+      if let Some(event) = editor.read_event().unwrap() {
+        editor.process_event(&event);
+      }
+      editor.refresh_screen().unwrap();
+    }
+  }
+
+  fn key(code: KeyCode) -> Event {
+    Event::Key(KeyEvent::new(code, KeyModifiers::NONE))
+  }
+
+  fn ctrl(c: char) -> Event {
+    Event::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL))
   }
 
-  fn die(&mut self, err: Error) -> Result<(), Error>{
-    self.terminal.clear_screen()?;
+  fn text(text: &str) -> Vec<Event> {
+    text.chars().map(|c| key(KeyCode::Char(c))).collect()
+  }
+
+  fn press(editor: &mut Editor<MockTerminal>, code: KeyCode, times: usize) {
+    send(editor, vec![key(code); times]);
+  }
+
+  fn type_text(editor: &mut Editor<MockTerminal>, typed: &str) {
+    send(editor, text(typed));
+  }
+
+  fn numbered_lines(count: usize) -> String {
+    (1..=count).map(|number| number.to_string() + "\n").collect()
+  }
+
+  #[test]
+  fn draws_the_rows_and_tildes_past_the_end() {
+    let editor = editor("one\ntwo\n", 20, 6);
+    assert_eq!(editor.terminal.line(0), "one");
+    assert_eq!(editor.terminal.line(1), "two");
+    assert_eq!(editor.terminal.line(2), "~");
+    assert_eq!(editor.terminal.line(3), "~");
+  }
+
+  #[test]
+  fn typing_inserts_at_the_cursor() {
+    let mut editor = editor("xy\n", 20, 6);
+    type_text(&mut editor, "ab");
+    press(&mut editor, KeyCode::Right, 1);
+    type_text(&mut editor, "c");
+    assert_eq!(editor.terminal.line(0), "abxcy");
+    assert_eq!(editor.terminal.cursor, (4, 0));
+  }
+
+  #[test]
+  fn the_cursor_goes_past_wide_characters_by_their_width() {
+    let mut editor = editor("你好world\n", 20, 6);
+    press(&mut editor, KeyCode::Right, 2);
+    assert_eq!(editor.terminal.cursor, (4, 0));
+    type_text(&mut editor, "x");
+    assert_eq!(editor.terminal.line(0), "你好xworld");
+    assert_eq!(editor.terminal.cursor, (5, 0));
+  }
+
+  #[test]
+  fn moving_down_scrolls_the_cursor_row_into_view() {
+    let mut editor = editor(&numbered_lines(50), 20, 10);
+    press(&mut editor, KeyCode::Down, 20);
+    let (_, y) = editor.terminal.cursor;
+    assert!(y < 8);
+    assert_eq!(editor.terminal.line(y), "21");
+    press(&mut editor, KeyCode::Up, 15);
+    let (_, y) = editor.terminal.cursor;
+    assert_eq!(editor.terminal.line(y), "6");
+  }
+
+  #[test]
+  fn long_rows_scroll_sideways() {
+    let mut editor = editor(&format!("{}\n", "x".repeat(30)), 20, 6);
+    press(&mut editor, KeyCode::End, 1);
+    assert!(editor.terminal.cursor.0 < 20);
+    assert!(editor.terminal.line(0).starts_with('<'));
+  }
+
+  #[test]
+  fn search_moves_to_the_next_match() {
+    let mut editor = editor("one\ntwo needle\nthree\n", 30, 6);
+    send(&mut editor, [vec![ctrl('f')], text("needle"), vec![key(KeyCode::Enter)]].concat());
+    assert_eq!(editor.buffer().cursor_position, Position { x: 4, y: 1 });
+    assert_eq!(editor.terminal.cursor, (4, 1));
+  }
+
+  #[test]
+  fn shrinking_the_screen_keeps_the_cursor_in_view() {
+    let mut editor = editor(&numbered_lines(100), 80, 40);
+    editor.config.scroll_off = 0;
+    press(&mut editor, KeyCode::Down, 37);
+    assert_eq!(editor.terminal.cursor, (0, 37));
+    send(&mut editor, [Event::Resize(20, 10)]);
+    let (x, y) = editor.terminal.cursor;
+    assert!(x < 20 && y < 8);
+    assert_eq!(editor.terminal.line(y), "38");
+  }
+
+  #[test]
+  fn home_toggles_between_the_indentation_and_the_row_start() {
+    let mut editor = editor("  \tindented\n", 30, 6);
+    press(&mut editor, KeyCode::End, 1);
+    press(&mut editor, KeyCode::Home, 1);
+    assert_eq!(editor.buffer().cursor_position.x, 3);
+    press(&mut editor, KeyCode::Home, 1);
+    assert_eq!(editor.buffer().cursor_position.x, 0);
+    press(&mut editor, KeyCode::Home, 1);
+    assert_eq!(editor.buffer().cursor_position.x, 3);
+  }
+
+  #[test]
+  fn home_counts_the_indentation_in_graphemes() {
+    // the second space carries a combining mark, so it ends the indentation
+    let mut editor = editor("  \u{301}x\n", 30, 6);
+    press(&mut editor, KeyCode::End, 1);
+    press(&mut editor, KeyCode::Home, 1);
+    assert_eq!(editor.buffer().cursor_position.x, 1);
+  }
+
+  #[test]
+  fn unindent_leaves_a_space_carrying_a_combining_mark() {
+    let mut editor = editor("  \u{301}x\n", 30, 6);
+    press(&mut editor, KeyCode::End, 1);
+    press(&mut editor, KeyCode::BackTab, 1);
+    assert_eq!(editor.buffer().document.row(0).unwrap().string(), " \u{301}x");
+    assert_eq!(editor.buffer().cursor_position.x, 2);
+  }
+
+  #[test]
+  fn home_on_a_whitespace_only_row_goes_to_its_start() {
+    let mut editor = editor("    \n", 30, 6);
+    press(&mut editor, KeyCode::End, 1);
+    press(&mut editor, KeyCode::Home, 1);
+    assert_eq!(editor.buffer().cursor_position.x, 0);
+    press(&mut editor, KeyCode::Home, 1);
+    assert_eq!(editor.buffer().cursor_position.x, 0);
+  }
+
+  #[test]
+  fn home_on_an_empty_row_stays_put() {
+    let mut editor = editor("\nnext\n", 30, 6);
+    press(&mut editor, KeyCode::Home, 2);
+    assert_eq!(editor.buffer().cursor_position, Position { x: 0, y: 0 });
+  }
+
+  #[test]
+  fn search_wraps_around_the_document_ends() {
+    let mut editor = editor("needle one\nmiddle\nneedle two\n", 30, 6);
+    press(&mut editor, KeyCode::Down, 1);
+    let keys = [vec![ctrl('f')], text("needle"), vec![key(KeyCode::Down), key(KeyCode::Enter)]].concat();
+    send(&mut editor, keys);
+    assert_eq!(editor.buffer().cursor_position, Position { x: 0, y: 0 });
+
+    let keys = [vec![ctrl('f')], text("needle"), vec![key(KeyCode::Up), key(KeyCode::Enter)]].concat();
+    send(&mut editor, keys);
+    assert_eq!(editor.buffer().cursor_position, Position { x: 0, y: 2 });
+  }
+
+  #[test]
+  fn search_stops_at_the_ends_without_wrapscan() {
+    let mut editor = editor("needle one\nmiddle\nneedle two\n", 30, 6);
+    editor.config.wrap_search = false;
+    press(&mut editor, KeyCode::Down, 1);
+    let keys = [vec![ctrl('f')], text("needle"), vec![key(KeyCode::Down), key(KeyCode::Enter)]].concat();
+    send(&mut editor, keys);
+    assert_eq!(editor.buffer().cursor_position, Position { x: 0, y: 2 });
+  }
+
+  fn modal_editor(text: &str) -> Editor<MockTerminal> {
+    let mut editor = editor(text, 30, 6);
+    editor.config.modal = true;
+    editor.mode = EditorMode::Normal;
+    editor
+  }
+
+  #[test]
+  fn enter_and_backspace_move_in_normal_mode() {
+    let mut editor = modal_editor("one\ntwo\nthree\n");
+    press(&mut editor, KeyCode::Right, 2);
+    press(&mut editor, KeyCode::Enter, 1);
+    assert_eq!(editor.buffer().cursor_position, Position { x: 2, y: 1 });
+    press(&mut editor, KeyCode::Backspace, 1);
+    assert_eq!(editor.buffer().cursor_position, Position { x: 1, y: 1 });
+    send(&mut editor, [key(KeyCode::Char('2')), key(KeyCode::Enter)]);
+    assert_eq!(editor.buffer().cursor_position.y, 2);
+    assert!(!editor.buffer().document.is_dirty());
+  }
+
+  #[test]
+  fn delete_and_tab_do_nothing_in_normal_mode() {
+    let mut editor = modal_editor("one\n");
+    press(&mut editor, KeyCode::Delete, 1);
+    press(&mut editor, KeyCode::Tab, 1);
+    press(&mut editor, KeyCode::BackTab, 1);
+    assert_eq!(editor.terminal.line(0), "one");
+    assert!(!editor.buffer().document.is_dirty());
+    // in insert mode they edit again
+    send(&mut editor, [key(KeyCode::Char('i')), key(KeyCode::Delete)]);
+    assert_eq!(editor.terminal.line(0), "ne");
+  }
+
+  #[test]
+  fn the_status_bar_shows_whether_typing_inserts_or_overwrites() {
+    let mut editor = editor("one\n", 40, 6);
+    assert!(editor.terminal.line(4).starts_with("INS "));
+    press(&mut editor, KeyCode::Insert, 1);
+    assert!(editor.terminal.line(4).starts_with("OVR "));
+    type_text(&mut editor, "t");
+    assert_eq!(editor.terminal.line(0), "tne");
+  }
+
+  #[test]
+  fn replacing_with_an_empty_string_deletes_the_matches() {
+    let mut editor = editor("abcb\n", 30, 6);
+    let keys = [vec![ctrl('r')], text("b"), vec![key(KeyCode::Enter), key(KeyCode::Enter)], text("a")].concat();
+    send(&mut editor, keys);
+    assert_eq!(editor.terminal.line(0), "ac");
+    assert_eq!(editor.status_message.text, "Replaced 2 occurrence(s)");
+  }
+
+  #[test]
+  fn esc_at_the_replacement_aborts() {
+    let mut editor = editor("abcb\n", 30, 6);
+    let keys = [vec![ctrl('r')], text("b"), vec![key(KeyCode::Enter), key(KeyCode::Esc)]].concat();
+    send(&mut editor, keys);
+    assert_eq!(editor.terminal.line(0), "abcb");
+    assert_eq!(editor.status_message.text, "Replace aborted");
+  }
+
+  #[test]
+  fn long_messages_are_cut_at_the_screen_width() {
+    let mut editor = editor("", 10, 6);
+    editor.set_status(StatusMessage::from("日本語の長いメッセージ".to_string()));
+    editor.refresh_screen().unwrap();
+    assert_eq!(editor.terminal.line(5), "日本語の長");
+  }
+
+  #[test]
+  fn the_severity_is_given_not_read_from_the_text() {
+    assert!(StatusMessage::from("ERR: looks bad".to_string()).severity == Severity::Info);
+    assert!(StatusMessage::error("Failed".to_string()).severity == Severity::Error);
+  }
+
+  #[test]
+  fn a_repeat_count_waits_behind_a_fresh_error() {
+    let mut editor = editor("one\n", 40, 6);
+    editor.set_status(StatusMessage::error("ERR: it broke".to_string()));
+    let repeat = Event::Key(KeyEvent::new(KeyCode::Char('u'), KeyModifiers::ALT));
+    send(&mut editor, [repeat, key(KeyCode::Char('3'))]);
+    assert_eq!(editor.pending_count, Some(3));
+    assert_eq!(editor.status_message.text, "ERR: it broke");
+  }
+
+  #[test]
+  fn typing_removes_the_welcome_message() {
+    let mut editor = editor("", 40, 10);
+    assert!(editor.terminal.line(5).contains("Slime editor"));
+    type_text(&mut editor, "a");
+    assert_eq!(editor.terminal.line(0), "a");
+    assert_eq!(editor.terminal.line(5), "~");
+  }
+
+  #[test]
+  fn every_frame_is_written_out_once() {
+    let mut editor = editor(&numbered_lines(200), 80, 24);
+    editor.terminal.flushes = 0;
+    type_text(&mut editor, "abc");
+    press(&mut editor, KeyCode::PageDown, 2);
+    assert_eq!(editor.terminal.flushes, 5);
+  }
+
+  #[test]
+  fn typing_redraws_only_the_edited_row_and_the_bars() {
+    let mut editor = editor(&numbered_lines(200), 80, 24);
+    press(&mut editor, KeyCode::Down, 10);
+    editor.terminal.cleared_lines = 0;
+    type_text(&mut editor, "abcde");
+    // the edited row and the message bar, the status bar is drawn over
+    assert_eq!(editor.terminal.cleared_lines, 5 * 2);
+    assert_eq!(editor.terminal.line(10), "abcde11");
+  }
+
+  #[test]
+  fn scrolling_redraws_every_row() {
+    let mut editor = editor(&numbered_lines(200), 80, 24);
+    editor.terminal.cleared_lines = 0;
+    press(&mut editor, KeyCode::PageDown, 1);
+    assert_eq!(editor.terminal.cleared_lines, 22 + 1);
+  }
+
+  #[test]
+  fn set_undolevels_forgets_the_oldest_edits() {
+    let mut editor = editor("", 30, 6);
+    type_text(&mut editor, "one two three");
+    let mut command = vec![Event::Key(KeyEvent::new(KeyCode::Char('x'), KeyModifiers::ALT))];
+    command.extend(text("set ul=2"));
+    command.push(key(KeyCode::Enter));
+    send(&mut editor, command);
+    assert_eq!(editor.config.undo_limit, 2);
+    // a word is undone with the space after it
+    send(&mut editor, vec![ctrl('z'); 10]);
+    assert_eq!(editor.buffer().document.row(0).unwrap().string(), "one ");
+  }
 
-    panic!("{}", err)    
+  #[test]
+  fn a_prompt_keeps_the_error_and_the_messages_behind_it() {
+    let mut editor = editor("text\n", 30, 6);
+    editor.set_status(StatusMessage::error("ERR: boom".to_string()));
+    editor.set_status(StatusMessage::from("later".to_string()));
+    let mut command = vec![Event::Key(KeyEvent::new(KeyCode::Char('x'), KeyModifiers::ALT))];
+    command.push(key(KeyCode::Esc));
+    send(&mut editor, command);
+    assert_eq!(editor.status_message.text, "ERR: boom");
+    assert_eq!(editor.status_queue.iter().map(|message| message.text.as_str()).collect::<Vec<_>>(), ["later"]);
   }
 }
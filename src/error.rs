@@ -0,0 +1,54 @@
+use std::{fmt, io};
+
+// what went wrong, told apart so the status bar can say more than the
+// bare I/O error and callers can react to a missing file
+#[derive(Debug)]
+pub enum Error {
+  // a file that doesn't exist, with the error saying so
+  NotFound(io::Error),
+  PermissionDenied,
+  IsADirectory,
+  ReadOnlyFilesystem,
+  // the text can't be written in the file's encoding
+  Encoding(String),
+  ReadOnly,
+  // a mistake in the config file or a `:set` command
+  Config(String),
+  Io(io::Error),
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Error::NotFound(_) => write!(f, "no such file"),
+      Error::PermissionDenied => write!(f, "permission denied"),
+      Error::IsADirectory => write!(f, "is a directory"),
+      Error::ReadOnlyFilesystem => write!(f, "the file system is read-only"),
+      Error::Encoding(message) | Error::Config(message) => write!(f, "{message}"),
+      Error::ReadOnly => write!(f, "buffer is read-only"),
+      Error::Io(err) => write!(f, "{err}"),
+    }
+  }
+}
+
+impl std::error::Error for Error {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Error::NotFound(err) | Error::Io(err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+// the kinds a user can do something about get their own variant
+impl From<io::Error> for Error {
+  fn from(err: io::Error) -> Self {
+    match err.kind() {
+      io::ErrorKind::NotFound => Error::NotFound(err),
+      io::ErrorKind::PermissionDenied => Error::PermissionDenied,
+      io::ErrorKind::IsADirectory => Error::IsADirectory,
+      io::ErrorKind::ReadOnlyFilesystem => Error::ReadOnlyFilesystem,
+      _ => Error::Io(err),
+    }
+  }
+}
@@ -0,0 +1,25 @@
+use crossterm::style::Color;
+
+#[derive(Default, PartialEq, Copy, Clone)]
+pub enum Type {
+  #[default]
+  None,
+  Number,
+  String,
+  Comment,
+  Keyword,
+  Builtin,
+}
+
+impl Type {
+  pub fn to_color(self) -> Color {
+    match self {
+      Type::None => Color::Reset,
+      Type::Number => Color::Rgb { r: 220, g: 163, b: 163 },
+      Type::String => Color::Rgb { r: 152, g: 195, b: 121 },
+      Type::Comment => Color::Rgb { r: 128, g: 128, b: 128 },
+      Type::Keyword => Color::Rgb { r: 229, g: 192, b: 123 },
+      Type::Builtin => Color::Rgb { r: 86, g: 182, b: 194 },
+    }
+  }
+}
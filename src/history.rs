@@ -0,0 +1,204 @@
+use std::collections::VecDeque;
+
+use crate::Document;
+use crate::Position;
+
+#[derive(Clone)]
+pub enum Operation {
+  InsertChar { at: Position<usize>, ch: char },
+  DeleteChar { at: Position<usize>, grapheme: String },
+  // row `at.y` was split at column `at.x`
+  InsertLine { at: Position<usize> },
+  // row `at.y + 1` was appended to row `at.y`, whose length was `at.x`
+  JoinLines { at: Position<usize> },
+  // rows starting at `at.y` were replaced, `at` is the cursor before the change
+  ReplaceRows { at: Position<usize>, removed: Vec<String>, inserted: Vec<String> },
+}
+
+impl Operation {
+  fn apply(&self, document: &mut Document) {
+    match self {
+      Operation::InsertChar { at, ch } => document.insert(at, *ch),
+      Operation::InsertLine { at } => document.insert(at, '\n'),
+      Operation::DeleteChar { at, .. } | Operation::JoinLines { at } => document.delete(at),
+      Operation::ReplaceRows { at, removed, inserted } =>
+        document.replace_rows(at.y, removed.len(), inserted),
+    }
+  }
+
+  fn revert(&self, document: &mut Document) {
+    match self {
+      Operation::InsertChar { at, .. } | Operation::InsertLine { at } => document.delete(at),
+      Operation::DeleteChar { at, grapheme } => {
+        for (offset, ch) in grapheme.chars().enumerate() {
+          document.insert(&Position { x: at.x + offset, y: at.y }, ch);
+        }
+      },
+      Operation::JoinLines { at } => document.insert(at, '\n'),
+      Operation::ReplaceRows { at, removed, inserted } =>
+        document.replace_rows(at.y, inserted.len(), removed),
+    }
+  }
+
+  // cursor position right after the operation has been applied
+  fn cursor_after(&self) -> Position<usize> {
+    match self {
+      Operation::InsertChar { at, .. } => Position { x: at.x + 1, y: at.y },
+      Operation::InsertLine { at } => Position { x: 0, y: at.y + 1 },
+      Operation::DeleteChar { at, .. }
+        | Operation::JoinLines { at }
+        | Operation::ReplaceRows { at, .. } => at.clone(),
+    }
+  }
+
+  fn position(&self) -> &Position<usize> {
+    match self {
+      Operation::InsertChar { at, .. }
+        | Operation::DeleteChar { at, .. }
+        | Operation::InsertLine { at }
+        | Operation::JoinLines { at }
+        | Operation::ReplaceRows { at, .. } => at,
+    }
+  }
+}
+
+pub struct UndoStack {
+  undo: VecDeque<Vec<Operation>>,
+  redo: Vec<Vec<Operation>>,
+  capacity: usize,
+  // length of `undo` when the document was last saved
+  saved_at: Option<usize>,
+  // how many `begin_group` calls are still open, groups nest so that an
+  // edit grouping its steps can be part of a larger one
+  grouping: usize,
+  group_open: bool,
+  coalesce: bool,
+}
+
+impl UndoStack {
+  pub fn new(capacity: usize) -> Self {
+    Self {
+      undo: VecDeque::new(),
+      redo: Vec::new(),
+      capacity,
+      saved_at: Some(0),
+      grouping: 0,
+      group_open: false,
+      coalesce: false,
+    }
+  }
+
+  pub fn record(&mut self, op: Operation) {
+    if !self.redo.is_empty() {
+      self.redo.clear();
+      if self.saved_at.is_some_and(|saved_at| saved_at > self.undo.len()) {
+        self.saved_at = None;
+      }
+    }
+
+    let append = if self.grouping > 0 {
+      self.group_open
+    } else {
+      self.coalesce && self.continues_typing(&op)
+    };
+    // typing a word is undone at once, whitespace ends the word
+    self.coalesce = matches!(op, Operation::InsertChar { ch, .. } if !ch.is_whitespace());
+
+    match self.undo.back_mut() {
+      Some(group) if append => group.push(op),
+      _ => self.push_group(vec![op]),
+    }
+    self.group_open = self.grouping > 0;
+  }
+
+  // every operation recorded until `end_group` is undone as a single step
+  pub fn begin_group(&mut self) {
+    if self.grouping == 0 {
+      self.group_open = false;
+    }
+    self.grouping += 1;
+  }
+
+  pub fn end_group(&mut self) {
+    self.grouping = self.grouping.saturating_sub(1);
+    if self.grouping == 0 {
+      self.group_open = false;
+      self.coalesce = false;
+    }
+  }
+
+  // stops the next insert from being merged into the current group
+  pub fn seal(&mut self) {
+    self.coalesce = false;
+  }
+
+  // the document no longer matches the file at any point of the history
+  pub fn mark_unsaved(&mut self) {
+    self.saved_at = None;
+  }
+
+  pub fn mark_saved(&mut self) {
+    self.saved_at = Some(self.undo.len());
+    self.coalesce = false;
+  }
+
+  pub fn undo(&mut self, document: &mut Document) -> Option<Position<usize>> {
+    let group = self.undo.pop_back()?;
+    for op in group.iter().rev() {
+      op.revert(document);
+    }
+    let position = group.first().map(|op| op.position().clone());
+    self.redo.push(group);
+    self.coalesce = false;
+    document.set_dirty(!self.is_at_save_point());
+
+    position
+  }
+
+  pub fn redo(&mut self, document: &mut Document) -> Option<Position<usize>> {
+    let group = self.redo.pop()?;
+    for op in &group {
+      op.apply(document);
+    }
+    let position = group.last().map(Operation::cursor_after);
+    self.undo.push_back(group);
+    self.coalesce = false;
+    document.set_dirty(!self.is_at_save_point());
+
+    position
+  }
+
+  fn is_at_save_point(&self) -> bool {
+    self.saved_at == Some(self.undo.len())
+  }
+
+  fn continues_typing(&self, op: &Operation) -> bool {
+    let last = self.undo.back().and_then(|group| group.last());
+    match (last, op) {
+      (Some(Operation::InsertChar { at: prev, .. }), Operation::InsertChar { at, .. }) =>
+        prev.y == at.y && prev.x + 1 == at.x,
+      _ => false,
+    }
+  }
+
+  // keeps at most `capacity` steps, dropping the oldest ones
+  pub fn set_capacity(&mut self, capacity: usize) {
+    self.capacity = capacity;
+    self.trim();
+  }
+
+  fn push_group(&mut self, group: Vec<Operation>) {
+    self.undo.push_back(group);
+    self.trim();
+  }
+
+  fn trim(&mut self) {
+    while self.undo.len() > self.capacity {
+      self.undo.pop_front();
+      self.saved_at = match self.saved_at {
+        Some(0) | None => None,
+        Some(saved_at) => Some(saved_at - 1),
+      };
+    }
+  }
+}
@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+use std::fs;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::config;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+  Newline,
+  Quit,
+  ForceQuit,
+  ToggleReadOnly,
+  NextBuffer,
+  PreviousBuffer,
+  ToggleSplit,
+  NextPane,
+  Command,
+  OpenFile,
+  NewBuffer,
+  Save,
+  Search,
+  Replace,
+  CursorsAtMatches,
+  Reload,
+  ReopenWithEncoding,
+  GoToLine,
+  CycleLineNumbers,
+  ToggleExpandTabs,
+  ToggleAutoIndent,
+  ToggleMouse,
+  ToggleOverwrite,
+  ToggleWrap,
+  ToggleInvisibles,
+  Undo,
+  Redo,
+  SetMark,
+  SetBlockMark,
+  ClearMark,
+  Cut,
+  Unindent,
+  ToggleComment,
+  Filter,
+  InsertDate,
+  RemoveDuplicateLines,
+  SqueezeBlankLines,
+  TransposeCharacters,
+  TransposeWords,
+  Uppercase,
+  Lowercase,
+  ToggleCase,
+  Titlecase,
+  DeleteLine,
+  DuplicateLine,
+  MoveLineUp,
+  MoveLineDown,
+  Copy,
+  Paste,
+  DeleteWordBackward,
+  DeleteWordForward,
+  WordLeft,
+  WordRight,
+  MatchingBracket,
+  DocumentStart,
+  DocumentEnd,
+  CenterView,
+  ViewTop,
+  ViewBottom,
+  Statistics,
+  RepeatCount,
+  StartMacro,
+  StopMacro,
+  PlayMacro,
+  JumpBack,
+  JumpForward,
+  MoveLeft,
+  MoveRight,
+  MoveUp,
+  MoveDown,
+  LineStart,
+  LineEnd,
+  PageUp,
+  PageDown,
+  HalfPageUp,
+  HalfPageDown,
+}
+
+// every action with its name in `keys.toml` and the keys it is bound to
+// unless that file says otherwise
+const ACTIONS: &[(Action, &str, &[&str])] = &[
+  (Action::Newline, "newline", &["enter", "ctrl-j"]),
+  (Action::Quit, "quit", &["ctrl-q"]),
+  (Action::ForceQuit, "force_quit", &["alt-q"]),
+  (Action::ToggleReadOnly, "toggle_read_only", &["ctrl-l"]),
+  (Action::NextBuffer, "next_buffer", &["ctrl-n"]),
+  (Action::PreviousBuffer, "previous_buffer", &["ctrl-p"]),
+  (Action::ToggleSplit, "toggle_split", &["alt-v"]),
+  (Action::NextPane, "next_pane", &["alt-w"]),
+  (Action::Command, "command", &["alt-x"]),
+  (Action::OpenFile, "open_file", &["alt-o"]),
+  (Action::NewBuffer, "new_buffer", &["ctrl-t"]),
+  (Action::Save, "save", &["ctrl-s"]),
+  (Action::Search, "search", &["ctrl-f"]),
+  (Action::Replace, "replace", &["ctrl-r"]),
+  // a cursor on every match of the last search, Esc goes back to one
+  (Action::CursorsAtMatches, "cursors_at_matches", &["alt-n"]),
+  (Action::Reload, "reload", &["ctrl-e"]),
+  (Action::ReopenWithEncoding, "reopen_with_encoding", &["alt-e"]),
+  (Action::GoToLine, "go_to_line", &["ctrl-g"]),
+  (Action::CycleLineNumbers, "cycle_line_numbers", &["alt-l"]),
+  (Action::ToggleExpandTabs, "toggle_expand_tabs", &["alt-t"]),
+  (Action::ToggleAutoIndent, "toggle_auto_indent", &["alt-i"]),
+  (Action::ToggleMouse, "toggle_mouse", &["alt-m"]),
+  (Action::ToggleOverwrite, "toggle_overwrite", &["insert"]),
+  (Action::ToggleWrap, "toggle_wrap", &["alt-z"]),
+  (Action::ToggleInvisibles, "toggle_invisibles", &["alt-h"]),
+  (Action::Undo, "undo", &["ctrl-z"]),
+  (Action::Redo, "redo", &["ctrl-y", "ctrl-shift-z"]),
+  (Action::SetMark, "set_mark", &["ctrl-space"]),
+  // the mark and the cursor are corners of a rectangle
+  (Action::SetBlockMark, "set_block_mark", &["alt-b"]),
+  (Action::ClearMark, "clear_mark", &["esc"]),
+  (Action::Cut, "cut", &["ctrl-k"]),
+  (Action::Unindent, "unindent", &["shift-tab"]),
+  (Action::ToggleComment, "toggle_comment", &["alt-;"]),
+  // the selected rows or the whole file through a shell command
+  (Action::Filter, "filter", &["alt-|"]),
+  // asks for a format, Enter alone takes the configured one
+  (Action::InsertDate, "insert_date", &["f6"]),
+  // in the selected rows or the whole file, also `:uniq` and `:squeeze`
+  (Action::RemoveDuplicateLines, "remove_duplicate_lines", &[]),
+  (Action::SqueezeBlankLines, "squeeze_blank_lines", &[]),
+  // like Emacs' Ctrl-T and Alt-T, which are taken by other actions here
+  (Action::TransposeCharacters, "transpose_characters", &["ctrl-alt-t"]),
+  (Action::TransposeWords, "transpose_words", &["alt-shift-t"]),
+  // the selection or the word at the cursor
+  (Action::Uppercase, "uppercase", &["alt-shift-u"]),
+  (Action::Lowercase, "lowercase", &["alt-shift-l"]),
+  (Action::ToggleCase, "toggle_case", &["alt-~"]),
+  (Action::Titlecase, "titlecase", &["alt-shift-c"]),
+  (Action::DeleteLine, "delete_line", &["alt-d"]),
+  (Action::DuplicateLine, "duplicate_line", &["ctrl-d"]),
+  (Action::MoveLineUp, "move_line_up", &["alt-up"]),
+  (Action::MoveLineDown, "move_line_down", &["alt-down"]),
+  (Action::Copy, "copy", &["alt-6"]),
+  (Action::Paste, "paste", &["ctrl-u", "ctrl-v"]),
+  (Action::DeleteWordBackward, "delete_word_backward", &["ctrl-backspace", "ctrl-h"]),
+  (Action::DeleteWordForward, "delete_word_forward", &["ctrl-delete"]),
+  (Action::WordLeft, "word_left", &["ctrl-left"]),
+  (Action::WordRight, "word_right", &["ctrl-right"]),
+  // the bracket pairing with the one at or after the cursor on its row,
+  // `%` in normal mode
+  (Action::MatchingBracket, "matching_bracket", &["alt-%"]),
+  (Action::DocumentStart, "document_start", &["ctrl-home"]),
+  (Action::DocumentEnd, "document_end", &["ctrl-end"]),
+  // scroll the cursor's row to the middle, top or bottom of the view
+  (Action::CenterView, "center_view", &["alt-c"]),
+  (Action::ViewTop, "view_top", &["alt-,"]),
+  (Action::ViewBottom, "view_bottom", &["alt-."]),
+  (Action::Statistics, "statistics", &["alt-s"]),
+  // digits typed after it give the number of times the next key, like a
+  // move, an edit or replaying a macro, is repeated
+  (Action::RepeatCount, "repeat_count", &["alt-u"]),
+  (Action::StartMacro, "start_macro", &["f3"]),
+  (Action::StopMacro, "stop_macro", &["f4"]),
+  (Action::PlayMacro, "play_macro", &["f5"]),
+  // terminals send Ctrl-I as Tab, so going forward also has Alt-J
+  (Action::JumpBack, "jump_back", &["ctrl-o"]),
+  (Action::JumpForward, "jump_forward", &["ctrl-i", "alt-j"]),
+  (Action::MoveLeft, "move_left", &["left"]),
+  (Action::MoveRight, "move_right", &["right"]),
+  (Action::MoveUp, "move_up", &["up"]),
+  (Action::MoveDown, "move_down", &["down"]),
+  (Action::LineStart, "line_start", &["home"]),
+  (Action::LineEnd, "line_end", &["end"]),
+  (Action::PageUp, "page_up", &["pageup"]),
+  (Action::PageDown, "page_down", &["pagedown"]),
+  // Ctrl-U and Ctrl-D as in Vim are taken by paste and duplicate_line
+  (Action::HalfPageUp, "half_page_up", &["alt-pageup"]),
+  (Action::HalfPageDown, "half_page_down", &["alt-pagedown"]),
+];
+
+type Key = (KeyModifiers, KeyCode);
+
+pub struct Keymap {
+  bindings: HashMap<Key, Action>,
+}
+
+impl Default for Keymap {
+  fn default() -> Self {
+    let mut bindings = HashMap::new();
+    for (action, _, keys) in ACTIONS {
+      for key in *keys {
+        if let Some(key) = parse_key(key) {
+          bindings.insert(key, *action);
+        }
+      }
+    }
+
+    Self { bindings }
+  }
+}
+
+impl Keymap {
+  // the action bound to a key press, keys other than characters also
+  // work with Shift held down
+  pub fn action(&self, event: &KeyEvent) -> Option<Action> {
+    let (modifiers, code) = normalize(event.modifiers, event.code);
+    self.bindings.get(&(modifiers, code)).copied().or_else(|| match code {
+      KeyCode::Char(_) => None,
+      _ => self.bindings.get(&(modifiers - KeyModifiers::SHIFT, code)).copied(),
+    })
+  }
+
+  // the first key bound to `action` written like `Ctrl-C`, for messages
+  pub fn describe(&self, action: Action) -> String {
+    let mut keys: Vec<String> = self.bindings
+      .iter()
+      .filter(|(_, bound)| **bound == action)
+      .map(|(key, _)| describe_key(key))
+      .collect();
+    keys.sort();
+    keys.into_iter().next().unwrap_or_else(|| "(unbound)".to_string())
+  }
+
+  // replaces the keys of `action`, a key taken from another action is
+  // reported as a conflict and goes to `action`
+  fn bind(&mut self, action: Action, keys: &[Key]) -> Vec<String> {
+    let mut conflicts = Vec::new();
+    self.bindings.retain(|_, bound| *bound != action);
+    for key in keys {
+      if let Some(previous) = self.bindings.insert(*key, action) {
+        conflicts.push(format!(
+          "{} is bound to both {} and {}",
+          describe_key(key),
+          name(previous),
+          name(action),
+        ));
+      }
+    }
+    conflicts
+  }
+}
+
+// the default keymap with the overrides from `<config dir>/keys.toml`,
+// which holds lines like `quit = "ctrl-q"` or `paste = ["ctrl-v", "alt-p"]`;
+// mistakes are reported back and leave the defaults in place
+pub fn load() -> (Keymap, Vec<String>) {
+  let mut keymap = Keymap::default();
+  let mut errors = Vec::new();
+  let Some(path) = config::config_dir().map(|dir| dir.join("keys.toml")) else {
+    return (keymap, errors);
+  };
+  let Ok(contents) = fs::read_to_string(&path) else {
+    return (keymap, errors);
+  };
+  let table: toml::Table = match toml::from_str(&contents) {
+    Ok(table) => table,
+    Err(err) => {
+      errors.push(format!("{}: {err}", path.display()));
+      return (keymap, errors);
+    },
+  };
+
+  for (name, value) in &table {
+    let Some(action) = ACTIONS.iter().find(|(_, known, _)| known == name).map(|(action, _, _)| *action) else {
+      errors.push(format!("Unknown action {name}"));
+      continue;
+    };
+    let names: Vec<&str> = match value {
+      toml::Value::String(key) => vec![key.as_str()],
+      toml::Value::Array(keys) => keys.iter().filter_map(toml::Value::as_str).collect(),
+      _ => {
+        errors.push(format!("Keys for {name} should be a string or a list"));
+        continue;
+      },
+    };
+    let mut keys = Vec::new();
+    for key in names {
+      match parse_key(key) {
+        Some(parsed) => keys.push(parsed),
+        None => errors.push(format!("Unknown key {key} for {name}")),
+      }
+    }
+    errors.extend(keymap.bind(action, &keys));
+  }
+
+  (keymap, errors)
+}
+
+fn name(action: Action) -> &'static str {
+  ACTIONS
+    .iter()
+    .find(|(known, _, _)| *known == action)
+    .map_or("", |(_, name, _)| name)
+}
+
+// reads keys like `ctrl-shift-z`, `alt-x`, `enter` or `f5`
+fn parse_key(key: &str) -> Option<Key> {
+  let mut modifiers = KeyModifiers::NONE;
+  let mut rest = key.trim().to_lowercase();
+  loop {
+    let modifier = if rest.starts_with("ctrl-") {
+      KeyModifiers::CONTROL
+    } else if rest.starts_with("alt-") {
+      KeyModifiers::ALT
+    } else if rest.starts_with("shift-") {
+      KeyModifiers::SHIFT
+    } else {
+      break;
+    };
+    modifiers |= modifier;
+    rest = rest[rest.find('-')? + 1..].to_string();
+  }
+
+  let code = match rest.as_str() {
+    "enter" => KeyCode::Enter,
+    "esc" => KeyCode::Esc,
+    "tab" => KeyCode::Tab,
+    "backtab" => KeyCode::BackTab,
+    "backspace" => KeyCode::Backspace,
+    "delete" => KeyCode::Delete,
+    "insert" => KeyCode::Insert,
+    "left" => KeyCode::Left,
+    "right" => KeyCode::Right,
+    "up" => KeyCode::Up,
+    "down" => KeyCode::Down,
+    "home" => KeyCode::Home,
+    "end" => KeyCode::End,
+    "pageup" => KeyCode::PageUp,
+    "pagedown" => KeyCode::PageDown,
+    "space" => KeyCode::Char(' '),
+    function if function.starts_with('f') && function.len() > 1 =>
+      KeyCode::F(function[1..].parse().ok()?),
+    character => {
+      let mut chars = character.chars();
+      match (chars.next(), chars.next()) {
+        (Some(ch), None) => KeyCode::Char(ch),
+        _ => return None,
+      }
+    },
+  };
+
+  Some(normalize(modifiers, code))
+}
+
+// terminals report Shift with a letter either as the modifier, an upper
+// case letter or both, settle on the modifier and a lower case letter;
+// other characters already are what Shift made of them, like `%`, so some
+// terminals' Shift is dropped from them; Shift-Tab arrives as a key of its
+// own
+fn normalize(mut modifiers: KeyModifiers, code: KeyCode) -> Key {
+  match code {
+    KeyCode::BackTab => (modifiers | KeyModifiers::SHIFT, code),
+    KeyCode::Tab if modifiers.contains(KeyModifiers::SHIFT) => (modifiers, KeyCode::BackTab),
+    KeyCode::Char(ch) if ch.is_uppercase() => {
+      modifiers |= KeyModifiers::SHIFT;
+      (modifiers, KeyCode::Char(ch.to_ascii_lowercase()))
+    },
+    KeyCode::Char(ch) if !ch.is_alphabetic() => (modifiers - KeyModifiers::SHIFT, code),
+    _ => (modifiers, code),
+  }
+}
+
+fn describe_key((modifiers, code): &Key) -> String {
+  let mut described = String::new();
+  for (modifier, label) in [
+    (KeyModifiers::CONTROL, "Ctrl-"),
+    (KeyModifiers::ALT, "Alt-"),
+    (KeyModifiers::SHIFT, "Shift-"),
+  ] {
+    if modifiers.contains(modifier) {
+      described.push_str(label);
+    }
+  }
+  let key = match code {
+    KeyCode::Char(' ') => "Space".to_string(),
+    KeyCode::Char(ch) => ch.to_ascii_uppercase().to_string(),
+    KeyCode::F(number) => format!("F{number}"),
+    code => format!("{code:?}"),
+  };
+  described.push_str(&key);
+  described
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn action(modifiers: KeyModifiers, code: KeyCode) -> Option<Action> {
+    Keymap::default().action(&KeyEvent::new(code, modifiers))
+  }
+
+  #[test]
+  fn alt_percent_matches_with_or_without_shift_reported() {
+    assert_eq!(action(KeyModifiers::ALT, KeyCode::Char('%')), Some(Action::MatchingBracket));
+    assert_eq!(action(KeyModifiers::ALT | KeyModifiers::SHIFT, KeyCode::Char('%')), Some(Action::MatchingBracket));
+  }
+
+  #[test]
+  fn shift_still_counts_with_letters() {
+    let shifted = KeyModifiers::ALT | KeyModifiers::SHIFT;
+    assert_eq!(action(shifted, KeyCode::Char('u')), Some(Action::Uppercase));
+    assert_eq!(action(shifted, KeyCode::Char('U')), Some(Action::Uppercase));
+    assert_eq!(action(KeyModifiers::ALT, KeyCode::Char('U')), Some(Action::Uppercase));
+    assert_ne!(action(KeyModifiers::ALT, KeyCode::Char('u')), Some(Action::Uppercase));
+    assert_eq!(action(KeyModifiers::CONTROL | KeyModifiers::SHIFT, KeyCode::Char('z')), Some(Action::Redo));
+  }
+
+  #[test]
+  fn alt_tilde_matches_with_or_without_shift_reported() {
+    assert_eq!(action(KeyModifiers::ALT, KeyCode::Char('~')), Some(Action::ToggleCase));
+    assert_eq!(action(KeyModifiers::ALT | KeyModifiers::SHIFT, KeyCode::Char('~')), Some(Action::ToggleCase));
+  }
+
+  #[test]
+  fn alt_bar_matches_with_or_without_shift_reported() {
+    assert_eq!(action(KeyModifiers::ALT, KeyCode::Char('|')), Some(Action::Filter));
+    assert_eq!(action(KeyModifiers::ALT | KeyModifiers::SHIFT, KeyCode::Char('|')), Some(Action::Filter));
+  }
+}
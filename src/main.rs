@@ -1,26 +1,64 @@
-#![warn(clippy::all, clippy::pedantic, clippy::restriction)]            
+#![warn(clippy::all, clippy::pedantic)]
+#![allow(clippy::missing_errors_doc, clippy::missing_panics_doc, clippy::must_use_candidate)]            
 mod editor;
 
-use core::panic;
+use std::{panic, process};
 
 use editor::Editor;
 mod terminal;
 mod row;
 mod document;
-pub use row::Row;
-pub use document::Document;
-pub use terminal::Terminal;
+mod history;
+mod clipboard;
+mod config;
+mod highlighting;
+mod syntax;
+mod buffer;
+mod pane;
+mod args;
+mod startup;
+mod keymap;
+mod swap;
+mod positions;
+mod shell;
+mod progress;
+mod date;
+mod error;
+pub use row::{RenderOptions, Row};
+pub use document::{Document, LineEnding};
+pub use history::{Operation, UndoStack};
+pub use terminal::{Terminal, TerminalBackend};
 pub use editor::Position;
+pub use config::Config;
+pub use syntax::SyntaxDef;
+pub use buffer::Buffer;
+pub use pane::Pane;
+pub use error::Error;
 
-fn main() -> std::io::Result<()> {
-    let res = Editor::default();
+fn main() {
+    // restore the terminal before the panic message is printed, dropping
+    // `Terminal` while unwinding would come too late
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let _ = terminal::restore();
+        default_hook(info);
+    }));
 
+    let res = Editor::default().and_then(|mut editor| editor.run());
+
+    // the terminal is restored by now, so the message stays readable;
+    // exiting with 0 for a clean quit, 1 for an error and 2 if changes
+    // were thrown away
     match res {
+        Ok(status) => {
+            if let Some(summary) = status.summary() {
+                eprintln!("slime: {summary}");
+            }
+            process::exit(status.code());
+        },
         Err(err) => {
-            panic!("{}", err)
+            eprintln!("slime: {err}");
+            process::exit(1);
         },
-        Ok(mut editor) => {
-            editor.run()
-        }
-    }    
+    }
 }
\ No newline at end of file
@@ -0,0 +1,10 @@
+use crate::Position;
+
+// a window onto one of the buffers; the active pane's cursor and offset
+// are those of the buffer it shows
+#[derive(Clone, Default)]
+pub struct Pane {
+  pub buffer: usize,
+  pub cursor_position: Position<usize>,
+  pub cursor_offset: Position<usize>,
+}
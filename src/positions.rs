@@ -0,0 +1,60 @@
+use std::fmt::Write;
+use std::fs;
+
+use crate::config;
+use crate::Position;
+
+// where the cursor was left in recently edited files, kept in
+// `<config dir>/positions` as `line column path` lines, newest first
+const FILE_NAME: &str = "positions";
+const LIMIT: usize = 100;
+
+fn read() -> Vec<(String, Position<usize>)> {
+  let Some(contents) = config::config_dir().and_then(|dir| fs::read_to_string(dir.join(FILE_NAME)).ok()) else {
+    return Vec::new();
+  };
+  contents
+    .lines()
+    .filter_map(|line| {
+      let mut parts = line.splitn(3, ' ');
+      let y = parts.next()?.parse().ok()?;
+      let x = parts.next()?.parse().ok()?;
+      Some((parts.next()?.to_string(), Position { x, y }))
+    })
+    .collect()
+}
+
+// the position remembered for a file
+pub fn find(path: &str) -> Option<Position<usize>> {
+  let path = canonical(path)?;
+  read().into_iter().find(|(known, _)| *known == path).map(|(_, position)| position)
+}
+
+// puts the given positions first, dropping files that no longer exist
+// and the oldest ones past the limit
+pub fn remember(positions: &[(String, Position<usize>)]) -> Result<(), std::io::Error> {
+  let Some(dir) = config::config_dir() else {
+    return Ok(());
+  };
+  let mut entries: Vec<(String, Position<usize>)> = positions
+    .iter()
+    .filter_map(|(path, position)| Some((canonical(path)?, position.clone())))
+    .collect();
+  for entry in read() {
+    if !entries.iter().any(|(path, _)| *path == entry.0) && fs::metadata(&entry.0).is_ok() {
+      entries.push(entry);
+    }
+  }
+  entries.truncate(LIMIT);
+
+  let mut contents = String::new();
+  for (path, position) in &entries {
+    let _ = writeln!(contents, "{} {} {path}", position.y, position.x);
+  }
+  fs::create_dir_all(&dir)?;
+  fs::write(dir.join(FILE_NAME), contents)
+}
+
+fn canonical(path: &str) -> Option<String> {
+  fs::canonicalize(path).ok().map(|path| path.to_string_lossy().into_owned())
+}
@@ -0,0 +1,59 @@
+use std::time::{Duration, Instant};
+
+use crate::TerminalBackend;
+
+// operations finishing sooner than this show nothing
+const PROGRESS_DELAY: Duration = Duration::from_millis(200);
+// the message line is redrawn at most this often
+const PROGRESS_FRAME: Duration = Duration::from_millis(100);
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+// how far a slow operation got, drawn on the message line while the editor
+// is busy with it instead of its own loop; the next refresh draws the
+// status message over it again
+pub struct Progress<'a, T: TerminalBackend> {
+  terminal: &'a mut T,
+  label: String,
+  started: Instant,
+  drawn_at: Option<Instant>,
+  frame: usize,
+}
+
+impl<'a, T: TerminalBackend> Progress<'a, T> {
+  pub fn new(terminal: &'a mut T, label: String) -> Self {
+    Self { terminal, label, started: Instant::now(), drawn_at: None, frame: 0 }
+  }
+
+  // a spinner for work whose length isn't known
+  pub fn tick(&mut self) {
+    self.draw(None);
+  }
+
+  // the percentage of `total` bytes done, a spinner while `total` is 0
+  pub fn advance(&mut self, done: u64, total: u64) {
+    self.draw(done.saturating_mul(100).checked_div(total).map(|percent| percent.min(100)));
+  }
+
+  fn draw(&mut self, percent: Option<u64>) {
+    if self.started.elapsed() < PROGRESS_DELAY || self.drawn_at.is_some_and(|at| at.elapsed() < PROGRESS_FRAME) {
+      return;
+    }
+    self.drawn_at = Some(Instant::now());
+    self.frame = (self.frame + 1) % SPINNER.len();
+    let mut text = match percent {
+      Some(percent) => format!("{} {} {percent}%", SPINNER[self.frame], self.label),
+      None => format!("{} {}", SPINNER[self.frame], self.label),
+    };
+    if let Some((end, _)) = text.char_indices().nth(self.terminal.size().width as usize) {
+      text.truncate(end);
+    }
+    // a failed frame is left for the next one or the refresh after
+    let _ = (|| {
+      self.terminal.hide_cursor()?;
+      self.terminal.move_cursor(0, self.terminal.size().height.saturating_sub(1))?;
+      self.terminal.clear_current_line()?;
+      self.terminal.print_string(&text)?;
+      self.terminal.flush()
+    })();
+  }
+}
@@ -1,81 +1,514 @@
+use std::cell::RefCell;
 use std::cmp::{self};
+use std::ops::Range;
+use crossterm::Command;
+use crossterm::style::{Attribute, Color, SetBackgroundColor, SetForegroundColor};
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
-use crate::editor::SearchDir;
+use crate::editor::{SearchDir, SearchOptions};
+use crate::highlighting;
+use crate::SyntaxDef;
+
+// what counts as whitespace at the end of a row
+pub const TRAILING_WHITESPACE: [char; 2] = [' ', '\t'];
+
+// rows with more bytes than this keep an index of their graphemes, shorter
+// ones are segmented again when asked, which costs less than the index
+const INDEX_THRESHOLD: usize = 512;
+
+const INVISIBLE_FG: Color = Color::DarkGrey;
+const GUIDE_BG: Color = Color::Rgb { r: 60, g: 60, b: 60 };
+
+// how rows are drawn besides their text
+#[derive(Clone, Copy)]
+pub struct RenderOptions<'a> {
+  // tabs are expanded to the next multiple of this
+  pub tab_width: usize,
+  // spaces, tabs and row ends are shown as dim `·`, `→` and `¬`, only on
+  // screen
+  pub invisibles: bool,
+  // columns counted from 1 marked with a background, in increasing order
+  pub guides: &'a [usize],
+}
 
 #[derive(Default, Clone)]
 pub struct Row {
   string: String,
   len: usize,
+  // byte offset of every grapheme followed by the length of the string,
+  // so long rows are indexed without segmenting them again; empty for
+  // rows up to `INDEX_THRESHOLD` bytes
+  boundaries: Vec<usize>,
+  // for indexed rows, screen column of every grapheme and of the row end
+  // for the tab width they were counted with; an edit drops the ones after
+  // it and they are counted again when next asked for
+  columns: RefCell<Option<(usize, Vec<usize>)>>,
+  highlighting: Vec<highlighting::Type>,
+  // whether a block comment is still open at the end of the row
+  ends_in_comment: bool,
 }
 
 impl Row {
-  pub fn render(&self, start: usize, end: usize) -> String {
-    let end = cmp::min(end, self.string.len());
-    let start = cmp::min(start, end);
+  // `start` and `end` are screen columns; `highlight` is a range of
+  // graphemes drawn with inverted colors, `marks` are ranges of graphemes
+  // drawn over a background color, later ones on top
+  pub fn render(
+    &self,
+    start: usize,
+    end: usize,
+    options: RenderOptions<'_>,
+    highlight: Option<&Range<usize>>,
+    marks: &[(Range<usize>, Color)],
+  ) -> String {
+    self.render_segment(0..self.len, start, end, options, highlight, marks)
+  }
+  // renders the graphemes in `segment` like `render`, with the columns
+  // counted from the start of the segment
+  pub fn render_segment(
+    &self,
+    segment: Range<usize>,
+    start: usize,
+    end: usize,
+    options: RenderOptions<'_>,
+    highlight: Option<&Range<usize>>,
+    marks: &[(Range<usize>, Color)],
+  ) -> String {
+    let tab_width = options.tab_width.max(1);
     let mut result = String::new();
-    for grapheme in self.string[..]
-      .graphemes(true)
-      .skip(start)
-      .take(end - start)
-    {
-      if grapheme == "\t" {
-        result.push_str(" ")
+    let mut highlighted = false;
+    let mut current_background = Color::Reset;
+    let mut current_type = highlighting::Type::None;
+    let (first, mut column) = self.first_shown(&segment, start, tab_width);
+    let graphemes = (first..).zip(self.graphemes_from(first)).take(segment.end.saturating_sub(first));
+    for (index, grapheme) in graphemes {
+      if column >= end {
+        break;
+      }
+      let width = grapheme_width(grapheme, column, tab_width);
+      let visible = column.max(start)..cmp::min(column + width, end);
+      column += width;
+      if visible.is_empty() {
+        continue;
+      }
+
+      let in_highlight = highlight.is_some_and(|range| range.contains(&index));
+      if in_highlight != highlighted {
+        let attribute = if in_highlight { Attribute::Reverse } else { Attribute::NoReverse };
+        result.push_str(&attribute.to_string());
+        highlighted = in_highlight;
+      }
+      let on_guide = options.guides.iter().any(|guide| (column - width..column).contains(&(guide - 1)));
+      let background = marks
+        .iter()
+        .rev()
+        .find(|(range, _)| range.contains(&index))
+        .map_or(if on_guide { GUIDE_BG } else { Color::Reset }, |(_, color)| *color);
+      if background != current_background {
+        push_background(&mut result, background);
+        current_background = background;
+      }
+      let highlighting_type = self.highlighting.get(index).copied().unwrap_or_default();
+      if highlighting_type != current_type {
+        push_color(&mut result, highlighting_type.to_color());
+        current_type = highlighting_type;
+      }
+      if options.invisibles && (grapheme == " " || grapheme == "\t") {
+        push_color(&mut result, INVISIBLE_FG);
+        // the arrow goes on the first column of a tab, if it is shown
+        let first_shown = visible.start == column - width;
+        if grapheme == " " {
+          result.push('·');
+        } else if first_shown {
+          result.push('→');
+          result.push_str(&" ".repeat(visible.len() - 1));
+        } else {
+          result.push_str(&" ".repeat(visible.len()));
+        }
+        push_color(&mut result, current_type.to_color());
+      } else if grapheme == "\t" || visible.len() < width {
+        // tabs and wide characters cut by the window edges are padded instead
+        result.push_str(&" ".repeat(visible.len()));
       } else {
         result.push_str(grapheme);
       }      
     }
+    if highlighted {
+      result.push_str(&Attribute::NoReverse.to_string());
+    }
+    if current_background != Color::Reset {
+      push_background(&mut result, Color::Reset);
+    }
+    let row_end_shown = segment.end == self.len && (start..end).contains(&column);
+    // a mark just past the text, like a cursor at the row end, colors the
+    // cell after it
+    let end_mark = marks
+      .iter()
+      .rev()
+      .find(|(range, _)| range.contains(&self.len))
+      .filter(|_| row_end_shown)
+      .map(|(_, color)| *color);
+    if let Some(color) = end_mark {
+      push_background(&mut result, color);
+    }
+    if options.invisibles && row_end_shown {
+      push_color(&mut result, INVISIBLE_FG);
+      result.push('¬');
+      push_color(&mut result, Color::Reset);
+    } else {
+      if current_type != highlighting::Type::None {
+        push_color(&mut result, Color::Reset);
+      }
+      if end_mark.is_some() {
+        result.push(' ');
+      }
+    }
+    if end_mark.is_some() {
+      push_background(&mut result, Color::Reset);
+    }
+    // guides past the end of the text go on blank cells
+    let mut filled = column.max(start) + usize::from(row_end_shown && (options.invisibles || end_mark.is_some()));
+    for guide in options.guides.iter().map(|guide| guide - 1) {
+      if guide < filled || guide >= end {
+        continue;
+      }
+      result.push_str(&" ".repeat(guide - filled));
+      push_background(&mut result, GUIDE_BG);
+      result.push(' ');
+      push_background(&mut result, Color::Reset);
+      filled = guide + 1;
+    }
     result
   }
+  // the grapheme `render_segment` starts from and its column; a whole row
+  // starts at the first one in view, so a long one isn't walked from its
+  // beginning
+  fn first_shown(&self, segment: &Range<usize>, start: usize, tab_width: usize) -> (usize, usize) {
+    if segment.start == 0 {
+      let first = self.rx_to_cx(start, tab_width).min(segment.end);
+      (first, self.with_columns(tab_width, |columns| columns[first]))
+    } else {
+      (segment.start, 0)
+    }
+  }
+  // colors the row and returns whether a block comment remains open
+  pub fn highlight(&mut self, syntax: Option<&SyntaxDef>, start_in_comment: bool) -> bool {
+    let string = &self.string;
+    let (offsets, graphemes): (Vec<usize>, Vec<&str>) = string.grapheme_indices(true).unzip();
+    let mut highlighting = vec![highlighting::Type::None; graphemes.len()];
+    let mut in_comment = false;
+
+    if let Some(syntax) = syntax {
+      let starts_with = |index: usize, token: &str| !token.is_empty() && string[offsets[index]..].starts_with(token);
+      let token_len = |token: &str| token.graphemes(true).count();
+      let is_separator = |grapheme: &&str| {
+        *grapheme != "_" && grapheme.chars().all(|ch| ch.is_ascii_punctuation() || ch.is_whitespace())
+      };
+
+      in_comment = start_in_comment;
+      let mut index = 0;
+      while index < graphemes.len() {
+        if let Some((start, end)) = &syntax.block_comment {
+          let len = if in_comment && starts_with(index, end) {
+            in_comment = false;
+            token_len(end)
+          } else if in_comment {
+            1
+          } else if starts_with(index, start) {
+            in_comment = true;
+            token_len(start)
+          } else {
+            0
+          };
+          if len > 0 {
+            highlighting[index..index + len].fill(highlighting::Type::Comment);
+            index += len;
+            continue;
+          }
+        }
+
+        if syntax.line_comment.as_deref().is_some_and(|marker| starts_with(index, marker)) {
+          highlighting[index..].fill(highlighting::Type::Comment);
+          break;
+        }
+
+        if let Some(delimiter) = syntax.string_delimiters.iter().find(|delimiter| starts_with(index, delimiter)) {
+          let mut end = index + token_len(delimiter);
+          while end < graphemes.len() {
+            if graphemes[end] == "\\" {
+              end += 2;
+            } else if starts_with(end, delimiter) {
+              end += token_len(delimiter);
+              break;
+            } else {
+              end += 1;
+            }
+          }
+          let end = end.min(graphemes.len());
+          highlighting[index..end].fill(highlighting::Type::String);
+          index = end;
+          continue;
+        }
+
+        // only whole words are colored
+        if index > 0 && !is_separator(&graphemes[index - 1]) {
+          index += 1;
+          continue;
+        }
+        let word_len = graphemes[index..].iter().take_while(|grapheme| !is_separator(grapheme)).count();
+        if word_len == 0 {
+          index += 1;
+          continue;
+        }
+        let word = graphemes[index..index + word_len].concat();
+        let highlighting_type = if syntax.highlight_numbers && word.starts_with(|ch: char| ch.is_ascii_digit()) {
+          highlighting::Type::Number
+        } else if syntax.keywords.contains(&word) {
+          highlighting::Type::Keyword
+        } else if syntax.types.contains(&word) {
+          highlighting::Type::Builtin
+        } else {
+          highlighting::Type::None
+        };
+        highlighting[index..index + word_len].fill(highlighting_type);
+        index += word_len;
+      }
+    }
+
+    self.highlighting = highlighting;
+    self.ends_in_comment = in_comment;
+    in_comment
+  }
+  pub fn ends_in_comment(&self) -> bool {
+    self.ends_in_comment
+  }
+  // converts a grapheme index into the screen column it is rendered at
+  pub fn cx_to_rx(&self, cx: usize, tab_width: usize) -> usize {
+    self.with_columns(tab_width, |columns| columns[cx.min(self.len)])
+  }
+  // converts a screen column into the index of the grapheme covering it
+  pub fn rx_to_cx(&self, rx: usize, tab_width: usize) -> usize {
+    // the first grapheme ending past `rx`
+    self.with_columns(tab_width, |columns| columns[1..].partition_point(|&end| end <= rx))
+  }
+  // grapheme indices where the screen lines of the row start when it is
+  // wrapped at `width` columns, breaking after whitespace where possible;
+  // a row that fills its last line ends with an empty one for the cursor
+  pub fn wrap_starts(&self, width: usize, tab_width: usize) -> Vec<usize> {
+    let (width, tab_width) = (width.max(1), tab_width.max(1));
+    let graphemes: Vec<&str> = self.graphemes_from(0).collect();
+    let mut starts = vec![0];
+    let mut column = 0;
+    let mut break_at = 0;
+    for (index, grapheme) in graphemes.iter().enumerate() {
+      let start = starts[starts.len() - 1];
+      if column + grapheme_width(grapheme, column, tab_width) > width && index > start {
+        // a word longer than the line is cut where it overflows
+        let next = if break_at > start { break_at } else { index };
+        starts.push(next);
+        column = graphemes[next..index]
+          .iter()
+          .fold(0, |column, grapheme| column + grapheme_width(grapheme, column, tab_width));
+      }
+      column += grapheme_width(grapheme, column, tab_width);
+      if grapheme.chars().all(char::is_whitespace) {
+        break_at = index + 1;
+      }
+    }
+    if column >= width && self.len > 0 {
+      starts.push(self.len);
+    }
+    starts
+  }
+  // screen columns taken by the graphemes `from..to`, counted from `from`
+  pub fn segment_width(&self, from: usize, to: usize, tab_width: usize) -> usize {
+    let tab_width = tab_width.max(1);
+    self.graphemes_from(from)
+      .take(to.saturating_sub(from))
+      .fold(0, |rx, grapheme| rx + grapheme_width(grapheme, rx, tab_width))
+  }
+  // the grapheme of `segment` covering a column counted from its start,
+  // past its end the last one
+  pub fn segment_cx(&self, segment: Range<usize>, rx: usize, tab_width: usize) -> usize {
+    let tab_width = tab_width.max(1);
+    let mut current_rx = 0;
+    for (cx, grapheme) in (segment.start..).zip(self.graphemes_from(segment.start)).take(segment.len()) {
+      current_rx += grapheme_width(grapheme, current_rx, tab_width);
+      if current_rx > rx {
+        return cx;
+      }
+    }
+    segment.end
+  }
+  // number of screen columns the whole row takes
+  pub fn width(&self, tab_width: usize) -> usize {
+    self.cx_to_rx(self.len, tab_width)
+  }
+  // number of graphemes, kept up to date by every edit
   pub fn size(&self) -> usize {
-    self.string[..].graphemes(true).count()
+    self.len
+  }
+  // graphemes of the spaces and tabs the row ends with
+  pub fn trailing_whitespace(&self) -> Range<usize> {
+    let text = self.string.trim_end_matches(TRAILING_WHITESPACE);
+    self.index_at(text.len())..self.len
+  }
+  // number of spaces and tabs the row starts with, in graphemes, so a
+  // space carrying a combining mark isn't counted
+  pub fn leading_whitespace(&self) -> usize {
+    self.graphemes_from(0).take_while(|grapheme| matches!(*grapheme, " " | "\t")).count()
   }
   pub fn insert(&mut self, at: usize, ch: char) {
-    if at >= self.len {
-      self.string.push(ch);      
-    } else {
-      let mut result: String = self.string[..].graphemes(true).take(at).collect();
-      let remainder: String = self.string[..].graphemes(true).skip(at).collect();
-      result.push(ch);
-      result.push_str(&remainder);
-      self.string = result;
-    }
-    self.update_len();
+    let offset = self.byte_offset(at);
+    self.string.insert(offset, ch);
+    self.update_boundaries(offset, 0, ch.len_utf8());
   }
   pub fn insert_str(&mut self, at: usize, s: &str) {
-    if at >= self.len {
-      self.string.push_str(s);      
-    } else {
-      let mut result: String = self.string[..].graphemes(true).take(at).collect();
-      let remainder: String = self.string[..].graphemes(true).skip(at).collect();
-      result.push_str(s);
-      result.push_str(&remainder);      
+    if s.is_empty() {
+      return;
     }
-    self.update_len();
+    let offset = self.byte_offset(at);
+    self.string.insert_str(offset, s);
+    self.update_boundaries(offset, 0, s.len());
   }
   pub fn delete(&mut self, at: usize) {
     if at < self.len {
-      let mut result: String = self.string[..].graphemes(true).take(at).collect();
-      let remainder: String = self.string[..].graphemes(true).skip(at + 1).collect();
-      result.push_str(&remainder);
-      self.string = result;
-      self.update_len();
+      let start = self.byte_offset(at);
+      let end = self.byte_offset(at + 1);
+      self.string.replace_range(start..end, "");
+      self.update_boundaries(start, end - start, 0);
     }
   }
+  // removes the graphemes in `from..to`, `to` being an exclusive end index
+  // and not a length, and returns them
   pub fn delete_slice(&mut self, from: usize, to: usize) -> Option<String> {
     if to > from && to <= self.len {
-      let removed_part: String = self.string[..].graphemes(true).skip(from).take(to - from).collect();
-      let mut result: String = self.string[..].graphemes(true).take(from).collect();
-      let remainder: String = self.string[..].graphemes(true).skip(from + to - from).collect();
-      result.push_str(&remainder);
-      self.string = result;
-      self.update_len();
+      let start = self.byte_offset(from);
+      let end = self.byte_offset(to);
+      let removed_part = self.string.drain(start..end).collect();
+      self.update_boundaries(start, end - start, 0);
 
       return Some(removed_part);
     }
 
     None
   }
+  pub fn grapheme(&self, at: usize) -> Option<&str> {
+    self.graphemes_from(at).next()
+  }
+  // end of the word or punctuation run after `at`, skipping whitespace
+  pub fn next_word_end(&self, at: usize) -> usize {
+    let classes: Vec<CharClass> = self.graphemes_from(0).map(CharClass::of).collect();
+    let mut index = at.min(classes.len());
+    while index < classes.len() && classes[index] == CharClass::Whitespace {
+      index += 1;
+    }
+    if let Some(&class) = classes.get(index) {
+      while index < classes.len() && classes[index] == class {
+        index += 1;
+      }
+    }
+    index
+  }
+  // start of the word or punctuation run before `at`, skipping whitespace
+  pub fn prev_word_start(&self, at: usize) -> usize {
+    let classes: Vec<CharClass> = self.graphemes_from(0).map(CharClass::of).collect();
+    let mut index = at.min(classes.len());
+    while index > 0 && classes[index - 1] == CharClass::Whitespace {
+      index -= 1;
+    }
+    if let Some(&class) = index.checked_sub(1).map(|prev| &classes[prev]) {
+      while index > 0 && classes[index - 1] == class {
+        index -= 1;
+      }
+    }
+    index
+  }
+  // swaps the grapheme before `at` with the one at it, or the last two at
+  // the end of the row, and returns the index after them; `None` at the
+  // start of the row or with fewer than two graphemes
+  pub fn transpose(&mut self, at: usize) -> Option<usize> {
+    if at == 0 || self.len < 2 {
+      return None;
+    }
+    let first = at.min(self.len - 1) - 1;
+    let second = self.delete_slice(first + 1, first + 2)?;
+    self.insert_str(first, &second);
+    Some(first + 2)
+  }
+  // swaps the word before `at` with the one after it, a word `at` is in
+  // with the next and the last two at the end of the row; what is between
+  // them stays, the index after the pair is returned
+  pub fn transpose_words(&mut self, at: usize) -> Option<usize> {
+    let words = self.words();
+    let next = words.iter().position(|word| word.end > at).unwrap_or(words.len());
+    let second = match words.get(next) {
+      Some(word) if word.start < at => next + 1,
+      _ => next.min(words.len().saturating_sub(1)).max(1),
+    };
+    let (first, second) = (words.get(second - 1)?.clone(), words.get(second)?.clone());
+    let second_text = self.delete_slice(second.start, second.end)?;
+    let first_text = self.delete_slice(first.start, first.end)?;
+    self.insert_str(second.start - first.len(), &first_text);
+    self.insert_str(first.start, &second_text);
+    Some(second.end)
+  }
+  // the word `at` is in or right after
+  pub fn word_at(&self, at: usize) -> Option<Range<usize>> {
+    self.words().into_iter().find(|word| word.start <= at && at <= word.end)
+  }
+  // the graphemes of every run of word characters
+  fn words(&self) -> Vec<Range<usize>> {
+    let mut words: Vec<Range<usize>> = Vec::new();
+    for (index, grapheme) in self.graphemes_from(0).enumerate() {
+      if CharClass::of(grapheme) != CharClass::Word {
+        continue;
+      }
+      match words.last_mut() {
+        Some(word) if word.end == index => word.end = index + 1,
+        _ => words.push(index..index + 1),
+      }
+    }
+    words
+  }
+  // changes the case of the graphemes in `range` and returns where the
+  // range ends afterwards, as a letter can turn into more than one
+  pub fn map_case(&mut self, range: Range<usize>, case: Case) -> usize {
+    let range = range.start.min(self.len)..range.end.min(self.len);
+    if range.is_empty() {
+      return range.end;
+    }
+    let text = &self.string[self.byte_offset(range.start)..self.byte_offset(range.end)];
+    let mapped = match case {
+      Case::Upper => text.to_uppercase(),
+      Case::Lower => text.to_lowercase(),
+      Case::Toggle => text.chars().map(|ch| {
+        if ch.is_uppercase() { ch.to_lowercase().to_string() } else { ch.to_uppercase().to_string() }
+      }).collect(),
+      Case::Title => {
+        // a range starting inside a word doesn't start a new one
+        let mut in_word = range.start > 0
+          && self.grapheme(range.start - 1).is_some_and(|grapheme| CharClass::of(grapheme) == CharClass::Word);
+        let mut mapped = String::new();
+        for grapheme in text.graphemes(true) {
+          let word = CharClass::of(grapheme) == CharClass::Word;
+          let mut chars = grapheme.chars();
+          if word && !in_word {
+            mapped.extend(chars.next().into_iter().flat_map(char::to_uppercase));
+          }
+          mapped.extend(chars.flat_map(char::to_lowercase));
+          in_word = word;
+        }
+        mapped
+      },
+    };
+    if mapped == text {
+      return range.end;
+    }
+    self.delete_slice(range.start, range.end);
+    self.insert_str(range.start, &mapped);
+    range.start + mapped.graphemes(true).count()
+  }
   pub fn string(&self) -> &str {
     &self.string
   }
@@ -83,55 +516,208 @@ impl Row {
     self.string.as_bytes()
   }
 
-  pub fn find(&self, query: &str, at: usize, direction: SearchDir) -> Option<usize> {    
+  // grapheme index of the first match starting at or after `at`, or of
+  // the last one starting before `at` going backward; the row and
+  // the query are compared grapheme by grapheme, so multibyte text never
+  // needs byte offsets converted
+  pub fn find(&self, query: &str, at: usize, direction: SearchDir, options: SearchOptions) -> Option<usize> {    
     if at > self.len {
       return None;
     }
 
-    let start = if direction == SearchDir::Forward {
-      at
-    } else {
-      0
+    let graphemes: Vec<&str> = self.graphemes_from(0).collect();
+    let query: Vec<&str> = query.graphemes(true).collect();
+    if query.is_empty() || query.len() > graphemes.len() {
+      return None;
+    }
+    let last_start = graphemes.len() - query.len();
+
+    let matches_at = |index: usize| {
+      let matched = graphemes[index..index + query.len()]
+        .iter()
+        .zip(&query)
+        .all(|(grapheme, expected)| {
+          if options.case_sensitive {
+            grapheme == expected
+          } else {
+            grapheme.to_lowercase() == expected.to_lowercase()
+          }
+        });
+      if !matched || !options.whole_word {
+        return matched;
+      }
+      let is_word = |grapheme: &&str| grapheme.chars().any(char::is_alphanumeric);
+      let before = index.checked_sub(1).and_then(|before| graphemes.get(before));
+      let after = graphemes.get(index + query.len());
+      !before.is_some_and(is_word) && !after.is_some_and(is_word)
     };
 
-    let end = if direction == SearchDir::Forward {
-      self.len
-    } else {
-      at.saturating_add(1)
-    };    
-
-    let substr: String = self.string[..]
-      .graphemes(true)
-      .skip(start)
-      .take(end - start)
-      .collect();    
-    let index = if direction == SearchDir::Forward {
-      substr.find(query)
+    if direction == SearchDir::Forward {
+      (at..=last_start).find(|&index| matches_at(index))
     } else {
-      substr.rfind(query)
-    };
+      // a match at `at` itself is the one the cursor is already on
+      (0..at.min(last_start + 1)).rev().find(|&index| matches_at(index))
+    }
+  }
 
-    if let Some(byte_index) = index {
-      for (grapheme_index, (index, _)) in substr[..].grapheme_indices(true).enumerate() {
-        if byte_index == index {
-          return Some(start + grapheme_index);
+  fn is_indexed(&self) -> bool {
+    !self.boundaries.is_empty()
+  }
+  // byte offset of the grapheme at index `at`, or the end of the string
+  // past the last one, so edits can splice the string in place
+  fn byte_offset(&self, at: usize) -> usize {
+    if self.is_indexed() {
+      return self.boundaries.get(at).copied().unwrap_or(self.string.len());
+    }
+    self.string.grapheme_indices(true).nth(at).map_or(self.string.len(), |(offset, _)| offset)
+  }
+  // index of the grapheme starting at or after byte `offset`
+  fn index_at(&self, offset: usize) -> usize {
+    if self.is_indexed() {
+      return self.boundaries.partition_point(|&boundary| boundary < offset).min(self.len);
+    }
+    self.string.grapheme_indices(true).take_while(|(start, _)| *start < offset).count()
+  }
+  // the graphemes from index `start` on
+  fn graphemes_from(&self, start: usize) -> impl Iterator<Item = &str> + '_ {
+    let indexed = self.boundaries
+      .get(start..)
+      .unwrap_or_default()
+      .windows(2)
+      .map(|pair| &self.string[pair[0]..pair[1]]);
+    let segmented = (!self.is_indexed()).then(|| self.string.graphemes(true).skip(start));
+    indexed.chain(segmented.into_iter().flatten())
+  }
+  // calls `f` with `columns` for `tab_width`, counted again if it
+  // changed; a row without an index counts them for this call only
+  fn with_columns<R>(&self, tab_width: usize, f: impl FnOnce(&[usize]) -> R) -> R {
+    let tab_width = tab_width.max(1);
+    let mut cached = self.columns.borrow_mut();
+    let mut uncached = None;
+    let columns = if self.is_indexed() { &mut *cached } else { &mut uncached };
+    if columns.as_ref().is_some_and(|(width, _)| *width != tab_width) {
+      *columns = None;
+    }
+    let (_, columns) = columns.get_or_insert_with(|| (tab_width, vec![0]));
+    let counted = columns.len() - 1;
+    let mut column = columns[counted];
+    for grapheme in self.graphemes_from(counted) {
+      column += grapheme_width(grapheme, column, tab_width);
+      columns.push(column);
+    }
+    f(columns)
+  }
+  // fixes up `boundaries` after the bytes `start..start + removed` were
+  // replaced by `inserted` bytes: the text is segmented again from the
+  // grapheme before the edit until a boundary lines up with an old one,
+  // the ones after that only move
+  fn update_boundaries(&mut self, start: usize, removed: usize, inserted: usize) {
+    if !self.is_indexed() {
+      self.update_len();
+      return;
+    }
+    let first = self.index_at(start).saturating_sub(1);
+    let from = self.boundaries[first];
+    let mut old = first;
+    let mut rest = self.boundaries.len();
+    let mut segmented = Vec::new();
+    for (offset, _) in self.string[from..].grapheme_indices(true) {
+      let boundary = from + offset;
+      if boundary >= start + inserted {
+        let before_edit = boundary - inserted + removed;
+        while self.boundaries[old] < before_edit {
+          old += 1;
+        }
+        if self.boundaries[old] == before_edit {
+          rest = old;
+          break;
         }
       }
+      segmented.push(boundary);
+    }
+    if rest == self.boundaries.len() {
+      segmented.push(self.string.len());
+    }
+    let moved = first + segmented.len();
+    self.boundaries.splice(first..rest, segmented);
+    for boundary in &mut self.boundaries[moved..] {
+      *boundary = *boundary - removed + inserted;
+    }
+    self.len = self.boundaries.len() - 1;
+    if let Some((_, columns)) = self.columns.get_mut() {
+      columns.truncate(first + 1);
     }
-    
-    None
   }
-
+  // counts the graphemes again, indexing them if the row is long enough
   fn update_len(&mut self) {
-    self.len = self.string[..].graphemes(true).count();
+    *self.columns.get_mut() = None;
+    if self.string.len() <= INDEX_THRESHOLD {
+      self.boundaries = Vec::new();
+      self.len = self.string.graphemes(true).count();
+      return;
+    }
+    self.boundaries = self.string
+      .grapheme_indices(true)
+      .map(|(offset, _)| offset)
+      .chain([self.string.len()])
+      .collect();
+    self.len = self.boundaries.len() - 1;
   }
 }
 
+// what `map_case` turns letters into, title case starts every word with
+// a capital and lowers the rest
+#[derive(Clone, Copy)]
+pub enum Case {
+  Upper,
+  Lower,
+  Toggle,
+  Title,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum CharClass {
+  Whitespace,
+  Word,
+  Punctuation,
+}
+
+impl CharClass {
+  fn of(grapheme: &str) -> Self {
+    if grapheme.chars().all(char::is_whitespace) {
+      CharClass::Whitespace
+    } else if grapheme.chars().any(|ch| ch.is_alphanumeric() || ch == '_') {
+      CharClass::Word
+    } else {
+      CharClass::Punctuation
+    }
+  }
+}
+
+// screen columns taken by a grapheme rendered at `column`; wide CJK and
+// emoji take two, combining marks and joiners add nothing
+fn grapheme_width(grapheme: &str, column: usize, tab_width: usize) -> usize {
+  if grapheme == "\t" {
+    tab_width - column % tab_width
+  } else {
+    grapheme.width()
+  }
+}
+
+fn push_color(result: &mut String, color: Color) {
+  // writing into a String can't fail
+  let _ = SetForegroundColor(color).write_ansi(result);
+}
+
+fn push_background(result: &mut String, color: Color) {
+  let _ = SetBackgroundColor(color).write_ansi(result);
+}
+
 impl From<String> for Row {
   fn from(string: String) -> Row {
     let mut row = Self {
       string,
-      len: 0,
+      ..Self::default()
     };
 
     row.update_len();
@@ -144,7 +730,7 @@ impl From<&str> for Row {
   fn from(slice: &str) -> Row {
     let mut row = Self {
       string: String::from(slice),
-      len: 0,
+      ..Self::default()
     };
 
     row.update_len();
@@ -152,3 +738,215 @@ impl From<&str> for Row {
     row
   }  
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn tabs_widen_to_the_next_tab_stop() {
+    let row = Row::from("\ta \tb");
+    let columns: Vec<usize> = (0..=row.size()).map(|cx| row.cx_to_rx(cx, 4)).collect();
+    assert_eq!(columns, [0, 4, 5, 6, 8, 9]);
+  }
+
+  #[test]
+  fn a_tab_after_spaces_ends_at_the_same_stop() {
+    let row = Row::from("  \tx");
+    assert_eq!(row.cx_to_rx(3, 4), 4);
+    assert_eq!(row.cx_to_rx(3, 8), 8);
+    assert_eq!(row.width(4), 5);
+  }
+
+  #[test]
+  fn every_column_of_a_tab_maps_back_to_it() {
+    let row = Row::from(" \t\tx");
+    assert_eq!((0..10).map(|rx| row.rx_to_cx(rx, 4)).collect::<Vec<_>>(), [0, 1, 1, 1, 2, 2, 2, 2, 3, 4]);
+    // past the row end is the row end
+    assert_eq!(row.rx_to_cx(40, 4), row.size());
+  }
+
+  #[test]
+  fn columns_follow_a_changed_tab_width() {
+    let row = Row::from("\tx");
+    assert_eq!(row.cx_to_rx(1, 4), 4);
+    assert_eq!(row.cx_to_rx(1, 2), 2);
+  }
+
+  #[test]
+  fn insert_str_in_the_middle_keeps_the_rest() {
+    let mut row = Row::from("hello world");
+    row.insert_str(5, ",");
+    assert_eq!(row.string(), "hello, world");
+    assert_eq!(row.size(), 12);
+    row.insert_str(0, "> ");
+    row.insert_str(row.size(), "!");
+    assert_eq!(row.string(), "> hello, world!");
+    assert_eq!(row.size(), 15);
+  }
+
+  #[test]
+  fn size_counts_graphemes_after_edits() {
+    let mut row = Row::from("e\u{301}x");
+    assert_eq!(row.size(), 2);
+    // a combining mark joins the grapheme before it
+    row.insert(2, '\u{301}');
+    assert_eq!(row.size(), 2);
+    row.delete(0);
+    assert_eq!(row.string(), "x\u{301}");
+    assert_eq!(row.size(), 1);
+  }
+
+  #[test]
+  fn insert_str_at_two_of_abcdef() {
+    let mut row = Row::from("abcdef");
+    row.insert_str(2, "XYZ");
+    assert_eq!(row.string(), "abXYZcdef");
+    assert_eq!(row.size(), 9);
+    assert_eq!(row.grapheme(5), Some("c"));
+  }
+
+  #[test]
+  fn delete_slice_to_the_row_end() {
+    let mut row = Row::from("split here");
+    // what Enter in the middle of a row moves to the next one
+    assert_eq!(row.delete_slice(5, row.size()).as_deref(), Some(" here"));
+    assert_eq!(row.string(), "split");
+    assert_eq!(row.size(), 5);
+  }
+
+  #[test]
+  fn delete_slice_takes_an_end_index_not_a_length() {
+    let mut row = Row::from("abcdef");
+    assert_eq!(row.delete_slice(2, 4).as_deref(), Some("cd"));
+    assert_eq!(row.string(), "abef");
+    assert_eq!(row.size(), 4);
+  }
+
+  #[test]
+  fn delete_slice_ignores_empty_and_out_of_range_slices() {
+    let mut row = Row::from("abc");
+    assert_eq!(row.delete_slice(1, 1), None);
+    assert_eq!(row.delete_slice(2, 1), None);
+    assert_eq!(row.delete_slice(1, 4), None);
+    assert_eq!(row.string(), "abc");
+  }
+
+  fn find(row: &str, query: &str, at: usize, direction: SearchDir) -> Option<usize> {
+    Row::from(row).find(query, at, direction, SearchOptions::default())
+  }
+
+  #[test]
+  fn find_returns_grapheme_indices_in_cjk_text() {
+    // every character is three bytes
+    assert_eq!(find("日本語の文章と日本", "日本", 0, SearchDir::Forward), Some(0));
+    assert_eq!(find("日本語の文章と日本", "日本", 1, SearchDir::Forward), Some(7));
+    assert_eq!(find("日本語の文章と日本", "文章", 9, SearchDir::Backward), Some(4));
+  }
+
+  #[test]
+  fn find_counts_a_combining_mark_with_its_letter() {
+    let row = "cafe\u{301} cafe";
+    assert_eq!(find(row, "cafe", 0, SearchDir::Forward), Some(5));
+    assert_eq!(find(row, "cafe\u{301}", 1, SearchDir::Backward), Some(0));
+    assert_eq!(find(row, "\u{301}", 0, SearchDir::Forward), None);
+  }
+
+  #[test]
+  fn find_matches_emoji_queries() {
+    let row = "ok 👍🏽 then 👍 and 👨‍👩‍👧 done";
+    assert_eq!(find(row, "👍", 0, SearchDir::Forward), Some(10));
+    assert_eq!(find(row, "👍🏽", 0, SearchDir::Forward), Some(3));
+    assert_eq!(find(row, "👨‍👩‍👧 d", 0, SearchDir::Forward), Some(16));
+    assert_eq!(find(row, "👍", 10, SearchDir::Backward), None);
+  }
+
+  fn transpose(text: &str, at: usize) -> (String, Option<usize>) {
+    let mut row = Row::from(text);
+    let cursor = row.transpose(at);
+    (row.string().to_string(), cursor)
+  }
+
+  #[test]
+  fn transpose_at_the_row_start_does_nothing() {
+    assert_eq!(transpose("abc", 0), ("abc".to_string(), None));
+    assert_eq!(transpose("", 0), (String::new(), None));
+  }
+
+  #[test]
+  fn transpose_at_the_row_end_swaps_the_last_two() {
+    assert_eq!(transpose("abc", 3), ("acb".to_string(), Some(3)));
+  }
+
+  #[test]
+  fn transpose_a_single_grapheme_does_nothing() {
+    assert_eq!(transpose("e\u{301}", 1), ("e\u{301}".to_string(), None));
+  }
+
+  #[test]
+  fn transpose_inside_a_word_swaps_around_the_cursor() {
+    assert_eq!(transpose("hlelo", 2), ("hello".to_string(), Some(3)));
+    // graphemes move whole
+    assert_eq!(transpose("日e\u{301}本", 2), ("日本e\u{301}".to_string(), Some(3)));
+  }
+
+  #[test]
+  fn transpose_words_around_and_inside_words() {
+    let mut row = Row::from("one two three");
+    assert_eq!(row.transpose_words(4), Some(7));
+    assert_eq!(row.string(), "two one three");
+    // inside a word it is swapped with the next one
+    assert_eq!(row.transpose_words(5), Some(13));
+    assert_eq!(row.string(), "two three one");
+    // at the end the last two are swapped
+    assert_eq!(row.transpose_words(13), Some(13));
+    assert_eq!(row.string(), "two one three");
+    assert_eq!(Row::from("alone").transpose_words(2), None);
+  }
+
+  #[test]
+  fn only_long_rows_are_indexed() {
+    assert!(!Row::from("x".repeat(INDEX_THRESHOLD).as_str()).is_indexed());
+    assert!(Row::from("x".repeat(INDEX_THRESHOLD + 1).as_str()).is_indexed());
+    // typing past the threshold indexes the row
+    let mut row = Row::from("x".repeat(INDEX_THRESHOLD).as_str());
+    row.insert(0, 'y');
+    assert!(row.is_indexed());
+    assert_eq!(row.size(), INDEX_THRESHOLD + 1);
+  }
+
+  #[test]
+  fn long_rows_edit_and_convert_columns_like_short_ones() {
+    let piece = "\t日本e\u{301}x ";
+    let mut long = Row::from(piece.repeat(100).as_str());
+    let mut short = Row::from(piece.repeat(4).as_str());
+    assert!(long.is_indexed() && !short.is_indexed());
+    for row in [&mut long, &mut short] {
+      row.insert(3, '\u{301}');
+      row.insert_str(6, "ab");
+      row.delete(1);
+      assert_eq!(row.delete_slice(0, 2).as_deref(), Some("\t本\u{301}"));
+    }
+    assert!(long.string().starts_with(short.string()));
+    assert_eq!(long.size() - short.size(), 96 * 6);
+    for cx in 0..short.size() {
+      assert_eq!(long.cx_to_rx(cx, 4), short.cx_to_rx(cx, 4));
+      assert_eq!(long.grapheme(cx), short.grapheme(cx));
+    }
+    for rx in 0..short.width(4) {
+      assert_eq!(long.rx_to_cx(rx, 4), short.rx_to_cx(rx, 4));
+    }
+  }
+
+  #[test]
+  #[ignore = "benchmark, run with `cargo test --release -- --ignored --nocapture`"]
+  fn bench_typing_at_the_end_of_a_long_row() {
+    let mut row = Row::default();
+    let started = std::time::Instant::now();
+    for _ in 0..100_000 {
+      row.insert(row.size(), 'x');
+    }
+    println!("100k characters typed at the end of one row in {:.2?}", started.elapsed());
+    assert_eq!(row.size(), 100_000);
+  }
+}
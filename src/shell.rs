@@ -0,0 +1,68 @@
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// how often a running command is checked on, `tick` is called in between
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// runs `command` with `sh`, feeding it `input`, and returns what it
+// printed; if it fails the first line it wrote to stderr is returned;
+// `tick` is called every so often while it runs
+pub fn filter(command: &str, input: &str, mut tick: impl FnMut()) -> Result<String, String> {
+  let mut child = Command::new("sh")
+    .arg("-c")
+    .arg(command)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .map_err(|err| format!("Could not run {command}: {err}"))?;
+
+  // written from another thread, a command that prints while it reads
+  // would otherwise fill its output pipe and wait for us forever; one
+  // that doesn't read its input at all makes the write fail, which is fine
+  let writer = child.stdin.take().map(|mut stdin| {
+    let input = input.to_string();
+    thread::spawn(move || stdin.write_all(input.as_bytes()))
+  });
+  let stdout = read_all(child.stdout.take());
+  let stderr = read_all(child.stderr.take());
+
+  let status = loop {
+    match child.try_wait() {
+      Ok(Some(status)) => break status,
+      Ok(None) => {
+        tick();
+        thread::sleep(POLL_INTERVAL);
+      },
+      Err(err) => return Err(format!("{command}: {err}")),
+    }
+  };
+  if let Some(writer) = writer {
+    let _ = writer.join();
+  }
+  let collect = |reader: Option<JoinHandle<io::Result<Vec<u8>>>>| {
+    reader.and_then(|reader| reader.join().ok()).transpose().map_err(|err| format!("{command}: {err}"))
+  };
+  let stdout = collect(stdout)?.unwrap_or_default();
+  let stderr = collect(stderr)?.unwrap_or_default();
+
+  if !status.success() {
+    let stderr = String::from_utf8_lossy(&stderr);
+    return Err(match stderr.lines().find(|line| !line.trim().is_empty()) {
+      Some(line) => line.to_string(),
+      None => format!("{command} failed ({status})"),
+    });
+  }
+  String::from_utf8(stdout).map_err(|_| format!("{command} printed invalid UTF-8"))
+}
+
+// reads a pipe to its end from another thread, so waiting for the command
+// can be done here
+fn read_all(pipe: Option<impl Read + Send + 'static>) -> Option<JoinHandle<io::Result<Vec<u8>>>> {
+  pipe.map(|mut pipe| thread::spawn(move || {
+    let mut bytes = Vec::new();
+    pipe.read_to_end(&mut bytes).map(|_| bytes)
+  }))
+}
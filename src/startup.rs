@@ -0,0 +1,61 @@
+use std::env;
+use std::io::{self, IsTerminal, Read};
+
+use crate::args::{parse_args, Args, FileArg};
+use crate::config;
+use crate::keymap::{self, Keymap};
+use crate::syntax;
+use crate::Config;
+use crate::Document;
+use crate::Error;
+use crate::SyntaxDef;
+
+// everything an editor is started with besides its terminal; `load` reads
+// it from the command line, stdin and the config directory, before the
+// terminal is set up
+#[derive(Default)]
+pub struct Startup {
+  pub args: Args,
+  // the text piped in, opened for a `-` argument
+  pub stdin: Option<Document>,
+  pub config: Config,
+  pub keymap: Keymap,
+  pub syntaxes: Vec<SyntaxDef>,
+  // what went wrong loading the above, the first one is shown at startup
+  pub errors: Vec<String>,
+}
+
+impl Startup {
+  pub fn load() -> Self {
+    let mut args = parse_args(env::args().skip(1));
+    // `cat log | slime` edits the piped text like `slime -`
+    if args.files.is_empty() && !io::stdin().is_terminal() {
+      args.files.push(FileArg { path: None, line: None, column: None });
+    }
+    let mut errors = args.errors.clone();
+    let stdin = if args.files.iter().any(|file| file.path.is_none()) {
+      read_stdin().map_err(|err| errors.push(format!("Could not open file stdin: {err}"))).ok()
+    } else {
+      None
+    };
+    let (keymap, keymap_errors) = keymap::load();
+    let (config, config_errors) = config::load();
+    let (syntaxes, syntax_errors) = syntax::load();
+    errors.extend(keymap_errors);
+    errors.extend(config_errors.iter().map(ToString::to_string));
+    errors.extend(syntax_errors.into_iter().map(|err| format!("Could not load syntax {err}")));
+
+    Self { args, stdin, config, keymap, syntaxes, errors }
+  }
+}
+
+// reads all of stdin into a document without a path, so saving asks for
+// one; this has to happen before the terminal is set up. Keys are still
+// read afterwards: crossterm only uses stdin for raw mode and input while
+// it is a terminal, otherwise it opens the controlling terminal `/dev/tty`
+// instead, so a piped stdin that hit EOF is never read again
+fn read_stdin() -> Result<Document, Error> {
+  let mut bytes = Vec::new();
+  io::stdin().read_to_end(&mut bytes)?;
+  Ok(Document::from_bytes(&bytes))
+}
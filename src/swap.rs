@@ -0,0 +1,63 @@
+use std::fs;
+use std::io::{BufWriter, Error, IntoInnerError, Write};
+use std::path::PathBuf;
+use std::process;
+
+use crate::Position;
+
+// the unsaved text of a file written out while it is edited, so it can be
+// recovered after a crash; kept next to the file as `.slime-swap-<name>`
+pub struct Swap {
+  pub cursor: Position<usize>,
+  pub text: String,
+}
+
+const HEADER: &str = "slime swap";
+
+pub fn path_for(path: &str) -> PathBuf {
+  let path = PathBuf::from(path);
+  let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+  path.with_file_name(format!(".slime-swap-{name}"))
+}
+
+// a first line with the cursor followed by the text of the rows, which
+// `text` writes out; it goes to a temporary file renamed over the swap, so
+// a crash while writing leaves the previous swap in place
+pub fn write(path: &str, cursor: &Position<usize>, text: impl FnOnce(&mut dyn Write) -> Result<(), Error>) -> Result<(), Error> {
+  let swap_path = path_for(path);
+  let name = swap_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+  let temporary = swap_path.with_file_name(format!("{name}.{}", process::id()));
+
+  let result = (|| {
+    let mut file = BufWriter::new(fs::File::create(&temporary)?);
+    writeln!(file, "{HEADER} {} {}", cursor.y, cursor.x)?;
+    text(&mut file)?;
+    file.into_inner().map_err(IntoInnerError::into_error)?.sync_all()?;
+    fs::rename(&temporary, &swap_path)
+  })();
+  if result.is_err() {
+    let _ = fs::remove_file(&temporary);
+  }
+  result
+}
+
+// the swap of `path` if there is one written after the file itself
+pub fn read(path: &str) -> Option<Swap> {
+  let swap_path = path_for(path);
+  let swapped = fs::metadata(&swap_path).and_then(|metadata| metadata.modified()).ok()?;
+  let saved = fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+  if saved.is_some_and(|saved| saved > swapped) {
+    return None;
+  }
+  let contents = fs::read_to_string(swap_path).ok()?;
+  let (header, text) = contents.split_once('\n')?;
+  let mut numbers = header.strip_prefix(HEADER)?.split_whitespace().map(str::parse);
+  let (Some(Ok(y)), Some(Ok(x))) = (numbers.next(), numbers.next()) else {
+    return None;
+  };
+  Some(Swap { cursor: Position { x, y }, text: text.to_string() })
+}
+
+pub fn remove(path: &str) {
+  let _ = fs::remove_file(path_for(path));
+}
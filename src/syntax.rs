@@ -0,0 +1,67 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::config;
+
+// compiled in so highlighting works without any config files
+const BUILTIN: [&str; 4] = [
+  include_str!("../syntax/rust.toml"),
+  include_str!("../syntax/c.toml"),
+  include_str!("../syntax/python.toml"),
+  include_str!("../syntax/shell.toml"),
+];
+
+#[derive(Deserialize, Clone)]
+pub struct SyntaxDef {
+  pub name: String,
+  pub extensions: Vec<String>,
+  #[serde(default)]
+  pub keywords: Vec<String>,
+  #[serde(default)]
+  pub types: Vec<String>,
+  #[serde(default)]
+  pub string_delimiters: Vec<String>,
+  pub line_comment: Option<String>,
+  pub block_comment: Option<(String, String)>,
+  #[serde(default = "highlight_numbers_default")]
+  pub highlight_numbers: bool,
+}
+
+fn highlight_numbers_default() -> bool {
+  true
+}
+
+// user definitions from `<config dir>/syntax/*.toml` come first so they
+// take precedence over the built-in ones; files that fail to parse are
+// reported back instead of aborting the startup
+pub fn load() -> (Vec<SyntaxDef>, Vec<String>) {
+  let mut defs = Vec::new();
+  let mut errors = Vec::new();
+
+  if let Some(entries) = config::config_dir().and_then(|dir| fs::read_dir(dir.join("syntax")).ok()) {
+    let mut paths: Vec<_> = entries
+      .filter_map(Result::ok)
+      .map(|entry| entry.path())
+      .filter(|path| path.extension().is_some_and(|extension| extension == "toml"))
+      .collect();
+    paths.sort();
+    for path in paths {
+      match fs::read_to_string(&path).map_err(|err| err.to_string())
+        .and_then(|contents| toml::from_str(&contents).map_err(|err| err.to_string())) {
+        Ok(def) => defs.push(def),
+        Err(err) => errors.push(format!("{}: {err}", path.display())),
+      }
+    }
+  }
+
+  defs.extend(BUILTIN.iter().filter_map(|contents| toml::from_str(contents).ok()));
+
+  (defs, errors)
+}
+
+pub fn for_path<'a>(defs: &'a [SyntaxDef], path: &str) -> Option<&'a SyntaxDef> {
+  let extension = Path::new(path).extension()?.to_str()?;
+  defs.iter().find(|def| def.extensions.iter().any(|known| known == extension))
+}
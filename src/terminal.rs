@@ -1,132 +1,347 @@
-use std::{io::{Error, self}, time::Duration};
+use std::{io::{self, BufWriter, Write}, time::Duration};
 
 use crossterm::{
   terminal::{self, Clear},
   cursor::{MoveTo, Hide, Show},
   ExecutableCommand,
-  style::{Print, SetColors, Colors, Color, SetForegroundColor, SetBackgroundColor}, 
-  event::{Event, poll, read}};
+  QueueableCommand,
+  style::{Print, SetColors, Colors, Color, SetForegroundColor, SetBackgroundColor},
+  event::{Event, poll, read, EnableMouseCapture, DisableMouseCapture}};
+
+use crate::Error;
+
+const OUTPUT_BUFFER_SIZE: usize = 1 << 16;
 
 pub struct Size {
   pub width: u16,
   pub height: u16,
 }
 
+// everything the editor needs from a terminal, so it can be driven by
+// something other than the real one
+pub trait TerminalBackend {
+  fn size(&self) -> &Size;
+  fn resize(&mut self, width: u16, height: u16);
+  fn move_cursor(&mut self, x: u16, y: u16) -> Result<(), Error>;
+  fn hide_cursor(&mut self) -> Result<(), Error>;
+  fn show_cursor(&mut self) -> Result<(), Error>;
+  fn print_string(&mut self, str: &str) -> Result<(), Error>;
+  fn clear_screen(&mut self) -> Result<(), Error>;
+  fn clear_current_line(&mut self) -> Result<(), Error>;
+  fn set_colors(&mut self, colors: Colors) -> Result<(), Error>;
+  fn reset_colors(&mut self) -> Result<(), Error>;
+  fn set_fg_color(&mut self, color: Color) -> Result<(), Error>;
+  fn reset_fg_color(&mut self) -> Result<(), Error>;
+  // whether clicks and the wheel are reported to the editor instead of
+  // being left to the terminal for selecting text
+  fn set_mouse_capture(&mut self, enabled: bool) -> Result<(), Error>;
+  // writes everything queued since the last flush, once per frame
+  fn flush(&mut self) -> Result<(), Error>;
+  // the next event, or `None` if nothing happened for a while
+  fn read_event(&mut self) -> Result<Option<Event>, Error>;
+}
+
 pub struct Terminal {
-  stdout: io::Stdout,
+  // commands are queued here and written out at once by `flush`
+  stdout: BufWriter<io::Stdout>,
   // terminal size
   size: Size,
 }
 
 impl Terminal {
+  // raw mode also turns off signal keys and flow control, so Ctrl-C, Ctrl-Z
+  // and Ctrl-Q arrive as key events instead of signals; without it the
+  // editor can't work and fails to start
+  #[allow(clippy::should_implement_trait)]
   pub fn default() -> Result<Terminal, Error> {
-    let stdout = io::stdout();
-    let _raw_mode = terminal::enable_raw_mode();    
+    let stdout = BufWriter::with_capacity(OUTPUT_BUFFER_SIZE, io::stdout());
     let (cols, rows) = terminal::size()?;
+    terminal::enable_raw_mode()?;
+    io::stdout().execute(EnableMouseCapture)?;
 
     Ok(Terminal{
       stdout,
       size: Size { width: cols, height: rows }
     })
-  }  
+  }
+
+  pub fn print_char(&mut self, ch: char) -> Result<(), Error> {
+    self.stdout.queue(Print(ch))?;
 
-  pub fn size(&self) -> &Size {
+    Ok(())
+  }
+
+  pub fn set_bg_color(&mut self, color: Color) -> Result<(), Error> {
+    self.stdout.queue(SetBackgroundColor(color))?;
+
+    Ok(())
+  }
+  pub fn reset_bg_color(&mut self) -> Result<(), Error> {
+    self.stdout.queue(SetBackgroundColor(Color::Reset))?;
+
+    Ok(())
+  }
+}
+
+impl TerminalBackend for Terminal {
+  fn size(&self) -> &Size {
     &self.size
   }
 
-  pub fn resize(&mut self, width: u16, height: u16) {
+  fn resize(&mut self, width: u16, height: u16) {
     self.size.width = width;
     self.size.height = height;
   }
 
-  pub fn move_cursor(&mut self, x: u16, y: u16) -> Result<(), Error> {
-    self.stdout.execute(MoveTo(x, y))?;
+  fn move_cursor(&mut self, x: u16, y: u16) -> Result<(), Error> {
+    self.stdout.queue(MoveTo(x, y))?;
 
     Ok(())
   }
 
-  pub fn hide_cursor(&mut self) -> Result<(), Error> {
-    self.stdout.execute(Hide)?;
+  fn hide_cursor(&mut self) -> Result<(), Error> {
+    self.stdout.queue(Hide)?;
 
     Ok(())
   }
 
-  pub fn show_cursor(&mut self) -> Result<(), Error> {
-    self.stdout.execute(Show)?;
+  fn show_cursor(&mut self) -> Result<(), Error> {
+    self.stdout.queue(Show)?;
 
     Ok(())
   }
 
-  pub fn print_char(&mut self, ch: char) -> Result<(), Error> {
-    self.stdout.execute(Print(ch))?;
-
-    Ok(())    
-  }
-
-  pub fn print_string(&mut self, str: &str) -> Result<(), Error> {
-    self.stdout.execute(Print(str))?;
+  fn print_string(&mut self, str: &str) -> Result<(), Error> {
+    self.stdout.queue(Print(str))?;
 
     Ok(())
   }
 
-  pub fn clear_screen(&mut self) -> Result<(), Error> {
+  fn clear_screen(&mut self) -> Result<(), Error> {
     self.stdout
-      .execute(Clear(terminal::ClearType::All))?
-      .execute(MoveTo(0, 0))?;
+      .queue(Clear(terminal::ClearType::All))?
+      .queue(MoveTo(0, 0))?;
 
     Ok(())
-  }  
+  }
 
-  pub fn read_event(&self) -> Result<Option<Event>, Error> {
+  fn flush(&mut self) -> Result<(), Error> {
+    Ok(self.stdout.flush()?)
+  }
+
+  fn read_event(&mut self) -> Result<Option<Event>, Error> {
     if poll(Duration::from_millis(100))? {
       match read() {
-        Ok(e) => {          
-          
+        Ok(e) => {
+
           return Ok(Some(e));
         },
         Err(err) => {
-          return Err(err);
+          return Err(err.into());
         }
-      }      
+      }
     }
 
     Ok(None)
   }
 
-  pub fn clear_current_line(&mut self) -> Result<(), Error> {
-    self.stdout.execute(Clear(terminal::ClearType::CurrentLine))?;
+  fn clear_current_line(&mut self) -> Result<(), Error> {
+    self.stdout.queue(Clear(terminal::ClearType::CurrentLine))?;
 
     Ok(())
   }
 
-  pub fn set_colors(&mut self, colors: Colors) -> Result<(), Error> {
-    self.stdout.execute(SetColors(colors))?;
+  fn set_colors(&mut self, colors: Colors) -> Result<(), Error> {
+    self.stdout.queue(SetColors(colors))?;
 
     Ok(())
   }
-  pub fn reset_colors(&mut self) -> Result<(), Error> {
-    self.stdout.execute(SetColors(Colors::new(Color::Reset, Color::Reset)))?;
+  fn reset_colors(&mut self) -> Result<(), Error> {
+    self.stdout.queue(SetColors(Colors::new(Color::Reset, Color::Reset)))?;
 
     Ok(())
   }
-  pub fn set_fg_color(&mut self, color: Color) -> Result<(), Error> {
-    self.stdout.execute(SetForegroundColor(color))?;
+  fn set_fg_color(&mut self, color: Color) -> Result<(), Error> {
+    self.stdout.queue(SetForegroundColor(color))?;
 
     Ok(())
   }
-  pub fn reset_fg_color(&mut self) -> Result<(), Error> {
-    self.stdout.execute(SetForegroundColor(Color::Reset))?;
+  fn reset_fg_color(&mut self) -> Result<(), Error> {
+    self.stdout.queue(SetForegroundColor(Color::Reset))?;
 
     Ok(())
   }
-  pub fn set_bg_color(&mut self, color: Color) -> Result<(), Error> {
-    self.stdout.execute(SetBackgroundColor(color))?;
+
+  fn set_mouse_capture(&mut self, enabled: bool) -> Result<(), Error> {
+    if enabled {
+      self.stdout.queue(EnableMouseCapture)?;
+    } else {
+      self.stdout.queue(DisableMouseCapture)?;
+    }
 
     Ok(())
   }
-  pub fn reset_bg_color(&mut self) -> Result<(), Error> {
-    self.stdout.execute(SetBackgroundColor(Color::Reset))?;
+}
+
+impl Drop for Terminal {
+  fn drop(&mut self) {
+    let _ = self.stdout.flush();
+    let _ = restore();
+  }
+}
+
+// hands the terminal back to the shell in the state it was found in, also
+// used by the panic hook where no `Terminal` is at hand
+pub fn restore() -> Result<(), Error> {
+  terminal::disable_raw_mode()?;
+  io::stdout().execute(DisableMouseCapture)?.execute(Show)?;
 
+  Ok(())
+}
+
+// a screen kept in memory for tests: printed text lands in a grid of cells,
+// one grapheme each and an empty one after a wide one, and the events
+// queued in `events` are read back in order
+#[cfg(test)]
+pub struct MockTerminal {
+  size: Size,
+  cells: Vec<Vec<String>>,
+  pub cursor: (u16, u16),
+  pub events: std::collections::VecDeque<Event>,
+  // times the output was flushed, a real terminal writes it out then
+  pub flushes: usize,
+  // rows cleared to be drawn again
+  pub cleared_lines: usize,
+}
+
+#[cfg(test)]
+impl MockTerminal {
+  pub fn new(width: u16, height: u16) -> Self {
+    Self {
+      size: Size { width, height },
+      cells: vec![vec![" ".to_string(); width as usize]; height as usize],
+      cursor: (0, 0),
+      events: std::collections::VecDeque::new(),
+      flushes: 0,
+      cleared_lines: 0,
+    }
+  }
+
+  // the text shown on a screen row, without the blanks after it
+  pub fn line(&self, y: u16) -> String {
+    self.cells[y as usize].concat().trim_end().to_string()
+  }
+
+  fn put(&mut self, grapheme: &str) {
+    use unicode_width::UnicodeWidthStr;
+
+    let (x, y) = (self.cursor.0 as usize, self.cursor.1 as usize);
+    let width = grapheme.width();
+    let Some(row) = self.cells.get_mut(y) else {
+      return;
+    };
+    if x + width > row.len() {
+      return;
+    }
+    row[x] = grapheme.to_string();
+    for cell in &mut row[x + 1..x + width] {
+      cell.clear();
+    }
+    self.cursor.0 += u16::try_from(width).unwrap_or(0);
+  }
+}
+
+#[cfg(test)]
+impl TerminalBackend for MockTerminal {
+  fn size(&self) -> &Size {
+    &self.size
+  }
+
+  fn resize(&mut self, width: u16, height: u16) {
+    self.size = Size { width, height };
+    self.cells = vec![vec![" ".to_string(); width as usize]; height as usize];
+  }
+
+  fn move_cursor(&mut self, x: u16, y: u16) -> Result<(), Error> {
+    self.cursor = (x, y);
+    Ok(())
+  }
+
+  fn hide_cursor(&mut self) -> Result<(), Error> {
+    Ok(())
+  }
+
+  fn show_cursor(&mut self) -> Result<(), Error> {
+    Ok(())
+  }
+
+  // colors and attributes come as escape sequences inside the text, they
+  // take no cells and are left out
+  fn print_string(&mut self, str: &str) -> Result<(), Error> {
+    use unicode_segmentation::UnicodeSegmentation;
+
+    let mut rest = str;
+    while !rest.is_empty() {
+      if let Some(sequence) = rest.strip_prefix("\x1b[") {
+        let end = sequence.find(|c| ('@'..='~').contains(&c)).map_or(sequence.len(), |end| end + 1);
+        rest = &sequence[end..];
+        continue;
+      }
+      let text = &rest[..rest.find('\x1b').unwrap_or(rest.len())];
+      for grapheme in text.graphemes(true) {
+        self.put(grapheme);
+      }
+      rest = &rest[text.len()..];
+    }
+    Ok(())
+  }
+
+  fn clear_screen(&mut self) -> Result<(), Error> {
+    let (width, height) = (self.size.width, self.size.height);
+    self.resize(width, height);
+    self.cursor = (0, 0);
+    Ok(())
+  }
+
+  fn clear_current_line(&mut self) -> Result<(), Error> {
+    self.cleared_lines += 1;
+    if let Some(row) = self.cells.get_mut(self.cursor.1 as usize) {
+      row.fill(" ".to_string());
+    }
+    Ok(())
+  }
+
+  fn set_colors(&mut self, _colors: Colors) -> Result<(), Error> {
+    Ok(())
+  }
+
+  fn reset_colors(&mut self) -> Result<(), Error> {
+    Ok(())
+  }
+
+  fn set_fg_color(&mut self, _color: Color) -> Result<(), Error> {
     Ok(())
   }
+
+  fn reset_fg_color(&mut self) -> Result<(), Error> {
+    Ok(())
+  }
+
+  fn set_mouse_capture(&mut self, _enabled: bool) -> Result<(), Error> {
+    Ok(())
+  }
+
+  fn flush(&mut self) -> Result<(), Error> {
+    self.flushes += 1;
+    Ok(())
+  }
+
+  // running out of events fails instead of waiting, so a test that
+  // leaves a prompt open ends
+  fn read_event(&mut self) -> Result<Option<Event>, Error> {
+    match self.events.pop_front() {
+      Some(event) => Ok(Some(event)),
+      None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "no more events").into()),
+    }
+  }
 }